@@ -3,9 +3,11 @@ extern crate criterion;
 extern crate smol;
 
 mod metrics;
+mod tagger;
 mod tokenize;
 
 criterion_main! {
     metrics::metrics,
+    tagger::tagger,
     tokenize::tokenize
 }
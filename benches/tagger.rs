@@ -0,0 +1,130 @@
+use criterion::Criterion;
+use smol::tag::*;
+use std::time::Instant;
+
+static CORPUS: &str = "The/DT quick/JJ brown/JJ fox/NN jumps/VBZ over/IN the/DT lazy/JJ dog/NN ./.
+In/IN addition/NN to/TO conventional/JJ static/JJ typing/NN ,/, Rust/NNP also/RB supported/VBD typestates/NNS ./.
+The/DT typestate/NN system/NN modeled/VBD assertions/NNS before/IN and/CC after/IN program/NN statements/NNS ./.
+Discrepancies/NNS could/MD be/VB discovered/VBN at/IN compile/NN time/NN ./.
+Typestates/NNS were/VBD removed/VBN because/IN in/IN practice/NN they/PRP found/VBD little/JJ use/NN ./.
+Version/NN 0.2/CD introduced/VBD classes/NNS for/IN the/DT first/JJ time/NN ./.
+In/IN January/NNP 2014/CD ,/, the/DT editor-in-chief/NN commented/VBD on/IN Rust/NNP 's/POS chances/NNS ./.
+Traits/NNS were/VBD added/VBN as/IN a/DT means/NN to/TO provide/VB inheritance/NN ./.";
+
+fn corpus() -> Vec<Vec<(String, String)>> {
+    let sentences = SlashTagged.read(CORPUS.as_bytes()).unwrap();
+    (0..25)
+        .flat_map(|_| sentences.iter().map(|s| tagged_pairs(s)))
+        .collect()
+}
+
+/// Prints the throughput of `f`, which handles `tokens` tokens per call.
+fn report<F: FnMut()>(name: &str, tokens: usize, mut f: F) {
+    let runs = 10;
+    let start = Instant::now();
+    for _ in 0..runs {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+    println!("{}: {:.0} tokens/s", name, (tokens * runs) as f64 / secs);
+}
+
+fn perceptron_tag(c: &mut Criterion) {
+    let sentences = corpus();
+    let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+    let tokens = corpus.iter().map(|s| s.len()).sum::<usize>();
+
+    let mut tagger = PerceptronTagger::new();
+    tagger.train_with(corpus.clone(), &mut TrainConfig::new().seed(0));
+
+    let inputs = corpus.iter().map(|s| sentence_tokens(s)).collect::<Vec<_>>();
+    let mut tag_all = move || {
+        for input in &inputs {
+            tagger.tag(input.clone()).unwrap();
+        }
+    };
+
+    report("perceptron tag", tokens, &mut tag_all);
+    c.bench_function(&format!("perceptron tag ({} tokens)", tokens), move |b| {
+        b.iter(&mut tag_all)
+    });
+}
+
+fn perceptron_train(c: &mut Criterion) {
+    let sentences = corpus();
+    let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+    let tokens = corpus.iter().map(|s| s.len()).sum::<usize>();
+
+    let train = || {
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(
+            corpus.clone(),
+            &mut TrainConfig::new().iterations(1).seed(0),
+        );
+        tagger
+    };
+
+    report("perceptron train", tokens, || {
+        train();
+    });
+    c.bench_function(
+        &format!("perceptron train, 1 iteration ({} tokens)", tokens),
+        |b| b.iter(&train),
+    );
+}
+
+fn perceptron_beam(c: &mut Criterion) {
+    let sentences = corpus();
+    let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+    let tokens = corpus.iter().map(|s| s.len()).sum::<usize>();
+
+    let width = 4;
+    let mut tagger = PerceptronTagger::new().beam_width(width);
+    tagger.train_with(
+        corpus.clone(),
+        &mut TrainConfig::new().seed(0).beam_width(width),
+    );
+
+    let inputs = corpus.iter().map(|s| sentence_tokens(s)).collect::<Vec<_>>();
+    let mut tag_all = move || {
+        for input in &inputs {
+            tagger.tag(input.clone()).unwrap();
+        }
+    };
+
+    report("perceptron beam tag", tokens, &mut tag_all);
+    c.bench_function(
+        &format!("perceptron tag, beam width {} ({} tokens)", width, tokens),
+        move |b| b.iter(&mut tag_all),
+    );
+}
+
+fn hmm_tag(c: &mut Criterion) {
+    let sentences = corpus();
+    let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+    let tokens = corpus.iter().map(|s| s.len()).sum::<usize>();
+
+    let mut hmm = HmmTagger::new();
+    hmm.train(&corpus);
+
+    let inputs = corpus.iter().map(|s| sentence_tokens(s)).collect::<Vec<_>>();
+    let mut tag_all = move || {
+        for input in &inputs {
+            hmm.tag(input.clone()).unwrap();
+        }
+    };
+
+    report("hmm tag", tokens, &mut tag_all);
+    c.bench_function(&format!("hmm tag ({} tokens)", tokens), move |b| {
+        b.iter(&mut tag_all)
+    });
+}
+
+criterion_group!(
+    tagger,
+    perceptron_tag,
+    perceptron_train,
+    perceptron_beam,
+    hmm_tag
+);
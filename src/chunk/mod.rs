@@ -0,0 +1,44 @@
+//! Shallow parsing: grouping tagged tokens into non-overlapping chunks, such as noun phrases.
+//!
+//! Chunkers take the output of a part-of-speech tagger and return labelled `Span`s of tokens.
+//! A `RegexpChunker` follows a hand-written grammar over tags, while a `PerceptronChunker` is
+//! trained from a chunked corpus such as CoNLL-2000.
+
+pub mod perceptron;
+pub mod regexp;
+
+// Re-exports
+pub use self::perceptron::*;
+pub use self::regexp::*;
+
+use error::*;
+use ner::{spans, Span, SpanEvaluation};
+use tag::AnnotatedSentence;
+use tokenize::*;
+
+/// Anything which can chunk tagged tokens.
+pub trait Chunker {
+    /// Finds the chunks of a tagged sentence, in order.
+    fn chunk(&self, tagged: &[(Token, String)]) -> Result<Vec<Span>, SmolError>;
+
+    /// Chunks the tokens and tags of every gold sentence, and compares the result against the
+    /// gold chunks.
+    fn evaluate(&self, gold: &[AnnotatedSentence]) -> Result<SpanEvaluation, SmolError> {
+        let mut res = SpanEvaluation::new();
+
+        for sentence in gold {
+            let tagged = sentence
+                .iter()
+                .map(|t| (t.token.clone(), t.tag.clone()))
+                .collect::<Vec<_>>();
+            let chunks = sentence
+                .iter()
+                .map(|t| t.chunk.as_deref().unwrap_or("O"))
+                .collect::<Vec<_>>();
+
+            res.add(&spans(&chunks), &self.chunk(&tagged)?);
+        }
+
+        Ok(res)
+    }
+}
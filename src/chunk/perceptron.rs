@@ -0,0 +1,318 @@
+//! A chunker trained with an averaged perceptron.
+//!
+//! Every token is tagged with its place in a chunk (`B-NP`, `I-NP`, `O` and so on) from left to
+//! right, looking at the words and part-of-speech tags around it and the chunk tags already
+//! chosen for the words before it.
+
+use super::*;
+use ner::Scheme;
+use tag::format::{decode, encode, now, read_all};
+use tag::split::seeded_rng;
+use tag::*;
+
+use bincode::deserialize;
+use failure::ResultExt;
+use rand::{thread_rng, Rng};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+/// A chunker based on an averaged perceptron, trained from chunked sentences like those of
+/// CoNLL-2000.
+///
+/// # Examples
+/// ```rust
+/// # use smol::chunk::*;
+/// # use smol::tag::*;
+/// let sentences = Conll2000
+///     .read("the DT B-NP\ndog NN I-NP\nbarked VBD B-VP\n".as_bytes())
+///     .unwrap();
+///
+/// let mut chunker = PerceptronChunker::new();
+/// chunker.train(&sentences, &mut EpochConfig::new().seed(0));
+/// assert_eq!(1.0, chunker.evaluate(&sentences).unwrap().f1());
+/// ```
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct PerceptronChunker {
+    model: AveragedPerceptron,
+    templates: TemplateSet,
+    corpus: CorpusInfo,
+}
+
+/// The features used for chunks by default: the words and part-of-speech tags around every word,
+/// along with the chunk tags of the previous two words.
+///
+/// When chunking, the tags which templates like `Tag` and `TagPos` look at are the chunk tags
+/// already chosen, while `Pos` templates look at the part-of-speech tags the chunker is given.
+pub fn chunk_templates() -> TemplateSet {
+    use tag::FeatureTemplate::*;
+
+    TemplateSet::empty().extended(vec![
+        Bias,
+        Word(-2),
+        Word(-1),
+        Word(0),
+        Word(1),
+        Word(2),
+        Pos(-2),
+        Pos(-1),
+        Pos(0),
+        Pos(1),
+        Pos(2),
+        PosBigram(-1),
+        PosBigram(0),
+        PosWord,
+        Tag(1),
+        TagBigram,
+        TagPos,
+    ])
+}
+
+impl Default for PerceptronChunker {
+    fn default() -> PerceptronChunker {
+        PerceptronChunker {
+            model: AveragedPerceptron::new(),
+            templates: chunk_templates(),
+            corpus: CorpusInfo::default(),
+        }
+    }
+}
+
+impl PerceptronChunker {
+    pub fn new() -> PerceptronChunker {
+        PerceptronChunker::default()
+    }
+
+    /// Sets the features to train with. Changing them after training leaves the model unable to
+    /// make sense of the features it is given.
+    pub fn templates(mut self, templates: TemplateSet) -> PerceptronChunker {
+        self.templates = templates;
+        self
+    }
+
+    pub fn get_templates(&self) -> &TemplateSet {
+        &self.templates
+    }
+
+    pub fn get_model(&self) -> &AveragedPerceptron {
+        &self.model
+    }
+
+    pub fn get_corpus(&self) -> &CorpusInfo {
+        &self.corpus
+    }
+
+    /// Trains the chunker on the tags and chunks of annotated sentences. Tokens without a chunk
+    /// count as outside any chunk.
+    pub fn train(&mut self, sentences: &[AnnotatedSentence], config: &mut EpochConfig) {
+        match config.get_seed() {
+            Some(seed) => self.train_with_rng(sentences, config, &mut seeded_rng(seed)),
+            None => self.train_with_rng(sentences, config, &mut thread_rng()),
+        }
+    }
+
+    /// Trains the chunker, shuffling the sentences between iterations with `rng`. The seed of the
+    /// configuration is ignored.
+    pub fn train_with_rng<R: Rng>(
+        &mut self,
+        sentences: &[AnnotatedSentence],
+        config: &mut EpochConfig,
+        rng: &mut R,
+    ) {
+        let start = Instant::now();
+        let mut sentences = sentences
+            .iter()
+            .map(|s| {
+                let chunks = s
+                    .iter()
+                    .map(|t| t.chunk.as_deref().unwrap_or("O"))
+                    .collect::<Vec<_>>();
+                let words = s.iter().map(|t| &*t.token.term).collect::<Vec<_>>();
+                let tags = s.iter().map(|t| &*t.tag).collect::<Vec<_>>();
+                (words, padded(&tags), Scheme::Bio.convert(&chunks))
+            })
+            .collect::<Vec<_>>();
+
+        self.corpus.sentences = sentences.len();
+        self.corpus.tokens = sentences.iter().map(|s| s.0.len()).sum();
+        let classes = sentences
+            .iter()
+            .flat_map(|s| s.2.iter())
+            .collect::<BTreeSet<_>>();
+        for c in classes {
+            self.model.add_class(c);
+        }
+
+        let mut features = Features::new();
+        let mut ids = Vec::new();
+
+        for epoch in 1..config.get_iterations() + 1 {
+            let (mut errors, mut tokens) = (0, 0);
+
+            for (words, tags, chunks) in &sentences {
+                let context = PerceptronTagger::context(words);
+                let mut guesses: Vec<String> = Vec::with_capacity(words.len());
+                for (i, chunk) in chunks.iter().enumerate() {
+                    self.extract(i, words[i], &context, tags, &guesses, &mut features);
+                    self.model.intern_all(&features, &mut ids);
+                    let truth = self.model.add_class(chunk);
+                    let guess = self.model.predict(&ids).unwrap();
+                    self.model.update(truth, guess, &ids);
+
+                    tokens += 1;
+                    errors += (truth != guess) as usize;
+                    guesses.push(self.model.classes()[guess].clone());
+                }
+            }
+
+            rng.shuffle(&mut sentences);
+            config.report(&EpochStats {
+                epoch,
+                errors,
+                tokens,
+                dev_accuracy: None,
+                dev_error: None,
+                elapsed: start.elapsed(),
+            });
+        }
+
+        self.model.average_weights();
+    }
+
+    /// Extracts the features of the word at `i`, given the chunk tags of the words before it.
+    fn extract(
+        &self,
+        i: usize,
+        word: &str,
+        context: &[String],
+        tags: &[String],
+        chunks: &[String],
+        features: &mut Features,
+    ) {
+        let chunk = |n: usize| if i >= n { &*chunks[i - n] } else { "-START-" };
+        let ctx = FeatureContext {
+            i,
+            word,
+            context,
+            p1: chunk(1),
+            p2: chunk(2),
+            pos: tags,
+        };
+        features.clear();
+        self.templates.extract(&ctx, features);
+    }
+
+    /// Describes the chunker, as it would be saved right now.
+    pub fn metadata(&self) -> ModelMetadata {
+        let mut tagset = self.model.classes().to_vec();
+        tagset.sort();
+
+        ModelMetadata {
+            templates: self.templates.clone(),
+            tagset,
+            corpus: self.corpus.clone(),
+            date: now(),
+        }
+    }
+}
+
+impl Chunker for PerceptronChunker {
+    fn chunk(&self, tagged: &[(Token, String)]) -> Result<Vec<Span>, SmolError> {
+        let words = tagged.iter().map(|x| &*x.0.term).collect::<Vec<_>>();
+        let tags = padded(&tagged.iter().map(|x| &*x.1).collect::<Vec<_>>());
+        let context = PerceptronTagger::context(&words);
+
+        let mut features = Features::new();
+        let mut ids = Vec::new();
+        let mut guesses: Vec<String> = Vec::with_capacity(words.len());
+
+        for (i, word) in words.iter().enumerate() {
+            self.extract(i, word, &context, &tags, &guesses, &mut features);
+            self.model.lookup(&features, &mut ids);
+            let guess = self.model.predict(&ids)?;
+            guesses.push(self.model.classes()[guess].clone());
+        }
+
+        Ok(spans(&guesses))
+    }
+}
+
+impl Persist for PerceptronChunker {
+    fn save_to<W: Write>(&self, mut writer: W) -> Result<(), SmolError> {
+        let s = encode(&(self.metadata(), self))?;
+        writer.write_all(&s).context(SmolErrorKind::Write)?;
+        Ok(())
+    }
+
+    fn load_from<R: Read>(reader: R) -> Result<PerceptronChunker, SmolError> {
+        let bytes = read_all(reader)?;
+        let body = decode(&bytes)?.ok_or(SmolErrorKind::Deserialize)?;
+        let (_, chunker): (ModelMetadata, PerceptronChunker) =
+            deserialize(body).context(SmolErrorKind::Deserialize)?;
+        Ok(chunker)
+    }
+}
+
+/// Pads part-of-speech tags with start and end markers, like `PerceptronTagger::context` does
+/// with words.
+fn padded(tags: &[&str]) -> Vec<String> {
+    vec!["-START-", "-START2-"]
+        .into_iter()
+        .chain(tags.iter().cloned())
+        .chain(vec!["-END-", "-END2-"])
+        .map(|t| t.to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CORPUS: &str = "Confidence NN B-NP
+in IN B-PP
+the DT B-NP
+pound NN I-NP
+is VBZ B-VP
+widely RB I-VP
+expected VBN I-VP
+to TO I-VP
+take VB I-VP
+another DT B-NP
+sharp JJ I-NP
+dive NN I-NP
+. . O
+
+The DT B-NP
+chancellor NN I-NP
+expected VBD B-VP
+a DT B-NP
+sharp JJ I-NP
+rise NN I-NP
+in IN B-PP
+the DT B-NP
+pound NN I-NP
+. . O
+";
+
+    #[test]
+    fn perceptron_chunker() {
+        let sentences = Conll2000.read(CORPUS.as_bytes()).unwrap();
+
+        let mut chunker = PerceptronChunker::new();
+        chunker.train(&sentences, &mut EpochConfig::new().iterations(10).seed(0));
+        assert_eq!(1.0, chunker.evaluate(&sentences).unwrap().f1());
+
+        let tagged = sentences[1]
+            .iter()
+            .map(|t| (t.token.clone(), t.tag.clone()))
+            .collect::<Vec<_>>();
+        let chunks = chunker.chunk(&tagged).unwrap();
+        assert_eq!(Span::new(0, 2, "NP"), chunks[0]);
+        assert_eq!(Span::new(2, 3, "VP"), chunks[1]);
+
+        let mut bytes = Vec::new();
+        chunker.save_to(&mut bytes).unwrap();
+        let loaded = PerceptronChunker::load_from(&bytes[..]).unwrap();
+        assert_eq!(chunks, loaded.chunk(&tagged).unwrap());
+    }
+}
@@ -0,0 +1,282 @@
+//! A chunker driven by regular expressions over tags, like NLTK's `RegexpParser`.
+//!
+//! A grammar is a list of rules, each under a chunk label:
+//!
+//! ```text
+//! # Noun phrases, leaving out verbs and prepositions
+//! NP: {<DT|PRP\$>?<JJ.*>*<NN.*>+}
+//!     }<VBD|IN>+{
+//! PP: {<IN>}
+//! VP: {<VB.*>+}
+//! ```
+//!
+//! A chunk rule `{...}` groups every run of unchunked tokens whose tags match its pattern, and a
+//! chink rule `}...{` removes matching tokens from chunks with its label, splitting them if need
+//! be. Rules apply in order. In a pattern, every tag is written in angle brackets and may itself
+//! be a regular expression, except that `.` never matches past the end of a tag.
+
+use super::*;
+
+use failure::ResultExt;
+use regex::Regex;
+
+/// A single rule of a grammar.
+#[derive(Clone, Debug)]
+struct Rule {
+    label: String,
+    /// Whether the rule chunks tokens, rather than chinking them.
+    chunk: bool,
+    pattern: Regex,
+}
+
+/// A chunker which follows a grammar of regular expressions over tags.
+///
+/// # Examples
+/// ```rust
+/// # use smol::chunk::*;
+/// # use smol::tag::*;
+/// let words = vec![("the", "DT"), ("little", "JJ"), ("dog", "NN"), ("barked", "VBD")]
+///     .into_iter()
+///     .map(|(w, t)| (w.to_owned(), t.to_owned()))
+///     .collect::<Vec<_>>();
+/// let tagged = sentence_tokens(&words)
+///     .into_iter()
+///     .zip(words.iter().map(|x| x.1.clone()))
+///     .collect::<Vec<_>>();
+///
+/// let chunker = RegexpChunker::new("NP: {<DT>?<JJ>*<NN>}").unwrap();
+/// let chunks = chunker.chunk(&tagged).unwrap();
+/// assert_eq!((0, 3, "NP"), (chunks[0].start, chunks[0].end, &*chunks[0].label));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RegexpChunker {
+    rules: Vec<Rule>,
+}
+
+impl RegexpChunker {
+    /// Parses a grammar. Lines starting with `#` are comments, and lines without a label belong to
+    /// the label before them.
+    pub fn new(grammar: &str) -> Result<RegexpChunker, SmolError> {
+        let mut rules = Vec::new();
+        let mut label = None;
+
+        for line in grammar.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let rule = match line.find([':', '{', '}']) {
+                Some(ix) if line[ix..].starts_with(':') => {
+                    label = Some(line[..ix].trim().to_owned());
+                    line[ix + 1..].trim()
+                }
+                _ => line,
+            };
+            if rule.is_empty() {
+                continue;
+            }
+
+            let chunk = if rule.starts_with('{') && rule.ends_with('}') {
+                true
+            } else if rule.starts_with('}') && rule.ends_with('{') {
+                false
+            } else {
+                return Err(SmolErrorKind::Parse.into());
+            };
+
+            rules.push(Rule {
+                label: label.clone().ok_or(SmolErrorKind::Parse)?,
+                chunk,
+                pattern: tag_pattern(&rule[1..rule.len() - 1])?,
+            });
+        }
+
+        Ok(RegexpChunker { rules })
+    }
+}
+
+impl Chunker for RegexpChunker {
+    fn chunk(&self, tagged: &[(Token, String)]) -> Result<Vec<Span>, SmolError> {
+        let tags = tagged.iter().map(|x| &*x.1).collect::<Vec<_>>();
+        // The chunk every token belongs to, and the label of every chunk
+        let mut chunks: Vec<Option<usize>> = vec![None; tags.len()];
+        let mut labels = Vec::new();
+
+        for rule in &self.rules {
+            let mut i = 0;
+            while i < tags.len() {
+                let target = chunks[i];
+                let end = (i..tags.len())
+                    .find(|&j| chunks[j] != target)
+                    .unwrap_or(tags.len());
+
+                let applies = match target {
+                    None => rule.chunk,
+                    Some(c) => !rule.chunk && labels[c] == rule.label,
+                };
+                if !applies {
+                    i = end;
+                    continue;
+                }
+
+                for (start, stop) in matches(&rule.pattern, &tags[i..end]) {
+                    let id = labels.len();
+                    for c in &mut chunks[i + start..i + stop] {
+                        *c = if rule.chunk { Some(id) } else { None };
+                    }
+                    if rule.chunk {
+                        labels.push(rule.label.clone());
+                    }
+                }
+
+                i = end;
+            }
+        }
+
+        let mut res: Vec<Span> = Vec::new();
+        for (i, c) in chunks.iter().enumerate() {
+            let c = match *c {
+                Some(c) => c,
+                None => continue,
+            };
+            match res.last_mut() {
+                Some(s) if s.end == i && i > 0 && chunks[i - 1] == Some(c) => s.end += 1,
+                _ => res.push(Span::new(i, i + 1, &labels[c])),
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+/// Turns a pattern over tags into a regular expression over tags written as `<TAG>`.
+fn tag_pattern(pattern: &str) -> Result<Regex, SmolError> {
+    let mut res = String::new();
+    let mut escaped = false;
+    let mut in_tag = false;
+
+    for c in pattern.chars().filter(|c| !c.is_whitespace()) {
+        match c {
+            _ if escaped => {
+                res.push(c);
+                escaped = false;
+            }
+            '\\' => {
+                res.push(c);
+                escaped = true;
+            }
+            '<' if !in_tag => {
+                res.push_str("(?:<(?:");
+                in_tag = true;
+            }
+            '>' if in_tag => {
+                res.push_str(")>)");
+                in_tag = false;
+            }
+            '<' | '>' | '{' | '}' => return Err(SmolErrorKind::Parse.into()),
+            '.' => res.push_str("[^<>]"),
+            _ => res.push(c),
+        }
+    }
+
+    if in_tag || escaped {
+        return Err(SmolErrorKind::Parse.into());
+    }
+
+    Ok(Regex::new(&res).context(SmolErrorKind::Parse)?)
+}
+
+/// Every non-empty match of a tag pattern, as ranges of tag indices.
+fn matches(pattern: &Regex, tags: &[&str]) -> Vec<(usize, usize)> {
+    let mut s = String::new();
+    let mut starts = Vec::with_capacity(tags.len() + 1);
+    for t in tags {
+        starts.push(s.len());
+        s.push('<');
+        s.push_str(t);
+        s.push('>');
+    }
+    starts.push(s.len());
+
+    pattern
+        .find_iter(&s)
+        .filter_map(|m| {
+            let start = starts.binary_search(&m.start()).ok()?;
+            let end = starts.binary_search(&m.end()).ok()?;
+            if start < end {
+                Some((start, end))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tag::sentence_tokens;
+
+    fn chunks(grammar: &str, words: &[(&str, &str)]) -> Vec<(usize, usize, String)> {
+        let words = words
+            .iter()
+            .map(|&(w, t)| (w.to_owned(), t.to_owned()))
+            .collect::<Vec<_>>();
+        let tagged = sentence_tokens(&words)
+            .into_iter()
+            .zip(words.iter().map(|x| x.1.clone()))
+            .collect::<Vec<_>>();
+
+        RegexpChunker::new(grammar)
+            .unwrap()
+            .chunk(&tagged)
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.start, s.end, s.label))
+            .collect()
+    }
+
+    #[test]
+    fn regexp_chunk_and_chink() {
+        let sentence = [
+            ("the", "DT"),
+            ("little", "JJ"),
+            ("yellow", "JJ"),
+            ("dog", "NN"),
+            ("barked", "VBD"),
+            ("at", "IN"),
+            ("the", "DT"),
+            ("cat", "NN"),
+        ];
+
+        let grammar = "
+            # Chunk everything, then chink verbs and prepositions
+            NP: {<.*>+}
+                }<VBD|IN>+{
+            VP: {<VB.*>}
+            PP: {<IN>}
+        ";
+        assert_eq!(
+            vec![
+                (0, 4, "NP".to_owned()),
+                (4, 5, "VP".to_owned()),
+                (5, 6, "PP".to_owned()),
+                (6, 8, "NP".to_owned()),
+            ],
+            chunks(grammar, &sentence)
+        );
+
+        // Adjacent matches are separate chunks, and tags must match whole
+        assert_eq!(
+            vec![(0, 1, "NP".to_owned()), (1, 2, "NP".to_owned())],
+            chunks(
+                "NP: {<NN>}",
+                &[("dogs", "NN"), ("cats", "NN"), ("run", "NNS")]
+            )
+        );
+
+        assert!(RegexpChunker::new("{<NN>}").is_err());
+        assert!(RegexpChunker::new("NP: {<NN}").is_err());
+        assert!(RegexpChunker::new("NP: <NN>").is_err());
+    }
+}
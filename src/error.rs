@@ -3,6 +3,9 @@
 //! Defines broad types used for errors and failures.
 //! Individual modules may also contain failure types of their own.
 
+// failure_derive generates its impls inside an anonymous const.
+#![allow(non_local_definitions)]
+
 use failure::{Backtrace, Context, Fail};
 use std::fmt;
 
@@ -20,7 +23,7 @@ impl SmolError {
 }
 
 impl Fail for SmolError {
-    fn cause(&self) -> Option<&Fail> {
+    fn cause(&self) -> Option<&dyn Fail> {
         self.inner.cause()
     }
 
@@ -45,7 +48,7 @@ impl From<SmolErrorKind> for SmolError {
 
 impl From<Context<SmolErrorKind>> for SmolError {
     fn from(inner: Context<SmolErrorKind>) -> SmolError {
-        SmolError { inner: inner }
+        SmolError { inner }
     }
 }
 
@@ -59,9 +62,16 @@ impl From<Context<SmolErrorKind>> for SmolError {
 // upsides: easy to tell where an error originated and its type
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail)]
 pub enum SmolErrorKind {
+    #[fail(display = "A model file is corrupt.")] Corrupt,
     #[fail(display = "Couldn't deserialize a data structure.")] Deserialize,
     #[fail(display = "Can't use an empty model.")] EmptyModel,
+    #[fail(display = "A tagger has no training state.")] MissingState,
+    #[fail(display = "A tagger's output didn't line up with its input.")] Mismatch,
+    #[fail(display = "Couldn't parse a corpus.")] Parse,
+    #[fail(display = "Couldn't read input.")] Read,
     #[fail(display = "Couldn't serialize a data structure.")] Serialize,
+    #[fail(display = "A tag isn't part of the expected tagset.")] UnknownTag,
+    #[fail(display = "A model file has an unsupported format version.")] Version,
     #[fail(display = "Error occurred while tagging.")] Write,
     #[fail(display = "A miscellaneous error ocurred")] Other,
 }
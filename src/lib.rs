@@ -1,15 +1,21 @@
 extern crate bincode;
-#[macro_use]
 extern crate failure;
 extern crate itertools;
+extern crate memmap;
 extern crate rand;
+#[cfg(feature = "parallel")]
+extern crate rayon;
 extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 
+pub mod chunk;
 pub mod error;
 pub mod metrics;
+pub mod ner;
+pub mod parse;
 pub mod tokenize;
 pub mod tag;
 
@@ -13,7 +13,7 @@ use std::mem::{replace, swap};
 /// * `a`: the first piece of text to be compared.
 /// * `b`: the second piece of text to be compared.
 /// * `threshold`: The maximum distance value. A lower distance value will improve performance
-/// in extreme cases, at the cost of correctness.
+///   in extreme cases, at the cost of correctness.
 ///
 /// # Examples
 /// ```rust
@@ -97,7 +97,7 @@ where
         let end = if i <= condition_row { i } else { endmax - i };
 
         if i <= zero_k {
-            nrow[zero_k + i as usize] = -1;
+            nrow[zero_k + i] = -1;
         }
 
         for x in start..end as isize {
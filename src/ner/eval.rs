@@ -0,0 +1,165 @@
+//! Span-level evaluation, as in the CoNLL shared tasks.
+//!
+//! A predicted span only counts as correct if both its boundaries and its label match a gold
+//! span exactly.
+
+use super::*;
+use tag::eval::ratio;
+use tag::TagScore;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// Counts of correct, predicted and gold spans.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct SpanCounts {
+    pub correct: usize,
+    pub predicted: usize,
+    pub gold: usize,
+}
+
+impl SpanCounts {
+    /// The fraction of predicted spans which are correct.
+    pub fn precision(&self) -> f64 {
+        ratio(self.correct as f64, self.predicted as f64)
+    }
+
+    /// The fraction of gold spans which were found.
+    pub fn recall(&self) -> f64 {
+        ratio(self.correct as f64, self.gold as f64)
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (p, r) = (self.precision(), self.recall());
+        ratio(2.0 * p * r, p + r)
+    }
+}
+
+/// The results of comparing predicted spans against gold spans.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct SpanEvaluation {
+    pub overall: SpanCounts,
+    pub labels: BTreeMap<String, SpanCounts>,
+}
+
+impl SpanEvaluation {
+    pub fn new() -> SpanEvaluation {
+        SpanEvaluation::default()
+    }
+
+    /// Adds the spans of a single sentence.
+    pub fn add(&mut self, gold: &[Span], predicted: &[Span]) {
+        let gold_set = gold.iter().collect::<HashSet<_>>();
+
+        for s in gold {
+            self.overall.gold += 1;
+            self.labels.entry(s.label.clone()).or_default().gold += 1;
+        }
+
+        for s in predicted {
+            let correct = gold_set.contains(s) as usize;
+            self.overall.predicted += 1;
+            self.overall.correct += correct;
+
+            let counts = self.labels.entry(s.label.clone()).or_default();
+            counts.predicted += 1;
+            counts.correct += correct;
+        }
+    }
+
+    pub fn precision(&self) -> f64 {
+        self.overall.precision()
+    }
+
+    pub fn recall(&self) -> f64 {
+        self.overall.recall()
+    }
+
+    pub fn f1(&self) -> f64 {
+        self.overall.f1()
+    }
+
+    /// Precision, recall and F1 for every label, sorted by label.
+    pub fn scores(&self) -> Vec<TagScore> {
+        self.labels
+            .iter()
+            .map(|(l, c)| TagScore {
+                tag: l.clone(),
+                precision: c.precision(),
+                recall: c.recall(),
+                f1: c.f1(),
+                support: c.gold,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for SpanEvaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .labels
+            .keys()
+            .map(|l| l.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(5);
+        writeln!(
+            f,
+            "{:>w$} {:>9} {:>9} {:>9} {:>9}",
+            "label",
+            "precision",
+            "recall",
+            "f1",
+            "support",
+            w = width
+        )?;
+
+        for (l, c) in self
+            .labels
+            .iter()
+            .chain(Some((&"overall".to_owned(), &self.overall)))
+        {
+            writeln!(
+                f,
+                "{:>w$} {:>9.4} {:>9.4} {:>9.4} {:>9}",
+                l,
+                c.precision(),
+                c.recall(),
+                c.f1(),
+                c.gold,
+                w = width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_scores() {
+        let gold = spans(&["B-PER", "I-PER", "O", "B-LOC", "O", "B-ORG"]);
+        // The person is cut short, the location is right and the organization is missed
+        let predicted = spans(&["B-PER", "O", "O", "B-LOC", "B-LOC", "O"]);
+
+        let mut e = SpanEvaluation::new();
+        e.add(&gold, &predicted);
+
+        assert_eq!(
+            SpanCounts {
+                correct: 1,
+                predicted: 3,
+                gold: 3,
+            },
+            e.overall
+        );
+        assert_eq!(1.0 / 3.0, e.precision());
+        assert_eq!(1.0 / 3.0, e.f1());
+        assert_eq!(0.5, e.labels["LOC"].precision());
+        assert_eq!(1.0, e.labels["LOC"].recall());
+        assert_eq!(0.0, e.labels["ORG"].f1());
+    }
+}
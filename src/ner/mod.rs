@@ -0,0 +1,14 @@
+//! Named entity recognition.
+//!
+//! Entities are found by tagging every token with its place in an entity, using one of the
+//! usual tagging schemes (`B-PER`, `I-PER`, `O` and so on), and then decoding those tags into
+//! spans. Any sequence model in `tag` can be used as the underlying tagger.
+
+pub mod eval;
+pub mod recognizer;
+pub mod scheme;
+
+// Re-exports
+pub use self::eval::*;
+pub use self::recognizer::*;
+pub use self::scheme::*;
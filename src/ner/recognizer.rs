@@ -0,0 +1,257 @@
+//! Recognizing entities with a sequence tagger.
+
+use super::*;
+use error::*;
+use tag::*;
+use tokenize::*;
+
+/// An entity found in some text, from character offset `start` up to but not including `end`.
+/// Offsets are counted like those of the tokens the entity was found in.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize)]
+pub struct Entity {
+    pub start: usize,
+    pub end: usize,
+    pub label: String,
+}
+
+impl Entity {
+    /// Finds where a span of tokens starts and ends in the text, or returns `None` if the span is
+    /// empty or runs past the end of the tokens.
+    pub fn from_span(span: &Span, tokens: &[Token]) -> Option<Entity> {
+        if span.start >= span.end {
+            return None;
+        }
+
+        let first = tokens.get(span.start)?;
+        let last = tokens.get(span.end - 1)?;
+        Some(Entity {
+            start: first.offset,
+            end: last.offset + last.term.chars().count(),
+            label: span.label.clone(),
+        })
+    }
+}
+
+/// The features used for entities by default: the perceptron's default features, along with the
+/// shape and capitalization of every word.
+pub fn entity_templates() -> TemplateSet {
+    TemplateSet::default().extended(vec![FeatureTemplate::Shape, FeatureTemplate::Capitalization])
+}
+
+/// Finds named entities, by tagging tokens with a sequence model and decoding the tags into
+/// spans.
+///
+/// # Examples
+/// ```rust
+/// # use smol::ner::*;
+/// # use smol::tag::*;
+/// let sentences = Conll2003
+///     .read("Alice NNP B-NP B-PER\nsleeps VBZ B-VP O\n".as_bytes())
+///     .unwrap();
+///
+/// let mut ner = EntityRecognizer::default();
+/// ner.train(&sentences, &mut TrainConfig::new().seed(0));
+///
+/// let words = [("Alice".to_owned(), String::new())];
+/// let entities = ner.recognize(sentence_tokens(&words)).unwrap();
+/// assert_eq!("PER", entities[0].label);
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub struct EntityRecognizer<T = PerceptronTagger> {
+    tagger: T,
+    scheme: Scheme,
+}
+
+impl Default for EntityRecognizer<PerceptronTagger> {
+    fn default() -> EntityRecognizer<PerceptronTagger> {
+        EntityRecognizer::new(PerceptronTagger::new().templates(entity_templates()))
+    }
+}
+
+impl<T> EntityRecognizer<T> {
+    /// Creates a recognizer from a tagger, which may already be trained.
+    pub fn new(tagger: T) -> EntityRecognizer<T> {
+        EntityRecognizer {
+            tagger,
+            scheme: Scheme::default(),
+        }
+    }
+
+    /// Sets the scheme the tagger is trained to output. Defaults to BIO.
+    pub fn scheme(mut self, scheme: Scheme) -> EntityRecognizer<T> {
+        self.scheme = scheme;
+        self
+    }
+
+    pub fn get_scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    pub fn get_tagger(&self) -> &T {
+        &self.tagger
+    }
+
+    pub fn into_tagger(self) -> T {
+        self.tagger
+    }
+
+    /// Turns annotated sentences into training pairs of words and entity tags, in this
+    /// recognizer's scheme.
+    fn training_pairs(&self, sentences: &[AnnotatedSentence]) -> Vec<Vec<(String, String)>> {
+        sentences
+            .iter()
+            .map(|s| {
+                let tags = self.scheme.convert(&entity_tags(s));
+                s.iter()
+                    .map(|t| t.token.term.to_string())
+                    .zip(tags)
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl<T: Tagger<Tag = String>> EntityRecognizer<T> {
+    /// Finds the entities in a sentence.
+    pub fn recognize<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        tokens: I,
+    ) -> Result<Vec<Entity>, SmolError> {
+        let tagged = self.tagger.tag(tokens)?;
+        let tags = tagged.iter().map(|x| &*x.1).collect::<Vec<_>>();
+        let tokens = tagged.iter().map(|x| x.0.clone()).collect::<Vec<_>>();
+
+        Ok(spans(&tags)
+            .iter()
+            .filter_map(|s| Entity::from_span(s, &tokens))
+            .collect())
+    }
+
+    /// Compares the entities found in every gold sentence against its gold entities.
+    pub fn evaluate(&self, gold: &[AnnotatedSentence]) -> Result<SpanEvaluation, SmolError> {
+        let mut res = SpanEvaluation::new();
+
+        for sentence in gold {
+            let tokens = sentence.iter().map(|t| t.token.clone());
+            let tagged = self.tagger.tag(tokens)?;
+            if tagged.len() != sentence.len() {
+                return Err(SmolErrorKind::Mismatch.into());
+            }
+
+            let predicted = tagged.iter().map(|x| &*x.1).collect::<Vec<_>>();
+            res.add(&spans(&entity_tags(sentence)), &spans(&predicted));
+        }
+
+        Ok(res)
+    }
+}
+
+impl EntityRecognizer<PerceptronTagger> {
+    /// Trains the tagger on the entities of annotated sentences.
+    pub fn train(&mut self, sentences: &[AnnotatedSentence], config: &mut TrainConfig) {
+        let pairs = self.training_pairs(sentences);
+        let pairs = pairs.iter().map(|s| &s[..]).collect();
+        self.tagger.train_with(pairs, config);
+    }
+}
+
+impl EntityRecognizer<CrfTagger> {
+    /// Trains the tagger on the entities of annotated sentences.
+    pub fn train(&mut self, sentences: &[AnnotatedSentence], config: &CrfConfig) {
+        let pairs = self.training_pairs(sentences);
+        let pairs = pairs.iter().map(|s| &s[..]).collect::<Vec<_>>();
+        self.tagger.train(&pairs, config);
+    }
+}
+
+/// The entity tags of a sentence, with `O` for tokens without one.
+fn entity_tags<'a>(sentence: &'a [AnnotatedToken]) -> Vec<&'a str> {
+    sentence
+        .iter()
+        .map(|t| t.entity.as_deref().unwrap_or("O"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CORPUS: &str = "-DOCSTART- -X- -X- O
+
+John NNP B-NP B-PER
+Smith NNP I-NP I-PER
+visited VBD B-VP O
+Paris NNP B-NP B-LOC
+. . O O
+
+Mary NNP B-NP B-PER
+left VBD B-VP O
+New NNP B-NP B-LOC
+York NNP I-NP I-LOC
+. . O O
+
+Smith NNP B-NP B-PER
+saw VBD B-VP O
+Mary NNP B-NP B-PER
+. . O O
+";
+
+    #[test]
+    fn recognizer_spans() {
+        let sentences = Conll2003.read(CORPUS.as_bytes()).unwrap();
+        assert_eq!(3, sentences.len());
+
+        let mut ner = EntityRecognizer::default().scheme(Scheme::Bioes);
+        ner.train(&sentences, &mut TrainConfig::new().iterations(10).seed(0));
+        assert!(ner.get_tagger().get_model().class_id("E-PER").is_some());
+
+        let e = ner.evaluate(&sentences).unwrap();
+        assert_eq!(1.0, e.f1());
+
+        // "John Smith visited Paris ." with the offsets of the original text
+        let entities = ner.recognize(sentences[0].iter().map(|t| t.token.clone()));
+        assert_eq!(
+            vec![
+                Entity {
+                    start: 0,
+                    end: 10,
+                    label: "PER".to_owned(),
+                },
+                Entity {
+                    start: 19,
+                    end: 24,
+                    label: "LOC".to_owned(),
+                },
+            ],
+            entities.unwrap()
+        );
+    }
+
+    #[test]
+    fn recognizer_offsets() {
+        let words = [
+            ("Zoë".to_owned(), "NNP".to_owned()),
+            ("Ågren".to_owned(), "NNP".to_owned()),
+            ("sleeps".to_owned(), "VBZ".to_owned()),
+        ];
+        let tokens = sentence_tokens(&words);
+
+        // Offsets are in characters, so the entity ends right before the space after "Ågren"
+        let entity = Entity::from_span(&Span::new(0, 2, "PER"), &tokens).unwrap();
+        assert_eq!((0, 9), (entity.start, entity.end));
+        assert_eq!(tokens[2].offset, entity.end + 1);
+
+        assert_eq!(None, Entity::from_span(&Span::new(1, 1, "PER"), &tokens));
+        assert_eq!(None, Entity::from_span(&Span::new(2, 4, "PER"), &tokens));
+    }
+
+    #[test]
+    fn recognizer_crf() {
+        let sentences = Conll2003.read(CORPUS.as_bytes()).unwrap();
+
+        let mut ner = EntityRecognizer::new(CrfTagger::new().templates(entity_templates()));
+        ner.train(&sentences, &CrfConfig::new().iterations(30).seed(0));
+
+        assert_eq!(1.0, ner.evaluate(&sentences).unwrap().f1());
+    }
+}
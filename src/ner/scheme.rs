@@ -0,0 +1,169 @@
+//! Converting between per-token tags and labelled spans.
+//!
+//! Decoding is lenient: it understands BIO, BIOES and BILOU tags alike, as well as the IOB1 tags
+//! of the original CoNLL-2003 data, where `I-` may start an entity.
+
+use std::fmt;
+
+/// A labelled range of tokens, from `start` up to but not including `end`.
+#[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub label: String,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, label: &str) -> Span {
+        Span {
+            start,
+            end,
+            label: label.to_owned(),
+        }
+    }
+}
+
+/// A way of marking spans with per-token tags.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub enum Scheme {
+    /// `B-` begins a span and `I-` continues it.
+    Bio,
+    /// Like `Bio`, but `E-` ends a span and `S-` marks a span of a single token.
+    Bioes,
+    /// The same as `Bioes`, with `L-` (last) and `U-` (unit) instead of `E-` and `S-`.
+    Bilou,
+}
+
+// Deriving this needs `#[default]`, which older compilers don't support
+#[allow(clippy::derivable_impls)]
+impl Default for Scheme {
+    fn default() -> Scheme {
+        Scheme::Bio
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Scheme::Bio => "BIO",
+            Scheme::Bioes => "BIOES",
+            Scheme::Bilou => "BILOU",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Scheme {
+    /// Tags `len` tokens with the given spans. Tokens outside any span are tagged `O`.
+    pub fn encode(self, spans: &[Span], len: usize) -> Vec<String> {
+        let mut res = vec!["O".to_owned(); len];
+
+        for s in spans.iter().filter(|s| s.start < s.end && s.end <= len) {
+            let (begin, inside, last, unit) = match self {
+                Scheme::Bio => ("B", "I", "I", "B"),
+                Scheme::Bioes => ("B", "I", "E", "S"),
+                Scheme::Bilou => ("B", "I", "L", "U"),
+            };
+
+            for (i, tag) in res.iter_mut().enumerate().take(s.end).skip(s.start) {
+                let prefix = if s.end - s.start == 1 {
+                    unit
+                } else if i == s.start {
+                    begin
+                } else if i == s.end - 1 {
+                    last
+                } else {
+                    inside
+                };
+                *tag = format!("{}-{}", prefix, s.label);
+            }
+        }
+
+        res
+    }
+
+    /// Rewrites tags in any scheme into this one.
+    pub fn convert<S: AsRef<str>>(self, tags: &[S]) -> Vec<String> {
+        self.encode(&spans(tags), tags.len())
+    }
+}
+
+/// Splits a tag like `B-PER` into its prefix and label. Tags without a label, such as `O`, have
+/// an empty label.
+fn split(tag: &str) -> (&str, &str) {
+    match tag.find('-') {
+        Some(1) => (&tag[..1], &tag[2..]),
+        _ => (tag, ""),
+    }
+}
+
+/// Finds the spans marked by per-token tags.
+pub fn spans<S: AsRef<str>>(tags: &[S]) -> Vec<Span> {
+    let mut res = Vec::new();
+    let mut open: Option<(usize, &str)> = None;
+
+    for (i, tag) in tags.iter().enumerate() {
+        let (prefix, label) = split(tag.as_ref());
+
+        let continues = ["I", "E", "L"].contains(&prefix) && open.map(|o| o.1) == Some(label);
+
+        if !continues {
+            if let Some((start, l)) = open.take() {
+                res.push(Span::new(start, i, l));
+            }
+            if !label.is_empty() {
+                open = Some((i, label));
+            }
+        }
+
+        // Spans which end here
+        if ["E", "L", "S", "U"].contains(&prefix) {
+            if let Some((start, l)) = open.take() {
+                res.push(Span::new(start, i + 1, l));
+            }
+        }
+    }
+
+    if let Some((start, l)) = open {
+        res.push(Span::new(start, tags.len(), l));
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scheme_conversion() {
+        let bio = vec!["B-PER", "I-PER", "O", "B-LOC", "B-LOC", "I-LOC", "I-LOC"];
+        let spans = spans(&bio);
+        assert_eq!(
+            vec![
+                Span::new(0, 2, "PER"),
+                Span::new(3, 4, "LOC"),
+                Span::new(4, 7, "LOC"),
+            ],
+            spans
+        );
+
+        let bioes = Scheme::Bioes.convert(&bio);
+        assert_eq!(
+            vec!["B-PER", "E-PER", "O", "S-LOC", "B-LOC", "I-LOC", "E-LOC"],
+            bioes
+        );
+        assert_eq!(bio, Scheme::Bio.convert(&bioes));
+        assert_eq!(
+            vec!["B-PER", "L-PER", "O", "U-LOC", "B-LOC", "I-LOC", "L-LOC"],
+            Scheme::Bilou.convert(&bio)
+        );
+
+        // IOB1, and ill-formed tags which switch labels mid-span
+        let iob1 = vec!["I-ORG", "I-ORG", "O", "I-PER", "I-LOC"];
+        assert_eq!(
+            vec!["B-ORG", "I-ORG", "O", "B-PER", "B-LOC"],
+            Scheme::Bio.convert(&iob1)
+        );
+    }
+}
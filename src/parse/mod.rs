@@ -0,0 +1,77 @@
+//! Dependency parsing.
+//!
+//! A dependency parser finds the syntactic head of every token of a tagged sentence, along with
+//! the relation between the two (e.g. `nsubj` or `obj`). Heads are positions counting from 1,
+//! with 0 standing for the root of the sentence, as in CoNLL-U.
+
+pub mod parser;
+mod transition;
+
+// Re-exports
+pub use self::parser::*;
+
+use tag::eval::ratio;
+
+use std::fmt;
+
+/// The head of a token, and its relation to it.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Serialize)]
+pub struct Dependency {
+    /// The position of the head, counting from 1, or 0 for the root.
+    pub head: usize,
+    pub deprel: String,
+}
+
+/// Counts of correctly attached tokens.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct AttachmentScore {
+    pub total: usize,
+    /// The number of tokens with the right head.
+    pub unlabeled: usize,
+    /// The number of tokens with both the right head and the right relation.
+    pub labeled: usize,
+}
+
+impl AttachmentScore {
+    /// Adds the dependencies of a single sentence.
+    pub fn add(&mut self, gold: &[Dependency], predicted: &[Dependency]) {
+        for (g, p) in gold.iter().zip(predicted) {
+            self.total += 1;
+            if g.head == p.head {
+                self.unlabeled += 1;
+                if g.deprel == p.deprel {
+                    self.labeled += 1;
+                }
+            }
+        }
+    }
+
+    /// The unlabeled attachment score: the fraction of tokens with the right head.
+    pub fn uas(&self) -> f64 {
+        ratio(self.unlabeled as f64, self.total as f64)
+    }
+
+    /// The labeled attachment score: the fraction of tokens with the right head and relation.
+    pub fn las(&self) -> f64 {
+        ratio(self.labeled as f64, self.total as f64)
+    }
+}
+
+impl fmt::Display for AttachmentScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "UAS: {:.4} ({}/{})",
+            self.uas(),
+            self.unlabeled,
+            self.total
+        )?;
+        write!(
+            f,
+            "LAS: {:.4} ({}/{})",
+            self.las(),
+            self.labeled,
+            self.total
+        )
+    }
+}
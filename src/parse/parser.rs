@@ -0,0 +1,377 @@
+//! A transition-based dependency parser, trained with an averaged perceptron.
+//!
+//! Based on [a parser by Matthew Honnibal](https://explosion.ai/blog/parsing-english-in-python),
+//! using the arc-eager transition system. Training follows the model's own moves rather than the
+//! gold ones, using a dynamic oracle to find the best move from wherever the model ends up, so
+//! that the parser learns to recover from its mistakes.
+
+use super::transition::{Move, State};
+use super::*;
+use error::*;
+use tag::format::{decode, encode, now, read_all};
+use tag::split::seeded_rng;
+use tag::*;
+use tokenize::*;
+
+use bincode::deserialize;
+use failure::ResultExt;
+use rand::{thread_rng, Rng};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+/// A dependency parser, which reads tagged sentences and finds the head of every token.
+///
+/// # Examples
+/// ```rust
+/// # use smol::parse::*;
+/// # use smol::tag::*;
+/// let sentences = Conllu::new()
+///     .read("1\tdogs\t_\tNOUN\tNNS\t_\t2\tnsubj\t_\t_\n2\tbark\t_\tVERB\tVBP\t_\t0\troot\t_\t_\n".as_bytes())
+///     .unwrap();
+///
+/// let mut parser = DependencyParser::new();
+/// parser.train(&sentences, &mut EpochConfig::new().seed(0));
+/// assert_eq!(1.0, parser.evaluate(&sentences).unwrap().las());
+/// ```
+#[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct DependencyParser {
+    model: AveragedPerceptron,
+    corpus: CorpusInfo,
+}
+
+impl DependencyParser {
+    pub fn new() -> DependencyParser {
+        DependencyParser::default()
+    }
+
+    pub fn get_model(&self) -> &AveragedPerceptron {
+        &self.model
+    }
+
+    pub fn get_corpus(&self) -> &CorpusInfo {
+        &self.corpus
+    }
+
+    /// Trains the parser on the tags and dependencies of annotated sentences, such as those read
+    /// from CoNLL-U. Sentences with a token without a head are skipped.
+    pub fn train(&mut self, sentences: &[AnnotatedSentence], config: &mut EpochConfig) {
+        match config.get_seed() {
+            Some(seed) => self.train_with_rng(sentences, config, &mut seeded_rng(seed)),
+            None => self.train_with_rng(sentences, config, &mut thread_rng()),
+        }
+    }
+
+    /// Trains the parser, shuffling the sentences between iterations with `rng`. The seed of the
+    /// configuration is ignored.
+    pub fn train_with_rng<R: Rng>(
+        &mut self,
+        sentences: &[AnnotatedSentence],
+        config: &mut EpochConfig,
+        rng: &mut R,
+    ) {
+        let start = Instant::now();
+        let mut sentences = sentences
+            .iter()
+            .filter(|s| {
+                s.iter()
+                    .all(|t| t.head.map(|h| h <= s.len()).unwrap_or(false))
+            })
+            .collect::<Vec<_>>();
+
+        self.corpus.sentences = sentences.len();
+        self.corpus.tokens = sentences.iter().map(|s| s.len()).sum();
+
+        let labels = sentences
+            .iter()
+            .flat_map(|s| s.iter().map(|t| t.deprel.clone().unwrap_or_default()))
+            .collect::<BTreeSet<_>>();
+        self.model.add_class(&Move::Shift.name());
+        self.model.add_class(&Move::Reduce.name());
+        for l in labels {
+            self.model.add_class(&Move::Left(l.clone()).name());
+            self.model.add_class(&Move::Right(l).name());
+        }
+        let moves = self.moves();
+
+        let mut features = Features::new();
+        let mut ids = Vec::new();
+
+        for epoch in 1..config.get_iterations() + 1 {
+            let (mut errors, mut tokens) = (0, 0);
+
+            for sentence in &sentences {
+                let (words, tags) = words_and_tags(sentence.iter().map(|t| (&t.token, &*t.tag)));
+                let heads = Some(0)
+                    .into_iter()
+                    .chain(sentence.iter().map(|t| t.head.unwrap()))
+                    .collect::<Vec<_>>();
+                let labels = Some(String::new())
+                    .into_iter()
+                    .chain(
+                        sentence
+                            .iter()
+                            .map(|t| t.deprel.clone().unwrap_or_default()),
+                    )
+                    .collect::<Vec<_>>();
+
+                let mut state = State::new(words.len() - 1);
+                while !state.is_done() {
+                    extract(&state, &words, &tags, &mut features);
+                    self.model.intern_all(&features, &mut ids);
+                    let scores = self.model.scores(&ids);
+
+                    let valid = (0..moves.len())
+                        .filter(|&c| state.is_valid(&moves[c]))
+                        .collect::<Vec<_>>();
+                    let costs = valid
+                        .iter()
+                        .map(|&c| state.cost(&moves[c], &heads, &labels))
+                        .collect::<Vec<_>>();
+                    let min_cost = costs.iter().cloned().min().unwrap_or(0);
+
+                    let guess = best(valid.iter().cloned(), &scores);
+                    let truth = best(
+                        valid
+                            .iter()
+                            .zip(&costs)
+                            .filter(|x| *x.1 == min_cost)
+                            .map(|x| *x.0),
+                        &scores,
+                    );
+
+                    self.model.update(truth, guess, &ids);
+                    tokens += 1;
+                    errors += (truth != guess) as usize;
+                    state.apply(&moves[guess]);
+                }
+            }
+
+            rng.shuffle(&mut sentences);
+            config.report(&EpochStats {
+                epoch,
+                errors,
+                tokens,
+                dev_accuracy: None,
+                dev_error: None,
+                elapsed: start.elapsed(),
+            });
+        }
+
+        self.model.average_weights();
+    }
+
+    /// The move of every class of the model, indexed by class ID.
+    fn moves(&self) -> Vec<Move> {
+        self.model
+            .classes()
+            .iter()
+            .map(|c| Move::from_name(c).unwrap_or(Move::Shift))
+            .collect()
+    }
+
+    /// Finds the head and relation of every token of a tagged sentence.
+    pub fn parse(&self, tagged: &[(Token, String)]) -> Result<Vec<Dependency>, SmolError> {
+        if self.model.classes().is_empty() {
+            return Err(SmolErrorKind::EmptyModel.into());
+        }
+
+        let moves = self.moves();
+        let (words, tags) = words_and_tags(tagged.iter().map(|x| (&x.0, &*x.1)));
+        let mut features = Features::new();
+        let mut ids = Vec::new();
+
+        let mut state = State::new(tagged.len());
+        while !state.is_done() {
+            extract(&state, &words, &tags, &mut features);
+            self.model.lookup(&features, &mut ids);
+            let scores = self.model.scores(&ids);
+            let valid = (0..moves.len()).filter(|&c| state.is_valid(&moves[c]));
+            let m = &moves[best(valid, &scores)];
+            state.apply(m);
+        }
+
+        Ok(state
+            .dependencies("root")
+            .into_iter()
+            .map(|(head, deprel)| Dependency { head, deprel })
+            .collect())
+    }
+
+    /// Parses every gold sentence, using its gold tags, and compares the result against its gold
+    /// dependencies.
+    pub fn evaluate(&self, gold: &[AnnotatedSentence]) -> Result<AttachmentScore, SmolError> {
+        let mut res = AttachmentScore::default();
+
+        for sentence in gold {
+            let tagged = sentence
+                .iter()
+                .map(|t| (t.token.clone(), t.tag.clone()))
+                .collect::<Vec<_>>();
+            let gold = sentence
+                .iter()
+                .map(|t| Dependency {
+                    head: t.head.unwrap_or(0),
+                    deprel: t.deprel.clone().unwrap_or_default(),
+                })
+                .collect::<Vec<_>>();
+
+            res.add(&gold, &self.parse(&tagged)?);
+        }
+
+        Ok(res)
+    }
+
+    /// Describes the parser, as it would be saved right now.
+    pub fn metadata(&self) -> ModelMetadata {
+        let mut tagset = self.model.classes().to_vec();
+        tagset.sort();
+
+        ModelMetadata {
+            templates: TemplateSet::empty(),
+            tagset,
+            corpus: self.corpus.clone(),
+            date: now(),
+        }
+    }
+}
+
+impl Persist for DependencyParser {
+    fn save_to<W: Write>(&self, mut writer: W) -> Result<(), SmolError> {
+        let s = encode(&(self.metadata(), self))?;
+        writer.write_all(&s).context(SmolErrorKind::Write)?;
+        Ok(())
+    }
+
+    fn load_from<R: Read>(reader: R) -> Result<DependencyParser, SmolError> {
+        let bytes = read_all(reader)?;
+        let body = decode(&bytes)?.ok_or(SmolErrorKind::Deserialize)?;
+        let (_, parser): (ModelMetadata, DependencyParser) =
+            deserialize(body).context(SmolErrorKind::Deserialize)?;
+        Ok(parser)
+    }
+}
+
+/// The normalized words and the tags of a sentence, indexed like a parse state.
+fn words_and_tags<'a, 't: 'a, I>(tokens: I) -> (Vec<String>, Vec<&'a str>)
+where
+    I: Iterator<Item = (&'a Token<'t>, &'a str)>,
+{
+    let mut words = vec!["-ROOT-".to_owned()];
+    let mut tags = vec!["-ROOT-"];
+    for (token, tag) in tokens {
+        words.push(PerceptronTagger::normalize_str(&token.term));
+        tags.push(tag);
+    }
+    (words, tags)
+}
+
+/// The highest scoring of the given classes, preferring earlier ones on ties.
+fn best<I: Iterator<Item = usize>>(classes: I, scores: &[f64]) -> usize {
+    classes
+        .fold(None, |best: Option<usize>, c| match best {
+            Some(b) if scores[b] >= scores[c] => Some(b),
+            _ => Some(c),
+        })
+        .unwrap_or(0)
+}
+
+/// Extracts the features of a parse state.
+fn extract(state: &State, words: &[String], tags: &[&str], features: &mut Features) {
+    let word = |w: Option<usize>| w.map(|w| &*words[w]).unwrap_or("-NONE-");
+    let tag = |w: Option<usize>| w.map(|w| tags[w]).unwrap_or("-NONE-");
+
+    let (s0, s1, s2) = (state.stack(0), state.stack(1), state.stack(2));
+    let (b0, b1, b2) = (state.buffer(0), state.buffer(1), state.buffer(2));
+    let s0_left = s0.and_then(|s| state.lefts[s].last().cloned());
+    let s0_right = s0.and_then(|s| state.rights[s].last().cloned());
+    let b0_left = b0.and_then(|b| state.lefts[b].last().cloned());
+    let s0_head = s0.and_then(|s| state.heads[s]);
+
+    let valency = |w: Option<usize>, children: &[Vec<usize>]| {
+        w.map(|w| children[w].len().min(5).to_string())
+            .unwrap_or_default()
+    };
+    let distance = match (s0, b0) {
+        (Some(s), Some(b)) => (b - s).min(5).to_string(),
+        _ => "0".to_owned(),
+    };
+
+    features.clear();
+    features.add(&["bias"]);
+
+    for &(name, w) in &[("s0", s0), ("s1", s1), ("b0", b0), ("b1", b1)] {
+        features.add(&[name, "word", word(w)]);
+        features.add(&[name, "tag", tag(w)]);
+        features.add(&[name, "word+tag", word(w), tag(w)]);
+    }
+    features.add(&["s2 tag", tag(s2)]);
+    features.add(&["b2 tag", tag(b2)]);
+
+    features.add(&["s0 word+b0 word", word(s0), word(b0)]);
+    features.add(&["s0 tag+b0 tag", tag(s0), tag(b0)]);
+    features.add(&["s0 word+b0 tag", word(s0), tag(b0)]);
+    features.add(&["s0 tag+b0 word", tag(s0), word(b0)]);
+    features.add(&["s0 tag+b0 tag+b1 tag", tag(s0), tag(b0), tag(b1)]);
+    features.add(&["s1 tag+s0 tag+b0 tag", tag(s1), tag(s0), tag(b0)]);
+    features.add(&["b0 tag+b1 tag+b2 tag", tag(b0), tag(b1), tag(b2)]);
+
+    features.add(&["s0 head tag", tag(s0_head)]);
+    features.add(&["s0 tag+s0 left tag", tag(s0), tag(s0_left)]);
+    features.add(&["s0 tag+s0 right tag", tag(s0), tag(s0_right)]);
+    features.add(&["b0 tag+b0 left tag", tag(b0), tag(b0_left)]);
+    features.add(&["s0 tag+s0 left tag+b0 tag", tag(s0), tag(s0_left), tag(b0)]);
+
+    features.add(&["s0 left valency", &valency(s0, &state.lefts)]);
+    features.add(&["s0 right valency", &valency(s0, &state.rights)]);
+    features.add(&["b0 left valency", &valency(b0, &state.lefts)]);
+    features.add(&["distance", &distance]);
+    features.add(&["distance+s0 tag+b0 tag", &distance, tag(s0), tag(b0)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CORPUS: &str = "# text = She saw the dog with a telescope
+1\tShe\t_\tPRON\tPRP\t_\t2\tnsubj\t_\t_
+2\tsaw\t_\tVERB\tVBD\t_\t0\troot\t_\t_
+3\tthe\t_\tDET\tDT\t_\t4\tdet\t_\t_
+4\tdog\t_\tNOUN\tNN\t_\t2\tobj\t_\t_
+5\twith\t_\tADP\tIN\t_\t7\tcase\t_\t_
+6\ta\t_\tDET\tDT\t_\t7\tdet\t_\t_
+7\ttelescope\t_\tNOUN\tNN\t_\t2\tobl\t_\t_
+
+# text = The cat sleeps
+1\tThe\t_\tDET\tDT\t_\t2\tdet\t_\t_
+2\tcat\t_\tNOUN\tNN\t_\t3\tnsubj\t_\t_
+3\tsleeps\t_\tVERB\tVBZ\t_\t0\troot\t_\t_
+
+# text = A dog saw the cat
+1\tA\t_\tDET\tDT\t_\t2\tdet\t_\t_
+2\tdog\t_\tNOUN\tNN\t_\t3\tnsubj\t_\t_
+3\tsaw\t_\tVERB\tVBD\t_\t0\troot\t_\t_
+4\tthe\t_\tDET\tDT\t_\t5\tdet\t_\t_
+5\tcat\t_\tNOUN\tNN\t_\t3\tobj\t_\t_
+";
+
+    #[test]
+    fn parser_training() {
+        let sentences = Conllu::new().read(CORPUS.as_bytes()).unwrap();
+
+        let mut parser = DependencyParser::new();
+        parser.train(&sentences, &mut EpochConfig::new().iterations(15).seed(0));
+
+        let score = parser.evaluate(&sentences).unwrap();
+        assert_eq!(1.0, score.uas());
+        assert_eq!(1.0, score.las());
+
+        let mut bytes = Vec::new();
+        parser.save_to(&mut bytes).unwrap();
+        assert_eq!(parser, DependencyParser::load_from(&bytes[..]).unwrap());
+        assert_eq!(
+            SmolErrorKind::EmptyModel,
+            DependencyParser::new().parse(&[]).err().unwrap().kind()
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! The arc-eager transition system, with the dynamic oracle of
+//! [Goldberg and Nivre (2012)](http://www.aclweb.org/anthology/C12-1059).
+//!
+//! A parse state has a stack, which starts out holding only the root, and a buffer holding every
+//! word of the sentence. Four moves build the tree from left to right:
+//!
+//! * `Shift` pushes the first word of the buffer onto the stack.
+//! * `Reduce` pops the top of the stack, once it has a head.
+//! * `Left` makes the first word of the buffer the head of the top of the stack, and pops it.
+//! * `Right` makes the top of the stack the head of the first word of the buffer, and pushes that
+//!   word onto the stack.
+//!
+//! The oracle tells how many gold arcs each move would make unreachable, so that training can
+//! learn the best move from any state, including ones reached after a mistake.
+
+/// A move of the transition system, with a label for moves which add an arc.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) enum Move {
+    Shift,
+    Reduce,
+    Left(String),
+    Right(String),
+}
+
+impl Move {
+    /// Parses the name of a class, as given by `name`.
+    pub fn from_name(name: &str) -> Option<Move> {
+        match name {
+            "S" => Some(Move::Shift),
+            "D" => Some(Move::Reduce),
+            _ if name.starts_with("L-") => Some(Move::Left(name[2..].to_owned())),
+            _ if name.starts_with("R-") => Some(Move::Right(name[2..].to_owned())),
+            _ => None,
+        }
+    }
+
+    /// The name of the move as a class of the model.
+    pub fn name(&self) -> String {
+        match *self {
+            Move::Shift => "S".to_owned(),
+            Move::Reduce => "D".to_owned(),
+            Move::Left(ref l) => format!("L-{}", l),
+            Move::Right(ref l) => format!("R-{}", l),
+        }
+    }
+}
+
+/// The state of a parse. Words are numbered from 1, with 0 standing for the root.
+pub(crate) struct State {
+    pub stack: Vec<usize>,
+    /// The first word of the buffer.
+    pub next: usize,
+    /// The number of words in the sentence.
+    pub len: usize,
+    pub heads: Vec<Option<usize>>,
+    pub labels: Vec<String>,
+    /// The dependents of every word to its left and right, closest first.
+    pub lefts: Vec<Vec<usize>>,
+    pub rights: Vec<Vec<usize>>,
+}
+
+impl State {
+    pub fn new(len: usize) -> State {
+        State {
+            stack: vec![0],
+            next: 1,
+            len,
+            heads: vec![None; len + 1],
+            labels: vec![String::new(); len + 1],
+            lefts: vec![Vec::new(); len + 1],
+            rights: vec![Vec::new(); len + 1],
+        }
+    }
+
+    /// Whether the buffer is empty, which ends the parse.
+    pub fn is_done(&self) -> bool {
+        self.next > self.len
+    }
+
+    /// The word at `n` from the top of the stack.
+    pub fn stack(&self, n: usize) -> Option<usize> {
+        self.stack.len().checked_sub(n + 1).map(|i| self.stack[i])
+    }
+
+    /// The word at `n` from the front of the buffer.
+    pub fn buffer(&self, n: usize) -> Option<usize> {
+        Some(self.next + n).filter(|&b| b <= self.len)
+    }
+
+    pub fn is_valid(&self, m: &Move) -> bool {
+        let s0 = self.stack(0);
+        let has_head = s0.map(|s| s == 0 || self.heads[s].is_some());
+
+        match *m {
+            Move::Shift => !self.is_done(),
+            Move::Reduce => s0.is_some() && s0 != Some(0) && has_head == Some(true),
+            Move::Left(_) => !self.is_done() && s0.is_some() && has_head == Some(false),
+            Move::Right(_) => !self.is_done() && s0.is_some(),
+        }
+    }
+
+    pub fn apply(&mut self, m: &Move) {
+        match *m {
+            Move::Shift => {
+                self.stack.push(self.next);
+                self.next += 1;
+            }
+            Move::Reduce => {
+                self.stack.pop();
+            }
+            Move::Left(ref l) => {
+                let s0 = self.stack.pop().unwrap();
+                self.attach(self.next, s0, l);
+            }
+            Move::Right(ref l) => {
+                let (s0, b0) = (self.stack(0).unwrap(), self.next);
+                self.attach(s0, b0, l);
+                self.stack.push(b0);
+                self.next += 1;
+            }
+        }
+    }
+
+    fn attach(&mut self, head: usize, child: usize, label: &str) {
+        self.heads[child] = Some(head);
+        self.labels[child] = label.to_owned();
+        if child < head {
+            self.lefts[head].insert(0, child);
+        } else {
+            self.rights[head].insert(0, child);
+        }
+    }
+
+    /// The head and label of every word, attaching words left without a head to the root.
+    pub fn dependencies(&self, root: &str) -> Vec<(usize, String)> {
+        (1..self.len + 1)
+            .map(|w| match self.heads[w] {
+                Some(h) => (h, self.labels[w].clone()),
+                None => (0, root.to_owned()),
+            })
+            .collect()
+    }
+
+    /// The number of gold arcs which can no longer be built after making a move. `heads` and
+    /// `labels` hold the gold head and label of every word, indexed like the state.
+    pub fn cost(&self, m: &Move, heads: &[usize], labels: &[String]) -> usize {
+        let in_buffer = |w: usize| w >= self.next && w <= self.len;
+        let unattached = |w: usize| w != 0 && self.heads[w].is_none();
+        let (s0, b0) = (self.stack(0).unwrap_or(0), self.next);
+
+        // Gold dependents of `w` in the buffer, or without a head on the stack
+        let buffer_deps = |w: usize| (self.next..self.len + 1).filter(|&k| heads[k] == w).count();
+        let stack_deps = |w: usize| {
+            self.stack
+                .iter()
+                .filter(|&&k| unattached(k) && heads[k] == w)
+                .count()
+        };
+
+        // Words left without a head are attached to the root in the end, so a word whose head is
+        // the root only loses it by being attached somewhere else
+        match *m {
+            Move::Shift => {
+                let head = (heads[b0] != 0 && self.stack.contains(&heads[b0])) as usize;
+                head + stack_deps(b0)
+            }
+            Move::Reduce => buffer_deps(s0),
+            Move::Left(ref l) => {
+                let head = if heads[s0] == b0 {
+                    (labels[s0] != *l) as usize
+                } else {
+                    (heads[s0] == 0 || in_buffer(heads[s0])) as usize
+                };
+                head + buffer_deps(s0)
+            }
+            Move::Right(ref l) => {
+                let head = if heads[b0] == s0 {
+                    (labels[b0] != *l) as usize
+                } else {
+                    (in_buffer(heads[b0]) || self.stack.contains(&heads[b0])) as usize
+                };
+                head + stack_deps(b0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oracle_reproduces_gold() {
+        // "She saw the dog with a telescope", with the prepositional phrase on the verb
+        let heads = vec![0, 2, 0, 4, 2, 7, 7, 2];
+        let labels = vec!["", "nsubj", "root", "det", "obj", "case", "det", "obl"]
+            .into_iter()
+            .map(|l| l.to_owned())
+            .collect::<Vec<_>>();
+        let moves = ["S", "D"]
+            .iter()
+            .map(|s| s.to_string())
+            .chain(
+                labels[1..]
+                    .iter()
+                    .flat_map(|l| vec![format!("L-{}", l), format!("R-{}", l)]),
+            )
+            .filter_map(|n| Move::from_name(&n))
+            .collect::<Vec<_>>();
+
+        let mut state = State::new(7);
+        while !state.is_done() {
+            let m = moves
+                .iter()
+                .find(|m| state.is_valid(m) && state.cost(m, &heads, &labels) == 0)
+                .unwrap();
+            state.apply(m);
+        }
+
+        let deps = state.dependencies("root");
+        assert_eq!(heads[1..], deps.iter().map(|d| d.0).collect::<Vec<_>>()[..]);
+        assert_eq!(
+            labels[1..],
+            deps.iter().map(|d| d.1.clone()).collect::<Vec<_>>()[..]
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Beam-search decoding for the perceptron tagger.
+//!
+//! Greedy decoding commits to the tag of every word before moving on to the next, so an early
+//! mistake feeds wrong previous-tag features to the words after it. A beam search instead keeps
+//! the `width` best partial tag sequences at every word, scoring a sequence by the sum of the
+//! scores of its tags.
+//!
+//! Training with a beam uses early updates (Collins and Roark, 2004): as soon as the correct
+//! sequence falls out of the beam, the model is updated towards the correct prefix and away from
+//! the best prefix in the beam, and the rest of the sentence is skipped.
+
+use super::*;
+use error::*;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A partial tag sequence, ending in `tag`.
+struct Node {
+    score: f64,
+    /// The index of the previous node in the previous step.
+    parent: usize,
+    tag: String,
+    /// Whether the sequence so far is correct.
+    gold: bool,
+}
+
+/// The best sequence found by a search.
+enum Outcome {
+    /// The search reached the end of the sentence.
+    Complete(Vec<String>),
+    /// The correct sequence fell out of the beam, leaving only this prefix.
+    Lost(Vec<String>),
+}
+
+/// A beam search over the tags of a sentence.
+pub(crate) struct Beam<'m> {
+    pub model: &'m AveragedPerceptron,
+    pub tags: &'m HashMap<String, String>,
+    /// Words whose tags are forced, taking precedence over the tag dictionary.
+    pub lexicon: Option<&'m HashMap<String, String>>,
+    pub templates: &'m TemplateSet,
+    pub width: usize,
+}
+
+impl<'m> Beam<'m> {
+    /// Finds the best tags for a sentence.
+    pub fn decode(&self, words: &[&str]) -> Result<Vec<String>, SmolError> {
+        match self.search(words, None)? {
+            Outcome::Complete(tags) | Outcome::Lost(tags) => Ok(tags),
+        }
+    }
+
+    /// Searches for the best tags of a sentence. Given the correct tags, the search stops once
+    /// the correct sequence falls out of the beam.
+    fn search(&self, words: &[&str], gold: Option<&[String]>) -> Result<Outcome, SmolError> {
+        let context = PerceptronTagger::context(words);
+        let mut steps: Vec<Vec<Node>> = Vec::with_capacity(words.len());
+        let mut features = Features::new();
+        let mut ids = Vec::new();
+
+        for (i, word) in words.iter().enumerate() {
+            let parents = steps.last().map(|s| s.len()).unwrap_or(1);
+            let dict = self
+                .lexicon
+                .and_then(|l| l.get(*word))
+                .or_else(|| self.tags.get(*word));
+            let mut candidates = Vec::new();
+
+            for parent in 0..parents {
+                let score = steps.last().map(|s| s[parent].score).unwrap_or(0.0);
+                if let Some(tag) = dict {
+                    candidates.push((score, parent, tag.clone()));
+                    continue;
+                }
+
+                let (p1, p2) = history(&steps, parent);
+                extract(self.templates, i, word, &context, p1, p2, &mut features);
+                self.model.lookup(&features, &mut ids);
+                for (c, s) in self.model.scores(&ids).into_iter().enumerate() {
+                    candidates.push((score + s, parent, self.model.classes()[c].clone()));
+                }
+            }
+
+            if candidates.is_empty() {
+                return Err(SmolErrorKind::EmptyModel.into());
+            }
+
+            // Like greedy decoding, ties go to the class which sorts last. The sort is stable, so
+            // any remaining ties go to earlier parents.
+            candidates.sort_by(|a, b| {
+                b.0.partial_cmp(&a.0)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| b.2.cmp(&a.2))
+            });
+            candidates.truncate(self.width.max(1));
+
+            let step = candidates
+                .into_iter()
+                .map(|(score, parent, tag)| {
+                    let parent_gold = steps.last().map(|s| s[parent].gold).unwrap_or(true);
+                    let gold = gold.map(|g| parent_gold && g[i] == tag).unwrap_or(false);
+                    Node {
+                        score,
+                        parent,
+                        tag,
+                        gold,
+                    }
+                })
+                .collect::<Vec<_>>();
+            let lost = gold.is_some() && !step.iter().any(|n| n.gold);
+            steps.push(step);
+
+            if lost {
+                return Ok(Outcome::Lost(backtrack(&steps)));
+            }
+        }
+
+        Ok(Outcome::Complete(backtrack(&steps)))
+    }
+}
+
+/// The tags before the node at `parent` in the last step, or the start markers.
+fn history(steps: &[Vec<Node>], parent: usize) -> (&str, &str) {
+    match steps.len() {
+        0 => ("-START-", "-START2-"),
+        1 => (&steps[0][parent].tag, "-START-"),
+        n => {
+            let node = &steps[n - 1][parent];
+            (&node.tag, &steps[n - 2][node.parent].tag)
+        }
+    }
+}
+
+/// The tags of the best node in the last step and its ancestors.
+fn backtrack(steps: &[Vec<Node>]) -> Vec<String> {
+    let mut res = Vec::with_capacity(steps.len());
+    let mut ix = 0;
+    for step in steps.iter().rev() {
+        res.push(step[ix].tag.clone());
+        ix = step[ix].parent;
+    }
+    res.reverse();
+    res
+}
+
+fn extract(
+    templates: &TemplateSet,
+    i: usize,
+    word: &str,
+    context: &[String],
+    p1: &str,
+    p2: &str,
+    features: &mut Features,
+) {
+    let ctx = FeatureContext {
+        i,
+        word,
+        context,
+        p1,
+        p2,
+        pos: &[],
+    };
+    features.clear();
+    templates.extract(&ctx, features);
+}
+
+/// Trains on a single sentence with a beam, returning the number of wrong guesses and the number
+/// of tokens seen before the search stopped.
+pub(crate) fn train_sentence(
+    model: &mut AveragedPerceptron,
+    tags: &HashMap<String, String>,
+    templates: &TemplateSet,
+    width: usize,
+    sentence: TaggedSentence,
+) -> (usize, usize) {
+    let words = sentence.iter().map(|x| &*x.0).collect::<Vec<_>>();
+    // Words in the tag dictionary are never tagged by the model, so their tags are taken as given
+    let gold = sentence
+        .iter()
+        .map(|(w, t)| tags.get(w).unwrap_or(t).clone())
+        .collect::<Vec<_>>();
+
+    let guess = {
+        let beam = Beam {
+            model,
+            tags,
+            lexicon: None,
+            templates,
+            width,
+        };
+        match beam.search(&words, Some(&gold)).unwrap() {
+            Outcome::Complete(tags) | Outcome::Lost(tags) => tags,
+        }
+    };
+
+    let n = guess.len();
+    let errors = guess
+        .iter()
+        .zip(sentence)
+        .filter(|&(g, t)| *g != t.1)
+        .count();
+
+    // Every token seen counts towards the averaged weights, updated or not
+    model.advance(n);
+    if guess[..] != gold[..n] {
+        let context = PerceptronTagger::context(&words);
+        let mut features = Features::new();
+        let mut ids = Vec::new();
+
+        for (seq, delta) in [(&gold[..n], 1.0), (&guess[..], -1.0)] {
+            for i in (0..n).filter(|&i| !tags.contains_key(words[i])) {
+                let p1 = if i >= 1 { &*seq[i - 1] } else { "-START-" };
+                let p2 = match i {
+                    0 => "-START2-",
+                    1 => "-START-",
+                    _ => &*seq[i - 2],
+                };
+                extract(templates, i, words[i], &context, p1, p2, &mut features);
+                model.intern_all(&features, &mut ids);
+                let class = model.add_class(&seq[i]);
+                model.adjust(class, &ids, delta);
+            }
+        }
+    }
+
+    (errors, n)
+}
+
+/// The accuracy of greedy and beam-search decoding on the same corpus.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct DecoderComparison {
+    pub width: usize,
+    pub greedy: Evaluation,
+    pub beam: Evaluation,
+}
+
+impl DecoderComparison {
+    /// Evaluates a tagger on a held-out corpus, both greedily and with a beam of the given width.
+    pub fn run(
+        evaluator: &Evaluator,
+        tagger: &PerceptronTagger,
+        gold: &[TaggedSentence],
+        width: usize,
+    ) -> Result<DecoderComparison, SmolError> {
+        let tagger = tagger.clone().beam_width(1);
+        let greedy = evaluator.evaluate(&tagger, gold)?;
+        let tagger = tagger.beam_width(width);
+        let beam = evaluator.evaluate(&tagger, gold)?;
+
+        Ok(DecoderComparison {
+            width,
+            greedy,
+            beam,
+        })
+    }
+
+    /// How much more accurate beam search is than greedy decoding.
+    pub fn difference(&self) -> f64 {
+        self.beam.accuracy() - self.greedy.accuracy()
+    }
+}
+
+impl fmt::Display for DecoderComparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "greedy:        {:.4}", self.greedy.accuracy())?;
+        writeln!(
+            f,
+            "beam (width {}): {:.4}",
+            self.width,
+            self.beam.accuracy()
+        )?;
+        write!(f, "difference:    {:+.4}", self.difference())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus() -> Vec<Vec<(String, String)>> {
+        vec![
+            vec![("the", "DT"), ("dog", "NN"), ("runs", "VBZ")],
+            vec![("the", "DT"), ("runs", "NNS"), ("end", "VBP")],
+            vec![("a", "DT"), ("cat", "NN"), ("sleeps", "VBZ")],
+            vec![("cats", "NNS"), ("sleep", "VBP")],
+        ].into_iter()
+            .map(|s| {
+                s.into_iter()
+                    .map(|(w, t)| (w.to_owned(), t.to_owned()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn beam_training() {
+        let sentences = corpus();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+
+        let mut tagger = PerceptronTagger::new().beam_width(4);
+        let mut config = TrainConfig::new().iterations(10).seed(1).beam_width(4);
+        tagger.train_with(corpus.clone(), &mut config);
+
+        let cmp = DecoderComparison::run(&Evaluator::new(), &tagger, &corpus, 4).unwrap();
+        assert_eq!(1.0, cmp.beam.accuracy());
+        assert_eq!(cmp.beam.accuracy() - cmp.greedy.accuracy(), cmp.difference());
+    }
+
+    #[test]
+    fn beam_width_one() {
+        let sentences = corpus();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(corpus.clone(), &mut TrainConfig::new().seed(2));
+
+        let greedy = corpus
+            .iter()
+            .map(|s| {
+                let tagged = tagger.pos(sentence_tokens(s)).unwrap();
+                tagged.into_iter().map(|x| x.1).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let beam = Beam {
+            model: tagger.get_model(),
+            tags: tagger.get_tagdict(),
+            lexicon: None,
+            templates: tagger.get_templates(),
+            width: 1,
+        };
+        for (sentence, greedy) in corpus.iter().zip(greedy) {
+            let words = sentence.iter().map(|x| &*x.0).collect::<Vec<_>>();
+            assert_eq!(greedy, beam.decode(&words).unwrap());
+        }
+
+        // Without any weights every class ties, and both decoders pick the one which sorts last
+        let classes = tagger.get_model().classes().iter().cloned().collect();
+        let untrained = PerceptronTagger::from_parts(
+            AveragedPerceptron::from_weights(HashMap::new(), classes),
+            HashMap::new(),
+            tagger.get_templates().clone(),
+            CorpusInfo::default(),
+        );
+        let beam = Beam {
+            model: untrained.get_model(),
+            tags: untrained.get_tagdict(),
+            ..beam
+        };
+        let tagged = untrained.pos(sentence_tokens(corpus[0])).unwrap();
+        let greedy = tagged.into_iter().map(|x| x.1).collect::<Vec<_>>();
+        assert_eq!(vec!["VBZ"; 3], greedy);
+        assert_eq!(greedy, beam.decode(&["the", "dog", "runs"]).unwrap());
+    }
+}
@@ -1,3 +1,689 @@
 //! Functions for dealing with (tagged) corpuses.
+//!
+//! Besides the `TaggedSentence` type used for training, this module provides readers and writers
+//! for a few common corpus formats, so that tagger output can be handed to other tools and read
+//! back in again:
+//!
+//! * `Conllu`: the [CoNLL-U](http://universaldependencies.org/format.html) format.
+//! * `SlashTagged`: one sentence per line, with every token written as `word/TAG`.
+//! * `JsonLines`: one JSON array of tokens per line, including offsets.
+//! * `Conll2000` and `Conll2003`: the whitespace-separated columns of the CoNLL-2000 chunking
+//!   and CoNLL-2003 named entity shared tasks.
+
+use error::*;
+use tokenize::*;
+
+use failure::ResultExt;
+use serde_json;
+use std::io::prelude::*;
+use std::io::BufRead;
 
 pub type TaggedSentence<'a> = &'a [(String, String)];
+
+/// A token along with its tag and any other annotations it might have.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct AnnotatedToken<'a> {
+    #[serde(flatten)]
+    pub token: Token<'a>,
+    /// The part-of-speech tag of the token.
+    pub tag: String,
+    /// The lemma of the token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lemma: Option<String>,
+    /// The chunk the token belongs to, in IOB notation (e.g. `B-NP`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<String>,
+    /// The named entity the token belongs to, in IOB notation (e.g. `B-PER`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity: Option<String>,
+    /// The position of the token's syntactic head, counting from 1, or 0 for the root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<usize>,
+    /// The dependency relation between the token and its head (e.g. `nsubj`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprel: Option<String>,
+}
+
+impl<'a> AnnotatedToken<'a> {
+    pub fn new(token: Token<'a>, tag: String) -> AnnotatedToken<'a> {
+        AnnotatedToken {
+            token,
+            tag,
+            ..Default::default()
+        }
+    }
+
+    pub fn lemma(mut self, lemma: String) -> AnnotatedToken<'a> {
+        self.lemma = Some(lemma);
+        self
+    }
+
+    pub fn chunk(mut self, chunk: String) -> AnnotatedToken<'a> {
+        self.chunk = Some(chunk);
+        self
+    }
+
+    pub fn entity(mut self, entity: String) -> AnnotatedToken<'a> {
+        self.entity = Some(entity);
+        self
+    }
+
+    /// Sets the head of the token and its relation to it.
+    pub fn dependency(mut self, head: usize, deprel: String) -> AnnotatedToken<'a> {
+        self.head = Some(head);
+        self.deprel = Some(deprel);
+        self
+    }
+
+    /// Converts this token into one which owns its term.
+    pub fn into_owned(self) -> AnnotatedToken<'static> {
+        AnnotatedToken {
+            token: Token {
+                term: self.token.term.into_owned().into(),
+                offset: self.token.offset,
+                index: self.token.index,
+            },
+            tag: self.tag,
+            lemma: self.lemma,
+            chunk: self.chunk,
+            entity: self.entity,
+            head: self.head,
+            deprel: self.deprel,
+        }
+    }
+}
+
+impl<'a> From<(Token<'a>, String)> for AnnotatedToken<'a> {
+    fn from(tagged: (Token<'a>, String)) -> AnnotatedToken<'a> {
+        AnnotatedToken::new(tagged.0, tagged.1)
+    }
+}
+
+pub type AnnotatedSentence<'a> = Vec<AnnotatedToken<'a>>;
+
+/// Turns an annotated sentence into the `(word, tag)` pairs used for training taggers.
+pub fn tagged_pairs(sentence: &[AnnotatedToken]) -> Vec<(String, String)> {
+    sentence
+        .iter()
+        .map(|t| (t.token.term.to_string(), t.tag.clone()))
+        .collect()
+}
+
+/// Anything which can read sentences from a corpus.
+pub trait CorpusReader {
+    /// Reads every sentence from the given reader.
+    fn read<R: BufRead>(&self, reader: R) -> Result<Vec<AnnotatedSentence<'static>>, SmolError>;
+}
+
+/// Anything which can write sentences as a corpus.
+pub trait CorpusWriter {
+    /// Writes a single sentence, including whatever separates it from the next one.
+    fn write_sentence<W: Write>(
+        &self,
+        writer: &mut W,
+        sentence: &[AnnotatedToken],
+    ) -> Result<(), SmolError>;
+
+    /// Writes every sentence in order.
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        sentences: &[AnnotatedSentence],
+    ) -> Result<(), SmolError> {
+        for sentence in sentences {
+            self.write_sentence(writer, sentence)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the output of a `Tagger` as a single sentence.
+    fn write_tagged<W: Write>(
+        &self,
+        writer: &mut W,
+        sentence: &[(Token, String)],
+    ) -> Result<(), SmolError> {
+        let s = sentence
+            .iter()
+            .cloned()
+            .map(AnnotatedToken::from)
+            .collect::<Vec<_>>();
+        self.write_sentence(writer, &s)
+    }
+}
+
+/// Which CoNLL-U column holds the tag of a token.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TagColumn {
+    Upos,
+    Xpos,
+}
+
+/// Reads and writes the CoNLL-U format.
+///
+/// Chunks, entities and token offsets are stored in the MISC column as `Chunk=`, `Entity=` and
+/// `Offset=` respectively.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Conllu {
+    column: TagColumn,
+}
+
+impl Default for Conllu {
+    fn default() -> Conllu {
+        Conllu {
+            column: TagColumn::Xpos,
+        }
+    }
+}
+
+impl Conllu {
+    pub fn new() -> Conllu {
+        Conllu::default()
+    }
+
+    /// Sets the column the tag is read from and written to. Defaults to XPOS.
+    pub fn column(mut self, column: TagColumn) -> Conllu {
+        self.column = column;
+        self
+    }
+
+    fn field(s: &str) -> Option<String> {
+        if s == "_" {
+            None
+        } else {
+            Some(s.to_owned())
+        }
+    }
+
+    fn parse_line(&self, line: &str, index: usize) -> Result<AnnotatedToken<'static>, SmolError> {
+        let cols = line.split('\t').collect::<Vec<_>>();
+        if cols.len() != 10 {
+            return Err(SmolErrorKind::Parse.into());
+        }
+
+        let (upos, xpos) = (Self::field(cols[3]), Self::field(cols[4]));
+        let tag = match self.column {
+            TagColumn::Upos => upos.or(xpos),
+            TagColumn::Xpos => xpos.or(upos),
+        };
+
+        let mut res = AnnotatedToken::new(
+            Token {
+                term: cols[1].to_owned().into(),
+                offset: 0,
+                index,
+            },
+            tag.unwrap_or_default(),
+        );
+        res.lemma = Self::field(cols[2]);
+        res.head = match Self::field(cols[6]) {
+            Some(h) => Some(h.parse::<usize>().context(SmolErrorKind::Parse)?),
+            None => None,
+        };
+        res.deprel = Self::field(cols[7]);
+
+        for attr in cols[9].split('|') {
+            let mut kv = attr.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("Chunk"), Some(v)) => res.chunk = Some(v.to_owned()),
+                (Some("Entity"), Some(v)) => res.entity = Some(v.to_owned()),
+                (Some("Offset"), Some(v)) => {
+                    res.token.offset = v.parse::<usize>().context(SmolErrorKind::Parse)?
+                }
+                _ => {}
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+impl CorpusReader for Conllu {
+    fn read<R: BufRead>(&self, reader: R) -> Result<Vec<AnnotatedSentence<'static>>, SmolError> {
+        let mut res = Vec::new();
+        let mut sentence = Vec::new();
+
+        for line in reader.lines() {
+            let line = line.context(SmolErrorKind::Read)?;
+            let line = line.trim_end_matches('\r');
+
+            if line.trim().is_empty() {
+                if !sentence.is_empty() {
+                    res.push(sentence);
+                    sentence = Vec::new();
+                }
+                continue;
+            }
+
+            if line.starts_with('#') {
+                continue;
+            }
+
+            // Multiword tokens (`1-2`) and empty nodes (`1.1`) don't correspond to a token.
+            let id = line.split('\t').next().unwrap_or("");
+            if id.contains('-') || id.contains('.') {
+                continue;
+            }
+
+            let index = sentence.len();
+            sentence.push(self.parse_line(line, index)?);
+        }
+
+        if !sentence.is_empty() {
+            res.push(sentence);
+        }
+
+        Ok(res)
+    }
+}
+
+impl CorpusWriter for Conllu {
+    fn write_sentence<W: Write>(
+        &self,
+        writer: &mut W,
+        sentence: &[AnnotatedToken],
+    ) -> Result<(), SmolError> {
+        for (i, t) in sentence.iter().enumerate() {
+            let (upos, xpos) = match self.column {
+                TagColumn::Upos => (&*t.tag, "_"),
+                TagColumn::Xpos => ("_", &*t.tag),
+            };
+
+            let mut misc = Vec::new();
+            if let Some(ref c) = t.chunk {
+                misc.push(format!("Chunk={}", c));
+            }
+            if let Some(ref e) = t.entity {
+                misc.push(format!("Entity={}", e));
+            }
+            misc.push(format!("Offset={}", t.token.offset));
+
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t_\t{}\t{}\t_\t{}",
+                i + 1,
+                t.token.term,
+                t.lemma.as_deref().unwrap_or("_"),
+                upos,
+                xpos,
+                t.head.map(|h| h.to_string()).as_deref().unwrap_or("_"),
+                t.deprel.as_deref().unwrap_or("_"),
+                misc.join("|")
+            )
+            .context(SmolErrorKind::Write)?;
+        }
+
+        writeln!(writer).context(SmolErrorKind::Write)?;
+
+        Ok(())
+    }
+}
+
+/// Reads and writes sentences as `word/TAG` pairs separated by spaces, one sentence per line.
+///
+/// Only the term and the tag of a token are kept. When reading, offsets are counted in
+/// characters from the start of the line, as though the words were separated by single spaces.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SlashTagged;
+
+impl CorpusReader for SlashTagged {
+    fn read<R: BufRead>(&self, reader: R) -> Result<Vec<AnnotatedSentence<'static>>, SmolError> {
+        let mut res = Vec::new();
+
+        for line in reader.lines() {
+            let line = line.context(SmolErrorKind::Read)?;
+            let mut sentence = Vec::new();
+            let mut offset = 0;
+
+            for (index, pair) in line.split_whitespace().enumerate() {
+                // Words may contain slashes themselves (e.g. `1/2/CD`), but tags never do.
+                let ix = pair.rfind('/').ok_or(SmolErrorKind::Parse)?;
+                let (word, tag) = (&pair[..ix], &pair[ix + 1..]);
+
+                sentence.push(AnnotatedToken::new(
+                    Token {
+                        term: word.to_owned().into(),
+                        offset,
+                        index,
+                    },
+                    tag.to_owned(),
+                ));
+                offset += word.chars().count() + 1;
+            }
+
+            if !sentence.is_empty() {
+                res.push(sentence);
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+impl CorpusWriter for SlashTagged {
+    fn write_sentence<W: Write>(
+        &self,
+        writer: &mut W,
+        sentence: &[AnnotatedToken],
+    ) -> Result<(), SmolError> {
+        let line = sentence
+            .iter()
+            .map(|t| format!("{}/{}", t.token.term, t.tag))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        writeln!(writer, "{}", line).context(SmolErrorKind::Write)?;
+
+        Ok(())
+    }
+}
+
+/// Reads and writes sentences as JSON arrays of tokens, one sentence per line.
+///
+/// Every annotation of a token is kept, including its offset and index.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct JsonLines;
+
+impl CorpusReader for JsonLines {
+    fn read<R: BufRead>(&self, reader: R) -> Result<Vec<AnnotatedSentence<'static>>, SmolError> {
+        let mut res = Vec::new();
+
+        for line in reader.lines() {
+            let line = line.context(SmolErrorKind::Read)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let sentence: Vec<AnnotatedToken<'static>> =
+                serde_json::from_str(&line).context(SmolErrorKind::Deserialize)?;
+            res.push(sentence);
+        }
+
+        Ok(res)
+    }
+}
+
+impl CorpusWriter for JsonLines {
+    fn write_sentence<W: Write>(
+        &self,
+        writer: &mut W,
+        sentence: &[AnnotatedToken],
+    ) -> Result<(), SmolError> {
+        serde_json::to_writer(&mut *writer, sentence).context(SmolErrorKind::Serialize)?;
+        writeln!(writer).context(SmolErrorKind::Write)?;
+
+        Ok(())
+    }
+}
+
+/// Reads sentences of whitespace-separated columns, one token per line with blank lines between
+/// sentences, turning the columns of every token into an `AnnotatedToken` with `token`.
+fn read_columns<R, F>(reader: R, mut token: F) -> Result<Vec<AnnotatedSentence<'static>>, SmolError>
+where
+    R: BufRead,
+    F: FnMut(&[&str], Token<'static>) -> Result<AnnotatedToken<'static>, SmolError>,
+{
+    let mut res = Vec::new();
+    let mut sentence = Vec::new();
+    let mut offset = 0;
+
+    for line in reader.lines() {
+        let line = line.context(SmolErrorKind::Read)?;
+        let cols = line.split_whitespace().collect::<Vec<_>>();
+
+        if cols.is_empty() || cols[0] == "-DOCSTART-" {
+            if !sentence.is_empty() {
+                res.push(sentence);
+                sentence = Vec::new();
+                offset = 0;
+            }
+            continue;
+        }
+
+        let t = Token {
+            term: cols[0].to_owned().into(),
+            offset,
+            index: sentence.len(),
+        };
+        sentence.push(token(&cols, t)?);
+        offset += cols[0].chars().count() + 1;
+    }
+
+    if !sentence.is_empty() {
+        res.push(sentence);
+    }
+
+    Ok(res)
+}
+
+/// Reads a tag column, where `_` stands for an empty tag.
+fn read_tag(s: &str) -> String {
+    if s == "_" {
+        String::new()
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Writes a tag column, writing an empty tag as `_` so that the column isn't lost.
+fn write_tag(tag: &str) -> &str {
+    if tag.is_empty() {
+        "_"
+    } else {
+        tag
+    }
+}
+
+/// Reads a chunk or entity column, where `O` marks a token outside of any chunk or entity.
+fn read_label(s: &str) -> Option<String> {
+    if s == "O" {
+        None
+    } else {
+        Some(s.to_owned())
+    }
+}
+
+/// Reads and writes the format of the CoNLL-2003 named entity data: one token per line with the
+/// word, tag, chunk and entity separated by spaces, and blank lines between sentences.
+///
+/// `-DOCSTART-` lines are skipped. Lines with only two columns are read as a word and an entity,
+/// and lines with three as a word, tag and entity. Empty tags are written as `_`, which is read
+/// back as an empty tag, and tokens without a chunk or entity are written as `O`, which is read
+/// back as `None`. Offsets are counted like `SlashTagged`'s, as though the words of a sentence
+/// were separated by single spaces.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Conll2003;
+
+impl CorpusReader for Conll2003 {
+    fn read<R: BufRead>(&self, reader: R) -> Result<Vec<AnnotatedSentence<'static>>, SmolError> {
+        read_columns(reader, |cols, token| {
+            let (tag, chunk) = match cols.len() {
+                1 => return Err(SmolErrorKind::Parse.into()),
+                2 => ("_", "O"),
+                3 => (cols[1], "O"),
+                _ => (cols[1], cols[2]),
+            };
+
+            let mut res = AnnotatedToken::new(token, read_tag(tag));
+            res.chunk = read_label(chunk);
+            res.entity = read_label(cols[cols.len() - 1]);
+            Ok(res)
+        })
+    }
+}
+
+impl CorpusWriter for Conll2003 {
+    fn write_sentence<W: Write>(
+        &self,
+        writer: &mut W,
+        sentence: &[AnnotatedToken],
+    ) -> Result<(), SmolError> {
+        for t in sentence {
+            writeln!(
+                writer,
+                "{} {} {} {}",
+                t.token.term,
+                write_tag(&t.tag),
+                t.chunk.as_deref().unwrap_or("O"),
+                t.entity.as_deref().unwrap_or("O")
+            )
+            .context(SmolErrorKind::Write)?;
+        }
+
+        writeln!(writer).context(SmolErrorKind::Write)?;
+
+        Ok(())
+    }
+}
+
+/// Reads and writes the format of the CoNLL-2000 chunking data: one token per line with the
+/// word, tag and chunk separated by spaces, and blank lines between sentences.
+///
+/// Like `Conll2003`, empty tags are written as `_` and tokens without a chunk as `O`, and both are
+/// read back the way they were. Offsets are counted like `SlashTagged`'s, as though the words of a
+/// sentence were separated by single spaces.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Conll2000;
+
+impl CorpusReader for Conll2000 {
+    fn read<R: BufRead>(&self, reader: R) -> Result<Vec<AnnotatedSentence<'static>>, SmolError> {
+        read_columns(reader, |cols, token| {
+            if cols.len() != 3 {
+                return Err(SmolErrorKind::Parse.into());
+            }
+            let mut res = AnnotatedToken::new(token, read_tag(cols[1]));
+            res.chunk = read_label(cols[2]);
+            Ok(res)
+        })
+    }
+}
+
+impl CorpusWriter for Conll2000 {
+    fn write_sentence<W: Write>(
+        &self,
+        writer: &mut W,
+        sentence: &[AnnotatedToken],
+    ) -> Result<(), SmolError> {
+        for t in sentence {
+            writeln!(
+                writer,
+                "{} {} {}",
+                t.token.term,
+                write_tag(&t.tag),
+                t.chunk.as_deref().unwrap_or("O")
+            )
+            .context(SmolErrorKind::Write)?;
+        }
+
+        writeln!(writer).context(SmolErrorKind::Write)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence() -> AnnotatedSentence<'static> {
+        vec![
+            AnnotatedToken::new(
+                Token {
+                    term: "Alice".into(),
+                    offset: 0,
+                    index: 0,
+                },
+                "NNP".to_owned(),
+            )
+            .entity("B-PER".to_owned())
+            .dependency(2, "nsubj".to_owned()),
+            AnnotatedToken::new(
+                Token {
+                    term: "sleeps".into(),
+                    offset: 6,
+                    index: 1,
+                },
+                "VBZ".to_owned(),
+            )
+            .lemma("sleep".to_owned())
+            .chunk("B-VP".to_owned())
+            .dependency(0, "root".to_owned()),
+        ]
+    }
+
+    fn round_trip<F: CorpusReader + CorpusWriter>(format: F) -> Vec<AnnotatedSentence<'static>> {
+        let sentences = vec![sentence(), sentence()];
+        let mut buf = Vec::new();
+        format.write(&mut buf, &sentences).unwrap();
+        format.read(&buf[..]).unwrap()
+    }
+
+    #[test]
+    fn corpus_round_trip() {
+        assert_eq!(vec![sentence(), sentence()], round_trip(Conllu::new()));
+        assert_eq!(vec![sentence(), sentence()], round_trip(JsonLines));
+
+        let slash = round_trip(SlashTagged);
+        assert_eq!(2, slash.len());
+        assert_eq!(tagged_pairs(&sentence()), tagged_pairs(&slash[0]));
+        assert_eq!(sentence()[1].token, slash[1][1].token);
+
+        let conll = round_trip(Conll2003);
+        assert_eq!(tagged_pairs(&sentence()), tagged_pairs(&conll[1]));
+        assert_eq!(Some("B-PER".to_owned()), conll[0][0].entity);
+        assert_eq!(None, conll[0][0].chunk);
+        assert_eq!(Some("B-VP".to_owned()), conll[0][1].chunk);
+        assert_eq!(None, conll[0][1].entity);
+
+        let conll = round_trip(Conll2000);
+        assert_eq!(tagged_pairs(&sentence()), tagged_pairs(&conll[1]));
+        assert_eq!(None, conll[0][0].chunk);
+        assert_eq!(Some("B-VP".to_owned()), conll[0][1].chunk);
+    }
+
+    #[test]
+    fn conll_empty_tags() {
+        let mut untagged = sentence();
+        untagged[0].tag.clear();
+
+        let mut buf = Vec::new();
+        Conll2003.write(&mut buf, &[untagged.clone()]).unwrap();
+        let conll = Conll2003.read(&buf[..]).unwrap();
+        assert_eq!(tagged_pairs(&untagged), tagged_pairs(&conll[0]));
+
+        let mut buf = Vec::new();
+        Conll2000.write(&mut buf, &[untagged.clone()]).unwrap();
+        let conll = Conll2000.read(&buf[..]).unwrap();
+        assert_eq!(tagged_pairs(&untagged), tagged_pairs(&conll[0]));
+        assert_eq!(untagged[1].chunk, conll[0][1].chunk);
+    }
+
+    #[test]
+    fn conllu_skips_comments_and_ranges() {
+        let input = "# text = Don't go\n\
+                     1-2\tDon't\t_\t_\t_\t_\t_\t_\t_\t_\n\
+                     1\tDo\tdo\tAUX\tVBP\t_\t3\taux\t_\t_\n\
+                     2\tn't\tnot\tPART\tRB\t_\t3\tadvmod\t_\t_\n\
+                     3\tgo\tgo\tVERB\tVB\t_\t0\troot\t_\t_\n";
+
+        let xpos = Conllu::new().read(input.as_bytes()).unwrap();
+        let upos = Conllu::new()
+            .column(TagColumn::Upos)
+            .read(input.as_bytes())
+            .unwrap();
+
+        assert_eq!(1, xpos.len());
+        assert_eq!(
+            vec![
+                ("Do".to_owned(), "VBP".to_owned()),
+                ("n't".to_owned(), "RB".to_owned()),
+                ("go".to_owned(), "VB".to_owned()),
+            ],
+            tagged_pairs(&xpos[0])
+        );
+        assert_eq!("PART", upos[0][1].tag);
+        assert_eq!(Some("not".to_owned()), upos[0][1].lemma);
+        assert_eq!(Some(3), upos[0][1].head);
+        assert_eq!(Some("root".to_owned()), upos[0][2].deprel);
+    }
+}
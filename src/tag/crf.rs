@@ -0,0 +1,598 @@
+//! A linear-chain conditional random field.
+//!
+//! A CRF scores a whole tag sequence at once: every word contributes the weights of its features
+//! for its tag, and every pair of neighbouring tags contributes a transition weight. Unlike the
+//! perceptron tagger, the features of a word never look at previous tags, so the best sequence can
+//! be found exactly with the Viterbi algorithm.
+//!
+//! Training maximizes the conditional log-likelihood of the training sentences with stochastic
+//! gradient descent, optionally with L1 and L2 regularization. Regularization is applied lazily,
+//! only to the features of the sentence at hand, with the cumulative L1 penalty of
+//! [Tsuruoka et al. (2009)](http://www.aclweb.org/anthology/P09-1054) so that L1 leaves
+//! irrelevant weights at exactly zero.
+
+use super::format::{decode, encode, now, read_all};
+use super::split::seeded_rng;
+use super::*;
+use error::*;
+
+use bincode::deserialize;
+use failure::ResultExt;
+use rand::{thread_rng, Rng};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
+
+/// Settings for training a `CrfTagger`.
+///
+/// # Examples
+/// ```rust
+/// # use smol::tag::*;
+/// let config = CrfConfig::new()
+///     .iterations(20)
+///     .learning_rate(0.05)
+///     .l1(0.5)
+///     .seed(42);
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub struct CrfConfig {
+    iterations: usize,
+    learning_rate: f64,
+    l1: f64,
+    l2: f64,
+    seed: Option<u64>,
+}
+
+impl Default for CrfConfig {
+    fn default() -> CrfConfig {
+        CrfConfig {
+            iterations: 10,
+            learning_rate: 0.1,
+            l1: 0.0,
+            l2: 0.1,
+            seed: None,
+        }
+    }
+}
+
+impl CrfConfig {
+    pub fn new() -> CrfConfig {
+        CrfConfig::default()
+    }
+
+    /// Sets the number of passes over the training sentences. Defaults to 10.
+    pub fn iterations(mut self, iterations: usize) -> CrfConfig {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn get_iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// Sets the initial learning rate, which decays as training goes on. Defaults to 0.1.
+    pub fn learning_rate(mut self, rate: f64) -> CrfConfig {
+        self.learning_rate = rate;
+        self
+    }
+
+    pub fn get_learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    /// Sets the strength of L1 regularization, which drives unhelpful weights to zero. Defaults to
+    /// 0.
+    pub fn l1(mut self, l1: f64) -> CrfConfig {
+        self.l1 = l1;
+        self
+    }
+
+    pub fn get_l1(&self) -> f64 {
+        self.l1
+    }
+
+    /// Sets the strength of L2 regularization, which keeps weights small. Defaults to 0.1.
+    pub fn l2(mut self, l2: f64) -> CrfConfig {
+        self.l2 = l2;
+        self
+    }
+
+    pub fn get_l2(&self) -> f64 {
+        self.l2
+    }
+
+    /// Sets the seed used to shuffle the training sentences between iterations.
+    pub fn seed(mut self, seed: u64) -> CrfConfig {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+}
+
+/// A sequence labeler based on a linear-chain CRF.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct CrfTagger {
+    /// Every label, sorted.
+    labels: Vec<String>,
+    /// The row of every feature in the weight table.
+    features: HashMap<String, usize>,
+    /// One row of `labels.len()` weights per feature.
+    weights: Vec<f64>,
+    /// The weight of every pair of labels, in a square table with one more row and column than
+    /// there are labels. The last row stands for the start of a sentence, and the last column for
+    /// the end of one.
+    transitions: Vec<f64>,
+    templates: TemplateSet,
+    corpus: CorpusInfo,
+}
+
+impl Default for CrfTagger {
+    fn default() -> CrfTagger {
+        CrfTagger {
+            labels: Vec::new(),
+            features: HashMap::new(),
+            weights: Vec::new(),
+            transitions: Vec::new(),
+            templates: TemplateSet::default().without_tags(),
+            corpus: CorpusInfo::default(),
+        }
+    }
+}
+
+/// The features and labels of a training sentence.
+struct Instance {
+    features: Vec<Vec<usize>>,
+    labels: Vec<usize>,
+}
+
+/// The lazily applied regularization penalties of stochastic gradient descent.
+struct Penalty {
+    /// The sum of the logs of every L2 scaling factor so far.
+    log_scale: f64,
+    /// The total L1 penalty every weight could have received so far.
+    total: f64,
+    /// The value of `log_scale` when each feature was last regularized.
+    seen: Vec<f64>,
+    /// The L1 penalty each weight actually received, laid out like the weights.
+    applied: Vec<f64>,
+}
+
+impl Penalty {
+    /// Applies every penalty a feature has missed since it was last regularized.
+    fn catch_up(&mut self, weights: &mut [f64], feature: usize, n: usize) {
+        let scale = (self.log_scale - self.seen[feature]).exp();
+        self.seen[feature] = self.log_scale;
+
+        let row = feature * n..(feature + 1) * n;
+        for (weight, applied) in weights[row.clone()].iter_mut().zip(&mut self.applied[row]) {
+            let w = *weight * scale;
+            let clipped = if w > 0.0 {
+                (w - (self.total + *applied)).max(0.0)
+            } else if w < 0.0 {
+                (w + (self.total - *applied)).min(0.0)
+            } else {
+                w
+            };
+            *applied += clipped - w;
+            *weight = clipped;
+        }
+    }
+}
+
+impl CrfTagger {
+    pub fn new() -> CrfTagger {
+        CrfTagger::default()
+    }
+
+    /// Sets the features used by the model. Templates which look at previous tags are dropped,
+    /// since transitions between tags are part of the model itself. Defaults to the perceptron's
+    /// default features without the tag features.
+    pub fn templates(mut self, templates: TemplateSet) -> CrfTagger {
+        self.templates = templates.without_tags();
+        self
+    }
+
+    pub fn get_templates(&self) -> &TemplateSet {
+        &self.templates
+    }
+
+    /// Every label the model can output, sorted.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The number of distinct features seen while training.
+    pub fn num_features(&self) -> usize {
+        self.features.len()
+    }
+
+    /// The number of feature weights which aren't zero.
+    pub fn num_nonzero(&self) -> usize {
+        self.weights.iter().filter(|w| **w != 0.0).count()
+    }
+
+    pub fn get_corpus(&self) -> &CorpusInfo {
+        &self.corpus
+    }
+
+    /// Trains the model from scratch on tagged sentences.
+    pub fn train(&mut self, sentences: &[TaggedSentence], config: &CrfConfig) {
+        match config.get_seed() {
+            Some(seed) => self.train_with_rng(sentences, config, &mut seeded_rng(seed)),
+            None => self.train_with_rng(sentences, config, &mut thread_rng()),
+        }
+    }
+
+    /// Trains the model from scratch, shuffling the sentences between iterations with `rng`. The
+    /// seed of the configuration is ignored.
+    pub fn train_with_rng<R: Rng>(
+        &mut self,
+        sentences: &[TaggedSentence],
+        config: &CrfConfig,
+        rng: &mut R,
+    ) {
+        self.labels = sentences
+            .iter()
+            .flat_map(|s| s.iter().map(|x| x.1.clone()))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        self.features.clear();
+        self.corpus.sentences = sentences.len();
+        self.corpus.tokens = sentences.iter().map(|s| s.len()).sum();
+
+        let mut instances = sentences
+            .iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| self.instance(s))
+            .collect::<Vec<_>>();
+
+        let n = self.labels.len();
+        self.weights = vec![0.0; self.features.len() * n];
+        self.transitions = vec![0.0; (n + 1) * (n + 1)];
+
+        let mut penalty = Penalty {
+            log_scale: 0.0,
+            total: 0.0,
+            seen: vec![0.0; self.features.len()],
+            applied: vec![0.0; self.weights.len()],
+        };
+        let count = instances.len().max(1) as f64;
+        let mut step = 0;
+
+        for _ in 0..config.get_iterations() {
+            for instance in &instances {
+                let rate = config.get_learning_rate() / (1.0 + step as f64 / count);
+                step += 1;
+
+                for ids in &instance.features {
+                    for &f in ids {
+                        penalty.catch_up(&mut self.weights, f, n);
+                    }
+                }
+
+                self.gradient_step(instance, rate);
+
+                // Penalties for this step, applied to each feature when it's next used
+                let l2 = (1.0 - rate * config.get_l2() / count).max(1e-6);
+                let l1 = rate * config.get_l1() / count;
+                penalty.log_scale += l2.ln();
+                penalty.total += l1;
+                for w in &mut self.transitions {
+                    *w *= l2;
+                    *w = w.signum() * (w.abs() - l1).max(0.0);
+                }
+            }
+
+            rng.shuffle(&mut instances);
+        }
+
+        for f in 0..self.features.len() {
+            penalty.catch_up(&mut self.weights, f, n);
+        }
+    }
+
+    /// Extracts the features of a training sentence, interning new ones.
+    fn instance(&mut self, sentence: &[(String, String)]) -> Instance {
+        let words = sentence.iter().map(|x| &*x.0).collect::<Vec<_>>();
+        let context = PerceptronTagger::context(&words);
+        let mut features = Features::new();
+
+        let ids = (0..words.len())
+            .map(|i| {
+                self.extract(i, words[i], &context, &mut features);
+                features
+                    .iter()
+                    .map(|f| {
+                        let next = self.features.len();
+                        *self.features.entry(f.to_owned()).or_insert(next)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let labels = sentence
+            .iter()
+            .map(|x| self.labels.binary_search(&x.1).unwrap())
+            .collect();
+
+        Instance {
+            features: ids,
+            labels,
+        }
+    }
+
+    fn extract(&self, i: usize, word: &str, context: &[String], features: &mut Features) {
+        let ctx = FeatureContext {
+            i,
+            word,
+            context,
+            p1: "",
+            p2: "",
+            pos: &[],
+        };
+        features.clear();
+        self.templates.extract(&ctx, features);
+    }
+
+    /// The score of every label for every word, given the features of every word.
+    fn emissions(&self, features: &[Vec<usize>]) -> Vec<Vec<f64>> {
+        let n = self.labels.len();
+        features
+            .iter()
+            .map(|ids| {
+                let mut scores = vec![0.0; n];
+                for &f in ids {
+                    for (s, w) in scores.iter_mut().zip(&self.weights[f * n..(f + 1) * n]) {
+                        *s += w;
+                    }
+                }
+                scores
+            })
+            .collect()
+    }
+
+    fn transition(&self, prev: usize, next: usize) -> f64 {
+        self.transitions[prev * (self.labels.len() + 1) + next]
+    }
+
+    /// Moves the weights along the gradient of the log-likelihood of a single sentence, which is
+    /// the difference between the observed and expected feature counts.
+    fn gradient_step(&mut self, instance: &Instance, rate: f64) {
+        let n = self.labels.len();
+        let len = instance.labels.len();
+        let emissions = self.emissions(&instance.features);
+
+        // Forward and backward scores, in log space
+        let mut alpha = vec![vec![0.0; n]; len];
+        for y in 0..n {
+            alpha[0][y] = self.transition(n, y) + emissions[0][y];
+        }
+        for i in 1..len {
+            for y in 0..n {
+                alpha[i][y] = log_sum_exp((0..n).map(|p| alpha[i - 1][p] + self.transition(p, y)))
+                    + emissions[i][y];
+            }
+        }
+
+        let mut beta = vec![vec![0.0; n]; len];
+        beta[len - 1] = (0..n).map(|y| self.transition(y, n)).collect();
+        for i in (0..len - 1).rev() {
+            for y in 0..n {
+                beta[i][y] = log_sum_exp(
+                    (0..n).map(|s| self.transition(y, s) + emissions[i + 1][s] + beta[i + 1][s]),
+                );
+            }
+        }
+
+        let log_z = log_sum_exp((0..n).map(|y| alpha[len - 1][y] + beta[len - 1][y]));
+
+        let mut transitions = vec![0.0; self.transitions.len()];
+        for i in 0..len {
+            let gold = instance.labels[i];
+            for y in 0..n {
+                let expected = (alpha[i][y] + beta[i][y] - log_z).exp();
+                let observed = if y == gold { 1.0 } else { 0.0 };
+                for &f in &instance.features[i] {
+                    self.weights[f * n + y] += rate * (observed - expected);
+                }
+
+                if i == 0 {
+                    transitions[n * (n + 1) + y] -= expected;
+                }
+                if i == len - 1 {
+                    transitions[y * (n + 1) + n] -= expected;
+                }
+                if i > 0 {
+                    for p in 0..n {
+                        transitions[p * (n + 1) + y] -= (alpha[i - 1][p]
+                            + self.transition(p, y)
+                            + emissions[i][y]
+                            + beta[i][y]
+                            - log_z)
+                            .exp();
+                    }
+                }
+            }
+
+            let prev = if i == 0 { n } else { instance.labels[i - 1] };
+            transitions[prev * (n + 1) + gold] += 1.0;
+        }
+        transitions[instance.labels[len - 1] * (n + 1) + n] += 1.0;
+
+        for (w, g) in self.transitions.iter_mut().zip(transitions) {
+            *w += rate * g;
+        }
+    }
+
+    /// Finds the best labels for a sentence.
+    pub fn viterbi(&self, words: &[&str]) -> Result<Vec<String>, SmolError> {
+        let n = self.labels.len();
+        if n == 0 {
+            return Err(SmolErrorKind::EmptyModel.into());
+        }
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let context = PerceptronTagger::context(words);
+        let mut features = Features::new();
+        let ids = (0..words.len())
+            .map(|i| {
+                self.extract(i, words[i], &context, &mut features);
+                features
+                    .iter()
+                    .filter_map(|f| self.features.get(f).cloned())
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        let emissions = self.emissions(&ids);
+
+        let mut scores: Vec<Vec<f64>> = Vec::with_capacity(words.len());
+        let mut backpointers = vec![vec![n; n]; words.len()];
+        for (i, emission) in emissions.iter().enumerate() {
+            let column = (0..n)
+                .map(|y| {
+                    let (best, score) = if i == 0 {
+                        (n, self.transition(n, y))
+                    } else {
+                        best((0..n).map(|p| (p, scores[i - 1][p] + self.transition(p, y))))
+                    };
+                    backpointers[i][y] = best;
+                    score + emission[y]
+                })
+                .collect();
+            scores.push(column);
+        }
+
+        let last = words.len() - 1;
+        let mut y = best((0..n).map(|y| (y, scores[last][y] + self.transition(y, n)))).0;
+        let mut res = vec![String::new(); words.len()];
+        for i in (0..words.len()).rev() {
+            res[i] = self.labels[y].clone();
+            y = backpointers[i][y];
+        }
+
+        Ok(res)
+    }
+
+    /// Describes the model, as it would be saved right now.
+    pub fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            templates: self.templates.clone(),
+            tagset: self.labels.clone(),
+            corpus: self.corpus.clone(),
+            date: now(),
+        }
+    }
+}
+
+impl Tagger for CrfTagger {
+    type Tag = String;
+
+    fn tag<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        tokens: I,
+    ) -> Result<Vec<(Token<'a>, Self::Tag)>, SmolError> {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tags = self.viterbi(&tokens.iter().map(|t| &*t.term).collect::<Vec<_>>())?;
+        Ok(tokens.into_iter().zip(tags).collect())
+    }
+}
+
+impl Persist for CrfTagger {
+    fn save_to<W: Write>(&self, mut writer: W) -> Result<(), SmolError> {
+        let s = encode(&(self.metadata(), self))?;
+        writer.write_all(&s).context(SmolErrorKind::Write)?;
+        Ok(())
+    }
+
+    fn load_from<R: Read>(reader: R) -> Result<CrfTagger, SmolError> {
+        let bytes = read_all(reader)?;
+        let body = decode(&bytes)?.ok_or(SmolErrorKind::Deserialize)?;
+        let (_, tagger): (ModelMetadata, CrfTagger) =
+            deserialize(body).context(SmolErrorKind::Deserialize)?;
+        Ok(tagger)
+    }
+}
+
+/// The highest scoring item, preferring earlier ones on ties.
+fn best<I: Iterator<Item = (usize, f64)>>(items: I) -> (usize, f64) {
+    items.fold((0, f64::NEG_INFINITY), |a, b| if b.1 > a.1 { b } else { a })
+}
+
+fn log_sum_exp<I: Iterator<Item = f64>>(xs: I) -> f64 {
+    let xs = xs.collect::<Vec<_>>();
+    let max = xs.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + xs.iter().map(|x| (x - max).exp()).sum::<f64>().ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus() -> Vec<Vec<(String, String)>> {
+        vec![
+            vec![("John", "B-PER"), ("Smith", "I-PER"), ("runs", "O")],
+            vec![("Mary", "B-PER"), ("visited", "O"), ("Paris", "B-LOC")],
+            vec![
+                ("the", "O"),
+                ("New", "B-LOC"),
+                ("York", "I-LOC"),
+                ("office", "O"),
+            ],
+            vec![("Smith", "B-PER"), ("left", "O")],
+        ]
+        .into_iter()
+        .map(|s| {
+            s.into_iter()
+                .map(|(w, t)| (w.to_owned(), t.to_owned()))
+                .collect()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn crf_training() {
+        let sentences = corpus();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+
+        let mut crf = CrfTagger::new();
+        crf.train(&corpus, &CrfConfig::new().iterations(30).seed(0));
+        assert!(crf
+            .get_templates()
+            .templates()
+            .iter()
+            .all(|t| !t.uses_tags()));
+
+        let e = Evaluator::new().evaluate(&crf, &corpus).unwrap();
+        assert_eq!(1.0, e.accuracy());
+
+        let mut bytes = Vec::new();
+        crf.save_to(&mut bytes).unwrap();
+        assert_eq!(crf, CrfTagger::load_from(&bytes[..]).unwrap());
+        assert_eq!(
+            SmolErrorKind::EmptyModel,
+            CrfTagger::new().viterbi(&["a"]).err().unwrap().kind()
+        );
+    }
+
+    #[test]
+    fn crf_l1_sparsity() {
+        let sentences = corpus();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+
+        let mut dense = CrfTagger::new();
+        dense.train(&corpus, &CrfConfig::new().seed(0));
+        let mut sparse = CrfTagger::new();
+        sparse.train(&corpus, &CrfConfig::new().l1(1.0).seed(0));
+
+        assert_eq!(dense.num_features(), sparse.num_features());
+        assert!(sparse.num_nonzero() < dense.num_nonzero());
+    }
+}
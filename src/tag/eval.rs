@@ -0,0 +1,535 @@
+//! Measuring how well a tagger does against a gold-standard corpus.
+//!
+//! An `Evaluator` tags the words of every gold sentence with some `Tagger`, and compares the
+//! output against the gold tags. The resulting `Evaluation` holds overall accuracy, accuracy on
+//! words seen and unseen during training, per-tag precision/recall/F1 and a confusion matrix.
+
+use super::*;
+use error::*;
+
+use failure::ResultExt;
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+/// The precision, recall and F1 score of a single tag.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct TagScore {
+    pub tag: String,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    /// The number of times the tag occurs in the gold corpus.
+    pub support: usize,
+}
+
+/// Counts of gold tags (rows) against predicted tags (columns).
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct ConfusionMatrix {
+    tags: Vec<String>,
+    counts: Vec<Vec<usize>>,
+}
+
+impl ConfusionMatrix {
+    /// Builds a confusion matrix from `(gold, predicted)` pairs.
+    pub fn from_pairs<'a, I: IntoIterator<Item = (&'a str, &'a str)>>(pairs: I) -> ConfusionMatrix {
+        let pairs = pairs.into_iter().collect::<Vec<_>>();
+        let tags = pairs
+            .iter()
+            .flat_map(|&(g, p)| vec![g, p])
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|t| t.to_owned())
+            .collect::<Vec<_>>();
+        let ix = tags
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (&**t, i))
+            .collect::<HashMap<_, _>>();
+
+        let mut counts = vec![vec![0; tags.len()]; tags.len()];
+        for (g, p) in pairs {
+            counts[ix[g]][ix[p]] += 1;
+        }
+
+        ConfusionMatrix { tags, counts }
+    }
+
+    /// Every tag which occurs in the matrix, sorted.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// The number of times `gold` was tagged as `predicted`.
+    pub fn get(&self, gold: &str, predicted: &str) -> usize {
+        match (self.index(gold), self.index(predicted)) {
+            (Some(g), Some(p)) => self.counts[g][p],
+            _ => 0,
+        }
+    }
+
+    /// Precision, recall and F1 for every tag, in the same order as `tags`.
+    pub fn scores(&self) -> Vec<TagScore> {
+        let n = self.tags.len();
+        (0..n)
+            .map(|i| {
+                let tp = self.counts[i][i] as f64;
+                let support = self.counts[i].iter().sum::<usize>();
+                let predicted = (0..n).map(|g| self.counts[g][i]).sum::<usize>();
+
+                let precision = ratio(tp, predicted as f64);
+                let recall = ratio(tp, support as f64);
+
+                TagScore {
+                    tag: self.tags[i].clone(),
+                    precision,
+                    recall,
+                    f1: ratio(2.0 * precision * recall, precision + recall),
+                    support,
+                }
+            })
+            .collect()
+    }
+
+    /// Formats the matrix as CSV, with gold tags as rows and predicted tags as columns.
+    pub fn to_csv(&self) -> String {
+        let mut res = String::from("gold\\predicted");
+        for t in &self.tags {
+            res.push(',');
+            res.push_str(&csv_field(t));
+        }
+        res.push('\n');
+
+        for (t, row) in self.tags.iter().zip(&self.counts) {
+            res.push_str(&csv_field(t));
+            for c in row {
+                res.push_str(&format!(",{}", c));
+            }
+            res.push('\n');
+        }
+
+        res
+    }
+
+    fn index(&self, tag: &str) -> Option<usize> {
+        self.tags.binary_search_by(|t| (**t).cmp(tag)).ok()
+    }
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .tags
+            .iter()
+            .map(|t| t.chars().count())
+            .chain(
+                self.counts
+                    .iter()
+                    .flat_map(|r| r.iter().map(|c| c.to_string().len())),
+            )
+            .max()
+            .unwrap_or(0);
+
+        write!(f, "{:>w$}", "", w = width)?;
+        for t in &self.tags {
+            write!(f, " {:>w$}", t, w = width)?;
+        }
+        writeln!(f)?;
+
+        for (t, row) in self.tags.iter().zip(&self.counts) {
+            write!(f, "{:>w$}", t, w = width)?;
+            for c in row {
+                write!(f, " {:>w$}", c, w = width)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The results of evaluating a tagger.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct Evaluation {
+    pub total: usize,
+    pub correct: usize,
+    pub known_total: usize,
+    pub known_correct: usize,
+    pub unknown_total: usize,
+    pub unknown_correct: usize,
+    pub confusion: ConfusionMatrix,
+}
+
+impl Evaluation {
+    /// The fraction of all tokens which were tagged correctly.
+    pub fn accuracy(&self) -> f64 {
+        ratio(self.correct as f64, self.total as f64)
+    }
+
+    /// The fraction of tokens seen during training which were tagged correctly.
+    pub fn known_accuracy(&self) -> f64 {
+        ratio(self.known_correct as f64, self.known_total as f64)
+    }
+
+    /// The fraction of tokens unseen during training which were tagged correctly.
+    pub fn unknown_accuracy(&self) -> f64 {
+        ratio(self.unknown_correct as f64, self.unknown_total as f64)
+    }
+
+    /// Precision, recall and F1 for every tag.
+    pub fn scores(&self) -> Vec<TagScore> {
+        self.confusion.scores()
+    }
+
+    pub fn to_json(&self) -> Result<String, SmolError> {
+        Ok(serde_json::to_string(self).context(SmolErrorKind::Serialize)?)
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "accuracy: {:.4} ({}/{})",
+            self.accuracy(),
+            self.correct,
+            self.total
+        )?;
+        writeln!(
+            f,
+            "known:    {:.4} ({}/{})",
+            self.known_accuracy(),
+            self.known_correct,
+            self.known_total
+        )?;
+        writeln!(
+            f,
+            "unknown:  {:.4} ({}/{})",
+            self.unknown_accuracy(),
+            self.unknown_correct,
+            self.unknown_total
+        )?;
+        writeln!(f)?;
+
+        let width = self
+            .confusion
+            .tags()
+            .iter()
+            .map(|t| t.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        writeln!(
+            f,
+            "{:>w$} {:>9} {:>9} {:>9} {:>9}",
+            "tag",
+            "precision",
+            "recall",
+            "f1",
+            "support",
+            w = width
+        )?;
+        for s in self.scores() {
+            writeln!(
+                f,
+                "{:>w$} {:>9.4} {:>9.4} {:>9.4} {:>9}",
+                s.tag,
+                s.precision,
+                s.recall,
+                s.f1,
+                s.support,
+                w = width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How much of some text is out of vocabulary (OOV) for a tagger, and how well the tagger does on
+/// known and unknown words.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct OovReport {
+    pub total: usize,
+    pub unknown: usize,
+    /// The number of known tokens with a gold tag, and how many of those were tagged correctly.
+    pub known_gold: usize,
+    pub known_correct: usize,
+    /// The number of unknown tokens with a gold tag, and how many of those were tagged correctly.
+    pub unknown_gold: usize,
+    pub unknown_correct: usize,
+    /// How often every unknown word occurs.
+    pub unknown_words: BTreeMap<String, usize>,
+    /// How often every shape of an unknown word occurs, e.g. `Xxxx` or `dd-dd`.
+    pub unknown_shapes: BTreeMap<String, usize>,
+}
+
+impl OovReport {
+    pub fn new() -> OovReport {
+        OovReport::default()
+    }
+
+    /// Counts a single token. An empty gold tag means the token has none, so it's left out of
+    /// the accuracies.
+    pub fn add(&mut self, word: &str, known: bool, gold: &str, predicted: &str) {
+        let tagged = !gold.is_empty() as usize;
+        let correct = (!gold.is_empty() && gold == predicted) as usize;
+
+        self.total += 1;
+        if known {
+            self.known_gold += tagged;
+            self.known_correct += correct;
+        } else {
+            self.unknown += 1;
+            self.unknown_gold += tagged;
+            self.unknown_correct += correct;
+            *self.unknown_words.entry(word.to_owned()).or_insert(0) += 1;
+            *self
+                .unknown_shapes
+                .entry(FeatureTemplate::shape(word))
+                .or_insert(0) += 1;
+        }
+    }
+
+    /// The fraction of tokens which are unknown.
+    pub fn oov_rate(&self) -> f64 {
+        ratio(self.unknown as f64, self.total as f64)
+    }
+
+    /// The fraction of known tokens with a gold tag which were tagged correctly.
+    pub fn known_accuracy(&self) -> f64 {
+        ratio(self.known_correct as f64, self.known_gold as f64)
+    }
+
+    /// The fraction of unknown tokens with a gold tag which were tagged correctly.
+    pub fn unknown_accuracy(&self) -> f64 {
+        ratio(self.unknown_correct as f64, self.unknown_gold as f64)
+    }
+
+    /// The `n` most common unknown words, most common first.
+    pub fn top_words(&self, n: usize) -> Vec<(&str, usize)> {
+        most_common(&self.unknown_words, n)
+    }
+
+    /// The `n` most common shapes of unknown words, most common first.
+    pub fn top_shapes(&self, n: usize) -> Vec<(&str, usize)> {
+        most_common(&self.unknown_shapes, n)
+    }
+}
+
+impl fmt::Display for OovReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "oov rate: {:.4} ({}/{})",
+            self.oov_rate(),
+            self.unknown,
+            self.total
+        )?;
+        writeln!(
+            f,
+            "known:    {:.4} ({}/{})",
+            self.known_accuracy(),
+            self.known_correct,
+            self.known_gold
+        )?;
+        writeln!(
+            f,
+            "unknown:  {:.4} ({}/{})",
+            self.unknown_accuracy(),
+            self.unknown_correct,
+            self.unknown_gold
+        )?;
+
+        writeln!(f)?;
+        writeln!(f, "top unknown shapes:")?;
+        for (s, c) in self.top_shapes(10) {
+            writeln!(f, "{:>9} {}", c, s)?;
+        }
+        writeln!(f, "top unknown words:")?;
+        for (w, c) in self.top_words(10) {
+            writeln!(f, "{:>9} {}", c, w)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn most_common(counts: &BTreeMap<String, usize>, n: usize) -> Vec<(&str, usize)> {
+    let mut res = counts
+        .iter()
+        .map(|(k, &c)| (&**k, c))
+        .collect::<Vec<_>>();
+    res.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    res.truncate(n);
+    res
+}
+
+/// Evaluates taggers against gold-standard tagged sentences.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Evaluator {
+    vocabulary: HashSet<String>,
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator::default()
+    }
+
+    /// Sets the words seen during training, which are used to split accuracy between known and
+    /// unknown words. Without a vocabulary, every word counts as unknown.
+    pub fn vocabulary(mut self, vocabulary: HashSet<String>) -> Evaluator {
+        self.vocabulary = vocabulary;
+        self
+    }
+
+    /// Uses the words of the given training sentences as the vocabulary.
+    pub fn training(self, sentences: &[TaggedSentence]) -> Evaluator {
+        let vocabulary = sentences
+            .iter()
+            .flat_map(|s| s.iter().map(|x| x.0.clone()))
+            .collect();
+        self.vocabulary(vocabulary)
+    }
+
+    /// Tags the words of every gold sentence, and compares the result against the gold tags.
+    pub fn evaluate<T>(
+        &self,
+        tagger: &T,
+        gold: &[TaggedSentence],
+    ) -> Result<Evaluation, SmolError>
+    where
+        T: Tagger,
+        T::Tag: ToString,
+    {
+        let mut res = Evaluation::default();
+        let mut pairs = Vec::new();
+
+        for sentence in gold {
+            let tagged = tagger.tag(sentence_tokens(sentence))?;
+            if tagged.len() != sentence.len() {
+                return Err(SmolErrorKind::Mismatch.into());
+            }
+
+            for ((word, gold), (_, guess)) in sentence.iter().zip(tagged) {
+                let guess = guess.to_string();
+                let correct = *gold == guess;
+                let known = self.vocabulary.contains(word);
+
+                res.total += 1;
+                res.correct += correct as usize;
+                if known {
+                    res.known_total += 1;
+                    res.known_correct += correct as usize;
+                } else {
+                    res.unknown_total += 1;
+                    res.unknown_correct += correct as usize;
+                }
+
+                pairs.push((gold.clone(), guess));
+            }
+        }
+
+        res.confusion = ConfusionMatrix::from_pairs(pairs.iter().map(|(g, p)| (&**g, &**p)));
+
+        Ok(res)
+    }
+}
+
+/// Turns the words of a tagged sentence into tokens, as though they were separated by spaces.
+pub fn sentence_tokens<'a>(sentence: TaggedSentence<'a>) -> Vec<Token<'a>> {
+    let mut offset = 0;
+    sentence
+        .iter()
+        .enumerate()
+        .map(|(index, x)| {
+            let t = Token {
+                term: (&*x.0).into(),
+                offset,
+                index,
+            };
+            offset += x.0.chars().count() + 1;
+            t
+        })
+        .collect()
+}
+
+/// Divides `a` by `b`, or returns zero when `b` is zero.
+pub(crate) fn ratio(a: f64, b: f64) -> f64 {
+    if b == 0.0 {
+        0.0
+    } else {
+        a / b
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tags everything as a noun, except for "the".
+    struct NounTagger;
+
+    impl Tagger for NounTagger {
+        type Tag = String;
+
+        fn tag<'a, I: IntoIterator<Item = Token<'a>>>(
+            &self,
+            tokens: I,
+        ) -> Result<Vec<(Token<'a>, Self::Tag)>, SmolError> {
+            Ok(tokens
+                .into_iter()
+                .map(|t| {
+                    let tag = if t.term == "the" { "DT" } else { "NN" };
+                    (t, tag.to_owned())
+                })
+                .collect())
+        }
+    }
+
+    fn pairs(s: &[(&str, &str)]) -> Vec<(String, String)> {
+        s.iter()
+            .map(|&(w, t)| (w.to_owned(), t.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn evaluate_counts() {
+        let train = pairs(&[("the", "DT"), ("dog", "NN")]);
+        let gold = pairs(&[
+            ("the", "DT"),
+            ("dog", "NN"),
+            ("barks", "VBZ"),
+            ("cat", "NN"),
+        ]);
+
+        let e = Evaluator::new()
+            .training(&[&train])
+            .evaluate(&NounTagger, &[&gold])
+            .unwrap();
+
+        assert_eq!(0.75, e.accuracy());
+        assert_eq!(1.0, e.known_accuracy());
+        assert_eq!(0.5, e.unknown_accuracy());
+        assert_eq!(1, e.confusion.get("VBZ", "NN"));
+        assert_eq!(0, e.confusion.get("NN", "VBZ"));
+
+        let nn = e.scores().into_iter().find(|s| s.tag == "NN").unwrap();
+        assert_eq!(2.0 / 3.0, nn.precision);
+        assert_eq!(1.0, nn.recall);
+        assert_eq!(2, nn.support);
+
+        assert_eq!(
+            "gold\\predicted,DT,NN,VBZ\nDT,1,0,0\nNN,0,2,0\nVBZ,0,1,0\n",
+            e.confusion.to_csv()
+        );
+    }
+}
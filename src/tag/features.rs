@@ -0,0 +1,600 @@
+//! Feature extraction for sequence models.
+//!
+//! Features are strings like `i suffix ing` or `i-1 tag DT`, each made up of the name of a
+//! template followed by the values it found. A `TemplateSet` is a declarative list of
+//! `FeatureTemplate`s, which can be serialized along with a model so that loading the model
+//! reproduces the same features. The default set matches the one used by NLTK and prose.
+//!
+//! # Compatibility
+//!
+//! Before templates existed, the `i+1 suffix` feature held the last characters of the previous
+//! word rather than the next one. The default set now uses the next word, like NLTK, so the `i+1
+//! suffix` weights of models trained before then no longer match the features they are given.
+//! Such models still load and tag, but slightly less accurately than they did; retrain them to
+//! get their full accuracy back.
+
+use error::*;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Everything a feature can look at when extracting features for a single word.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct FeatureContext<'a> {
+    /// The index of the word in its sentence.
+    pub i: usize,
+    /// The word itself, as it appears in the input.
+    pub word: &'a str,
+    /// The normalized words of the sentence, padded with two start and two end markers.
+    pub context: &'a [String],
+    /// The tag of the previous word.
+    pub p1: &'a str,
+    /// The tag of the word before the previous word.
+    pub p2: &'a str,
+    /// The part-of-speech tags of the sentence, padded like `context`, for models which label
+    /// words that are already tagged. Empty otherwise.
+    pub pos: &'a [String],
+}
+
+impl<'a> FeatureContext<'a> {
+    /// The normalized word at `offset` from the current one, which may be a start or end marker.
+    pub fn at(&self, offset: isize) -> &'a str {
+        let ix = (self.i as isize + 2 + offset).max(0) as usize;
+        &self.context[ix.min(self.context.len() - 1)]
+    }
+
+    /// The part-of-speech tag at `offset` from the current word, which may be a start or end
+    /// marker, or an empty string if the words aren't tagged.
+    pub fn pos_at(&self, offset: isize) -> &'a str {
+        if self.pos.is_empty() {
+            return "";
+        }
+        let ix = (self.i as isize + 2 + offset).max(0) as usize;
+        &self.pos[ix.min(self.pos.len() - 1)]
+    }
+}
+
+/// The features of a single word.
+///
+/// All features are stored in a single buffer, so that extracting features doesn't allocate a
+/// string for every feature. A `Features` can be cleared and reused for every word.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Features {
+    buf: String,
+    ends: Vec<usize>,
+}
+
+impl Features {
+    pub fn new() -> Features {
+        Features::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.ends.clear();
+    }
+
+    /// Adds a feature made up of the given parts, separated by spaces.
+    pub fn add(&mut self, parts: &[&str]) {
+        for (i, p) in parts.iter().enumerate() {
+            if i > 0 {
+                self.buf.push(' ');
+            }
+            self.buf.push_str(p);
+        }
+        self.ends.push(self.buf.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Iterates over every feature, in the order they were added.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a str> + 'a {
+        let starts = Some(0).into_iter().chain(self.ends.iter().cloned());
+        starts
+            .zip(&self.ends)
+            .map(move |(start, &end)| &self.buf[start..end])
+    }
+}
+
+/// A single kind of feature.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub enum FeatureTemplate {
+    /// A feature which is always present.
+    Bias,
+    /// The normalized word at an offset from the current word.
+    Word(isize),
+    /// The last characters of the word at an offset from the current word. The current word is
+    /// used as-is, while its neighbors are normalized.
+    Suffix(isize, usize),
+    /// The first characters of the current word.
+    Prefix(usize),
+    /// The tag of the word `n` words back, which must be either 1 or 2.
+    Tag(usize),
+    /// The tags of the previous two words.
+    TagBigram,
+    /// The tag of the previous word along with the current normalized word.
+    TagWord,
+    /// The part-of-speech tag of the word at an offset from the current word.
+    Pos(isize),
+    /// The part-of-speech tags of the word at an offset from the current word and the word after
+    /// it.
+    PosBigram(isize),
+    /// The part-of-speech tag of the current word along with the current normalized word.
+    PosWord,
+    /// The tag of the previous word along with the part-of-speech tag of the current word.
+    TagPos,
+    /// The shape of the current word, e.g. `Xxxx` for "Word" or `dd-dd` for "12-34".
+    Shape,
+    /// Whether the current word is lowercase, capitalized, all caps or mixed.
+    Capitalization,
+    /// Present if the current word contains a digit.
+    HasDigit,
+    /// Present if the current word contains a hyphen.
+    HasHyphen,
+    /// Present for the first word of a sentence, along with its capitalization, since a capital
+    /// letter says much less about the first word than about any other.
+    SentenceInitial,
+    /// Present if the (lowercased) current word is in a named list of words.
+    Gazetteer(String, BTreeSet<String>),
+    /// The cluster of the (lowercased) current word, from a named mapping of words to cluster IDs.
+    Cluster(String, BTreeMap<String, String>),
+}
+
+impl FeatureTemplate {
+    /// The name of a position relative to the current word, e.g. `i`, `i-1` or `i+2`.
+    fn position(offset: isize) -> Cow<'static, str> {
+        match offset {
+            -2 => "i-2".into(),
+            -1 => "i-1".into(),
+            0 => "i".into(),
+            1 => "i+1".into(),
+            2 => "i+2".into(),
+            o if o < 0 => format!("i{}", o).into(),
+            o => format!("i+{}", o).into(),
+        }
+    }
+
+    /// A name followed by a number, without allocating for small numbers.
+    fn numbered(name: &'static str, n: usize) -> Cow<'static, str> {
+        match (name, n) {
+            ("pref", 1) => "pref1".into(),
+            ("pref", 2) => "pref2".into(),
+            ("pref", 3) => "pref3".into(),
+            ("pref", 4) => "pref4".into(),
+            ("suffix", 1) => "suffix1".into(),
+            ("suffix", 2) => "suffix2".into(),
+            ("suffix", 4) => "suffix4".into(),
+            _ => format!("{}{}", name, n).into(),
+        }
+    }
+
+    /// The last `n` characters of `s`.
+    fn suffix(s: &str, n: usize) -> &str {
+        if n == 0 {
+            return "";
+        }
+        let start = s.char_indices().rev().nth(n - 1).map(|x| x.0).unwrap_or(0);
+        &s[start..]
+    }
+
+    /// The first `n` characters of `s`.
+    fn prefix(s: &str, n: usize) -> &str {
+        let end = s
+            .char_indices()
+            .nth(n)
+            .map(|x| x.0)
+            .unwrap_or_else(|| s.len());
+        &s[..end]
+    }
+
+    /// The shape of a word. Uppercase letters become `X`, lowercase letters `x` and digits `d`,
+    /// while anything else is kept. Runs of more than four of the same character are truncated.
+    pub fn shape(word: &str) -> String {
+        let mut res = String::new();
+        let mut last = None;
+        let mut run = 0;
+
+        for c in word.chars() {
+            let s = if c.is_uppercase() {
+                'X'
+            } else if c.is_lowercase() {
+                'x'
+            } else if c.is_numeric() {
+                'd'
+            } else {
+                c
+            };
+
+            if Some(s) == last {
+                run += 1;
+            } else {
+                last = Some(s);
+                run = 1;
+            }
+
+            if run <= 4 {
+                res.push(s);
+            }
+        }
+
+        res
+    }
+
+    /// Checks that the template can extract features, returning a `Parse` error for a `Tag`
+    /// which doesn't look 1 or 2 words back.
+    pub fn validate(&self) -> Result<(), SmolError> {
+        match *self {
+            FeatureTemplate::Tag(n) if n == 0 || n > 2 => Err(SmolErrorKind::Parse.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds the features of the word described by `ctx` to `features`.
+    ///
+    /// Templates only extract features as part of a `TemplateSet`, which never holds an invalid
+    /// template.
+    fn extract(&self, ctx: &FeatureContext, features: &mut Features) {
+        use self::FeatureTemplate::*;
+
+        match *self {
+            Bias => features.add(&["bias"]),
+            Word(o) => features.add(&[&Self::position(o), "word", ctx.at(o)]),
+            Suffix(o, n) => {
+                // NLTK calls three-character suffixes just "suffix"
+                let name = match n {
+                    3 => "suffix".into(),
+                    n => Self::numbered("suffix", n),
+                };
+                let word = if o == 0 { ctx.word } else { ctx.at(o) };
+                features.add(&[&Self::position(o), &name, Self::suffix(word, n)])
+            }
+            Prefix(n) => features.add(&[
+                "i",
+                &Self::numbered("pref", n),
+                Self::prefix(ctx.word, n),
+            ]),
+            Tag(1) => features.add(&["i-1", "tag", ctx.p1]),
+            Tag(2) => features.add(&["i-2", "tag", ctx.p2]),
+            Tag(_) => unreachable!("template sets only hold validated templates"),
+            TagBigram => features.add(&["i tag+i-2 tag", ctx.p1, ctx.p2]),
+            TagWord => features.add(&["i-1 tag+i word", ctx.p1, ctx.at(0)]),
+            Pos(o) => features.add(&[&Self::position(o), "pos", ctx.pos_at(o)]),
+            PosBigram(o) => {
+                let name = format!("{} pos+{} pos", Self::position(o), Self::position(o + 1));
+                features.add(&[&name, ctx.pos_at(o), ctx.pos_at(o + 1)])
+            }
+            PosWord => features.add(&["i pos+i word", ctx.pos_at(0), ctx.at(0)]),
+            TagPos => features.add(&["i-1 tag+i pos", ctx.p1, ctx.pos_at(0)]),
+            Shape => features.add(&["i shape", &Self::shape(ctx.word)]),
+            Capitalization => {
+                features.add(&["i capitalization", Self::capitalization(ctx.word)])
+            }
+            HasDigit => {
+                if ctx.word.chars().any(|c| c.is_numeric()) {
+                    features.add(&["i has-digit"])
+                }
+            }
+            HasHyphen => {
+                if ctx.word.contains('-') {
+                    features.add(&["i has-hyphen"])
+                }
+            }
+            SentenceInitial => {
+                if ctx.i == 0 {
+                    features.add(&["i sentence-initial", Self::capitalization(ctx.word)])
+                }
+            }
+            Gazetteer(ref name, ref words) => {
+                if words.contains(ctx.word) || words.contains(&ctx.word.to_lowercase()) {
+                    features.add(&["i in", name])
+                }
+            }
+            Cluster(ref name, ref clusters) => {
+                let cluster = clusters
+                    .get(ctx.word)
+                    .or_else(|| clusters.get(&ctx.word.to_lowercase()));
+                if let Some(c) = cluster {
+                    features.add(&["i cluster", name, c])
+                }
+            }
+        }
+    }
+
+    /// Whether the template looks at the tags of previous words.
+    pub fn uses_tags(&self) -> bool {
+        use self::FeatureTemplate::*;
+
+        matches!(*self, Tag(_) | TagBigram | TagWord | TagPos)
+    }
+
+    fn capitalization(word: &str) -> &'static str {
+        let mut chars = word.chars().filter(|c| c.is_alphabetic());
+        let first = match chars.next() {
+            Some(c) => c,
+            None => return "none",
+        };
+        let rest = chars.collect::<Vec<_>>();
+
+        if first.is_lowercase() && rest.iter().all(|c| c.is_lowercase()) {
+            "lower"
+        } else if first.is_uppercase() && rest.iter().all(|c| c.is_uppercase()) {
+            "upper"
+        } else if first.is_uppercase() && rest.iter().all(|c| c.is_lowercase()) {
+            "title"
+        } else {
+            "mixed"
+        }
+    }
+}
+
+/// A list of feature templates, which extracts the features of every template in order.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub struct TemplateSet {
+    #[serde(deserialize_with = "validated")]
+    templates: Vec<FeatureTemplate>,
+}
+
+/// Deserializes templates, rejecting any which can't extract features.
+fn validated<'de, D>(deserializer: D) -> Result<Vec<FeatureTemplate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let templates = Vec::<FeatureTemplate>::deserialize(deserializer)?;
+    for t in &templates {
+        t.validate().map_err(D::Error::custom)?;
+    }
+    Ok(templates)
+}
+
+impl Default for TemplateSet {
+    /// The features used by NLTK and prose.
+    fn default() -> TemplateSet {
+        use self::FeatureTemplate::*;
+
+        TemplateSet {
+            templates: vec![
+                Bias,
+                Suffix(0, 3),
+                Prefix(1),
+                Tag(1),
+                Tag(2),
+                TagBigram,
+                Word(0),
+                TagWord,
+                Word(-1),
+                Suffix(-1, 3),
+                Word(-2),
+                Word(1),
+                Suffix(1, 3),
+                Word(2),
+            ],
+        }
+    }
+}
+
+impl TemplateSet {
+    /// Creates a template set with no templates at all.
+    pub fn empty() -> TemplateSet {
+        TemplateSet {
+            templates: Vec::new(),
+        }
+    }
+
+    /// The default features, along with the shape, capitalization, digits, hyphens and sentence
+    /// position of the current word, which help with words unseen in training.
+    pub fn unknown_words() -> TemplateSet {
+        use self::FeatureTemplate::*;
+
+        TemplateSet::default().extended(vec![
+            Shape,
+            Capitalization,
+            SentenceInitial,
+            HasDigit,
+            HasHyphen,
+        ])
+    }
+
+    /// Adds a template to the set, returning a `Parse` error if the template is invalid, such as
+    /// a `Tag` which doesn't look 1 or 2 words back.
+    pub fn with(mut self, template: FeatureTemplate) -> Result<TemplateSet, SmolError> {
+        template.validate()?;
+        self.templates.push(template);
+        Ok(self)
+    }
+
+    /// Adds templates which are known to be valid, such as ones which don't take any arguments.
+    pub(crate) fn extended(mut self, templates: Vec<FeatureTemplate>) -> TemplateSet {
+        debug_assert!(templates.iter().all(|t| t.validate().is_ok()));
+        self.templates.extend(templates);
+        self
+    }
+
+    pub fn templates(&self) -> &[FeatureTemplate] {
+        &self.templates
+    }
+
+    /// Adds the features of the word described by `ctx` to `features`, for every template in
+    /// order.
+    pub fn extract(&self, ctx: &FeatureContext, features: &mut Features) {
+        for t in &self.templates {
+            t.extract(ctx, features);
+        }
+    }
+
+    /// The same set without any templates which look at previous tags, for models which only
+    /// see the words of a sentence.
+    pub fn without_tags(&self) -> TemplateSet {
+        TemplateSet {
+            templates: self
+                .templates
+                .iter()
+                .filter(|t| !t.uses_tags())
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Vec<String> {
+        vec![
+            "-START-", "-START2-", "the", "!hyphen", "dog", "-END-", "-END2-",
+        ]
+        .into_iter()
+        .map(|s| s.to_owned())
+        .collect()
+    }
+
+    #[test]
+    fn features_default() {
+        let context = context();
+        let ctx = FeatureContext {
+            i: 1,
+            word: "Well-Known",
+            context: &context,
+            p1: "DT",
+            p2: "-START-",
+            pos: &[],
+        };
+
+        let mut features = Features::new();
+        TemplateSet::default().extract(&ctx, &mut features);
+        let keys = features.iter().collect::<Vec<_>>();
+
+        for f in &[
+            "bias",
+            "i suffix own",
+            "i pref1 W",
+            "i-1 tag DT",
+            "i-2 tag -START-",
+            "i tag+i-2 tag DT -START-",
+            "i word !hyphen",
+            "i-1 tag+i word DT !hyphen",
+            "i-1 word the",
+            "i-1 suffix the",
+            "i-2 word -START2-",
+            "i+1 word dog",
+            "i+1 suffix dog",
+            "i+2 word -END-",
+        ] {
+            assert!(keys.contains(f), "missing {}", f);
+        }
+        assert_eq!(14, features.len());
+    }
+
+    #[test]
+    fn features_extra() {
+        let context = context();
+        let ctx = FeatureContext {
+            i: 1,
+            word: "Well-Known",
+            context: &context,
+            p1: "DT",
+            p2: "-START-",
+            pos: &[],
+        };
+
+        let set = TemplateSet::empty()
+            .with(FeatureTemplate::Prefix(4))
+            .and_then(|s| s.with(FeatureTemplate::Shape))
+            .and_then(|s| s.with(FeatureTemplate::Capitalization))
+            .and_then(|s| s.with(FeatureTemplate::HasDigit))
+            .and_then(|s| s.with(FeatureTemplate::HasHyphen))
+            .and_then(|s| {
+                s.with(FeatureTemplate::Gazetteer(
+                    "orgs".to_owned(),
+                    vec!["well-known".to_owned()].into_iter().collect(),
+                ))
+            })
+            .unwrap();
+
+        let mut features = Features::new();
+        set.extract(&ctx, &mut features);
+
+        let mut keys = features.iter().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(
+            vec![
+                "i capitalization mixed",
+                "i has-hyphen",
+                "i in orgs",
+                "i pref4 Well",
+                "i shape Xxxx-Xxxxx",
+            ],
+            keys
+        );
+        assert_eq!("dd-dd", FeatureTemplate::shape("12-34"));
+
+        // Only the first word of a sentence is flagged
+        features.clear();
+        FeatureTemplate::SentenceInitial.extract(&ctx, &mut features);
+        assert!(features.is_empty());
+        FeatureTemplate::SentenceInitial.extract(&FeatureContext { i: 0, ..ctx }, &mut features);
+        assert_eq!(vec!["i sentence-initial mixed"], features.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn features_pos() {
+        let context = context();
+        let pos = vec!["-START-", "-START2-", "DT", "JJ", "NN", "-END-", "-END2-"]
+            .into_iter()
+            .map(|s| s.to_owned())
+            .collect::<Vec<_>>();
+        let ctx = FeatureContext {
+            i: 1,
+            word: "Well-Known",
+            context: &context,
+            p1: "B-NP",
+            p2: "-START-",
+            pos: &pos,
+        };
+
+        let set = TemplateSet::empty().extended(vec![
+            FeatureTemplate::Pos(-2),
+            FeatureTemplate::Pos(1),
+            FeatureTemplate::PosBigram(-1),
+            FeatureTemplate::PosWord,
+            FeatureTemplate::TagPos,
+        ]);
+        let mut features = Features::new();
+        set.extract(&ctx, &mut features);
+        assert_eq!(
+            vec![
+                "i-2 pos -START2-",
+                "i+1 pos NN",
+                "i-1 pos+i pos DT JJ",
+                "i pos+i word JJ !hyphen",
+                "i-1 tag+i pos B-NP JJ",
+            ],
+            features.iter().collect::<Vec<_>>()
+        );
+        assert!(FeatureTemplate::TagPos.uses_tags());
+    }
+
+    #[test]
+    fn features_tag_offsets() {
+        assert!(FeatureTemplate::Tag(2).validate().is_ok());
+        for n in &[0, 3, 100] {
+            let kind = FeatureTemplate::Tag(*n).validate().err().unwrap().kind();
+            assert_eq!(SmolErrorKind::Parse, kind);
+        }
+
+        let kind = TemplateSet::empty().with(FeatureTemplate::Tag(3)).err().unwrap().kind();
+        assert_eq!(SmolErrorKind::Parse, kind);
+
+        let set = TemplateSet::empty().with(FeatureTemplate::Tag(2)).unwrap();
+        let json = ::serde_json::to_string(&set).unwrap().replace("2", "3");
+        assert!(::serde_json::from_str::<TemplateSet>(&json).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! The file format of saved tagger models.
+//!
+//! A model file starts with a fixed header: 8 magic bytes, the format version, a checksum of the
+//! body and the length of the body. The body is a bincode-encoded `ModelMetadata`, followed by
+//! the model itself. All header fields are little-endian.
+//!
+//! Files without the magic bytes are assumed to be bare bincode models from before the header
+//! existed.
+
+use super::*;
+use error::*;
+
+use bincode::{deserialize, serialize, Infinite};
+use failure::ResultExt;
+use serde::Serialize;
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MAGIC: &[u8; 8] = b"SMOLTAG\0";
+/// The newest format version this version of smol can read and write.
+pub const FORMAT_VERSION: u32 = 1;
+/// The size of the header: the magic bytes, the version, the checksum and the body length.
+const HEADER: usize = 28;
+
+/// A model which can be saved and loaded.
+///
+/// Models are written to and read from any `Write` or `Read`, so they can be kept in memory,
+/// embedded with `include_bytes!` or streamed from an archive, as well as stored in files.
+///
+/// # Examples
+/// ```rust
+/// # use smol::tag::*;
+/// let tagger = PerceptronTagger::new();
+///
+/// let mut bytes = Vec::new();
+/// tagger.save_to(&mut bytes).unwrap();
+/// let loaded = PerceptronTagger::load_from(&bytes[..]).unwrap();
+/// ```
+pub trait Persist: Sized {
+    fn save_to<W: Write>(&self, writer: W) -> Result<(), SmolError>;
+
+    fn load_from<R: Read>(reader: R) -> Result<Self, SmolError>;
+
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SmolError> {
+        let f = File::create(path).context(SmolErrorKind::Write)?;
+        let mut w = BufWriter::new(f);
+
+        self.save_to(&mut w)?;
+        w.flush().context(SmolErrorKind::Write)?;
+
+        Ok(())
+    }
+
+    fn load<P: AsRef<Path>>(path: P) -> Result<Self, SmolError> {
+        let f = File::open(path).context(SmolErrorKind::Read)?;
+        Self::load_from(BufReader::new(f))
+    }
+}
+
+/// Information about the corpus a model was trained on.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct CorpusInfo {
+    /// A name or description of the corpus.
+    pub name: Option<String>,
+    pub sentences: usize,
+    pub tokens: usize,
+}
+
+/// Describes a saved model.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize)]
+pub struct ModelMetadata {
+    /// The features the model was trained with.
+    pub templates: TemplateSet,
+    /// Every tag the model can output, sorted.
+    pub tagset: Vec<String>,
+    pub corpus: CorpusInfo,
+    /// When the model was saved, in seconds since the Unix epoch.
+    pub date: u64,
+}
+
+impl ModelMetadata {
+    /// Reads the metadata of a saved model, checking the integrity of the whole file. Models saved
+    /// before the current format existed have no metadata, and result in a `Version` error.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<ModelMetadata, SmolError> {
+        let f = File::open(path).context(SmolErrorKind::Read)?;
+        ModelMetadata::read_from(BufReader::new(f))
+    }
+
+    /// Reads the metadata of a saved model from a reader.
+    pub fn read_from<R: Read>(reader: R) -> Result<ModelMetadata, SmolError> {
+        let bytes = read_all(reader)?;
+        match decode(&bytes)? {
+            // The metadata comes first, so the rest of the body can be ignored
+            Some(body) => Ok(deserialize(body).context(SmolErrorKind::Deserialize)?),
+            None => Err(SmolErrorKind::Version.into()),
+        }
+    }
+}
+
+/// The current time in seconds since the Unix epoch.
+///
+/// Respects `SOURCE_DATE_EPOCH`, so that saving a model can be made reproducible.
+pub(crate) fn now() -> u64 {
+    env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        })
+}
+
+pub(crate) fn read_all<R: Read>(mut reader: R) -> Result<Vec<u8>, SmolError> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .context(SmolErrorKind::Read)?;
+    Ok(bytes)
+}
+
+/// Serializes a body and puts it in a container.
+pub(crate) fn encode<T: Serialize>(body: &T) -> Result<Vec<u8>, SmolError> {
+    let body = serialize(body, Infinite).context(SmolErrorKind::Serialize)?;
+
+    let mut res = Vec::with_capacity(HEADER + body.len());
+    res.extend_from_slice(MAGIC);
+    res.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    res.extend_from_slice(&checksum(&body).to_le_bytes());
+    res.extend_from_slice(&(body.len() as u64).to_le_bytes());
+    res.extend_from_slice(&body);
+
+    Ok(res)
+}
+
+/// Checks a container and returns its body, or `None` if the bytes have no header at all.
+pub(crate) fn decode(bytes: &[u8]) -> Result<Option<&[u8]>, SmolError> {
+    if !bytes.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    if bytes.len() < HEADER {
+        return Err(SmolErrorKind::Corrupt.into());
+    }
+
+    let mut version = [0; 4];
+    version.copy_from_slice(&bytes[8..12]);
+    if u32::from_le_bytes(version) != FORMAT_VERSION {
+        return Err(SmolErrorKind::Version.into());
+    }
+
+    let (mut sum, mut len) = ([0; 8], [0; 8]);
+    sum.copy_from_slice(&bytes[12..20]);
+    len.copy_from_slice(&bytes[20..28]);
+
+    let body = &bytes[HEADER..];
+    if body.len() as u64 != u64::from_le_bytes(len) || checksum(body) != u64::from_le_bytes(sum) {
+        return Err(SmolErrorKind::Corrupt.into());
+    }
+
+    Ok(Some(body))
+}
+
+/// The 64-bit FNV-1a hash of some bytes.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn container_errors() {
+        let mut bytes = encode(&("some", "body")).unwrap();
+        assert!(decode(&bytes).unwrap().is_some());
+        assert!(decode(b"legacy").unwrap().is_none());
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert_eq!(SmolErrorKind::Corrupt, decode(&bytes).err().unwrap().kind());
+        assert_eq!(
+            SmolErrorKind::Corrupt,
+            decode(&bytes[..last]).err().unwrap().kind()
+        );
+
+        bytes[8] = 2;
+        assert_eq!(SmolErrorKind::Version, decode(&bytes).err().unwrap().kind());
+    }
+}
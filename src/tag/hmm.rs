@@ -0,0 +1,560 @@
+//! A hidden Markov model part of speech tagger.
+//!
+//! The model is a first-order HMM: the probability of a tag depends only on the tag before it.
+//! Transition probabilities interpolate bigram and unigram estimates, with weights found by
+//! deleted interpolation. Words seen during training are emitted with their relative frequency,
+//! while unseen words are handled by a suffix model, as in
+//! [TnT](http://www.coli.uni-saarland.de/~thorsten/publications/Brants-ANLP00.pdf): the tag
+//! distribution of rare words with the same ending, smoothed over shorter and shorter suffixes.
+//!
+//! Tagging finds the most likely tag sequence with the Viterbi algorithm, and the probability of
+//! every tag for every word can be found with the forward-backward algorithm.
+
+use super::eval::ratio;
+use super::format::{decode, encode, now, read_all};
+use super::*;
+use error::*;
+
+use bincode::deserialize;
+use failure::ResultExt;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
+
+/// The least weight given to the unigram transition estimate. Without it, a corpus in which every
+/// bigram is best predicted by its own counts would leave no way to get past a bigram which was
+/// never seen in training.
+const MIN_UNIGRAM_WEIGHT: f64 = 0.01;
+
+/// A tagger based on a hidden Markov model.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct HmmTagger {
+    /// Every tag, sorted.
+    tags: Vec<String>,
+    /// The number of times every tag occurs.
+    tag_counts: Vec<usize>,
+    /// Counts of tag bigrams. Row `n` stands for the start of a sentence, and column `n` for the
+    /// end of one, where `n` is the number of tags.
+    transitions: Vec<Vec<usize>>,
+    /// The weights of the unigram and bigram transition estimates.
+    lambdas: (f64, f64),
+    /// The number of times every word occurs with each tag.
+    lexicon: HashMap<String, Vec<(usize, usize)>>,
+    /// Tag counts of the suffixes of rare lowercase and capitalized words.
+    suffixes: (HashMap<String, Vec<usize>>, HashMap<String, Vec<usize>>),
+    /// How much shorter suffixes are trusted over longer ones.
+    theta: f64,
+    max_suffix: usize,
+    rare: usize,
+    corpus: CorpusInfo,
+}
+
+impl Default for HmmTagger {
+    fn default() -> HmmTagger {
+        HmmTagger {
+            tags: Vec::new(),
+            tag_counts: Vec::new(),
+            transitions: Vec::new(),
+            lambdas: (0.0, 0.0),
+            lexicon: HashMap::new(),
+            suffixes: (HashMap::new(), HashMap::new()),
+            theta: 0.0,
+            max_suffix: 10,
+            rare: 10,
+            corpus: CorpusInfo::default(),
+        }
+    }
+}
+
+impl HmmTagger {
+    pub fn new() -> HmmTagger {
+        HmmTagger::default()
+    }
+
+    /// Sets the length of the longest suffix used to guess the tags of unknown words. Defaults to
+    /// 10.
+    pub fn max_suffix(mut self, n: usize) -> HmmTagger {
+        self.max_suffix = n;
+        self
+    }
+
+    /// Sets how often a word may occur to count as rare. Only rare words are used to learn the
+    /// suffix model, since unknown words are rare too. Defaults to 10.
+    pub fn rare_threshold(mut self, n: usize) -> HmmTagger {
+        self.rare = n;
+        self
+    }
+
+    /// Every tag the tagger can output, sorted.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn get_corpus(&self) -> &CorpusInfo {
+        &self.corpus
+    }
+
+    /// Estimates the model from tagged sentences, replacing anything learned before.
+    pub fn train(&mut self, sentences: &[TaggedSentence]) {
+        self.tags = sentences
+            .iter()
+            .flat_map(|s| s.iter().map(|x| x.1.clone()))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let ids = self
+            .tags
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (&**t, i))
+            .collect::<HashMap<_, _>>();
+        let n = self.tags.len();
+
+        self.tag_counts = vec![0; n];
+        self.transitions = vec![vec![0; n + 1]; n + 1];
+        let mut lexicon: HashMap<&str, HashMap<usize, usize>> = HashMap::new();
+
+        for sentence in sentences {
+            let mut prev = n;
+            for (word, tag) in *sentence {
+                let t = ids[&**tag];
+                self.tag_counts[t] += 1;
+                self.transitions[prev][t] += 1;
+                *lexicon.entry(word).or_default().entry(t).or_insert(0) += 1;
+                prev = t;
+            }
+            self.transitions[prev][n] += 1;
+        }
+
+        self.lexicon = lexicon
+            .into_iter()
+            .map(|(w, counts)| {
+                let mut counts = counts.into_iter().collect::<Vec<_>>();
+                counts.sort();
+                (w.to_owned(), counts)
+            })
+            .collect();
+
+        self.lambdas = self.deleted_interpolation();
+        self.learn_suffixes();
+
+        self.corpus.sentences = sentences.len();
+        self.corpus.tokens = self.tag_counts.iter().sum();
+    }
+
+    /// Finds how much to trust bigram over unigram estimates, by checking which estimate predicts
+    /// every bigram better once that bigram is left out of the counts.
+    fn deleted_interpolation(&self) -> (f64, f64) {
+        let n = self.tags.len();
+        let total = self
+            .transitions
+            .iter()
+            .map(|r| r.iter().sum::<usize>())
+            .sum::<usize>();
+        let columns = (0..n + 1)
+            .map(|c| self.transitions.iter().map(|r| r[c]).sum::<usize>())
+            .collect::<Vec<_>>();
+
+        let (mut l1, mut l2) = (0.0, 0.0);
+        for row in &self.transitions {
+            let row_total = row.iter().sum::<usize>();
+            for (c, &count) in row.iter().enumerate().filter(|x| *x.1 > 0) {
+                let bigram = ratio((count - 1) as f64, (row_total - 1) as f64);
+                let unigram = ratio((columns[c] - 1) as f64, (total - 1) as f64);
+                if bigram > unigram {
+                    l2 += count as f64;
+                } else {
+                    l1 += count as f64;
+                }
+            }
+        }
+
+        if l1 + l2 == 0.0 {
+            (1.0, 0.0)
+        } else {
+            let l1 = (l1 / (l1 + l2)).max(MIN_UNIGRAM_WEIGHT);
+            (l1, 1.0 - l1)
+        }
+    }
+
+    fn learn_suffixes(&mut self) {
+        let n = self.tags.len();
+        let mut suffixes = (HashMap::new(), HashMap::new());
+
+        for (word, counts) in &self.lexicon {
+            if counts.iter().map(|x| x.1).sum::<usize>() > self.rare {
+                continue;
+            }
+
+            let trie = if is_capitalized(word) {
+                &mut suffixes.1
+            } else {
+                &mut suffixes.0
+            };
+            for suffix in word_suffixes(word, self.max_suffix) {
+                let tags = trie.entry(suffix.to_owned()).or_insert_with(|| vec![0; n]);
+                for &(t, c) in counts {
+                    tags[t] += c;
+                }
+            }
+        }
+
+        self.suffixes = suffixes;
+
+        // The standard deviation of the tag distribution
+        let total = self.tag_counts.iter().sum::<usize>() as f64;
+        let mean = 1.0 / n as f64;
+        let var = self
+            .tag_counts
+            .iter()
+            .map(|&c| (c as f64 / total - mean).powi(2))
+            .sum::<f64>()
+            / (n as f64 - 1.0).max(1.0);
+        self.theta = var.sqrt();
+    }
+
+    /// The probability of tag `next` following tag `prev`, where `n` stands for the start or end
+    /// of the sentence.
+    fn transition(&self, prev: usize, next: usize) -> f64 {
+        let total = self.corpus.tokens + self.corpus.sentences;
+        let unigram = ratio(
+            self.transitions.iter().map(|r| r[next]).sum::<usize>() as f64,
+            total as f64,
+        );
+        let row = &self.transitions[prev];
+        let bigram = ratio(row[next] as f64, row.iter().sum::<usize>() as f64);
+
+        self.lambdas.0 * unigram + self.lambdas.1 * bigram
+    }
+
+    /// The probability of every tag emitting `word`, up to a factor which is the same for every
+    /// tag.
+    fn emissions(&self, word: &str) -> Vec<f64> {
+        let n = self.tags.len();
+        let known = self
+            .lexicon
+            .get(word)
+            .or_else(|| self.lexicon.get(&word.to_lowercase()));
+
+        if let Some(counts) = known {
+            let mut res = vec![0.0; n];
+            for &(t, c) in counts {
+                res[t] = ratio(c as f64, self.tag_counts[t] as f64);
+            }
+            return res;
+        }
+
+        // P(word | tag) = P(tag | suffix) P(word) / P(tag), and P(word) is the same for every tag
+        let total = self.corpus.tokens;
+        let probs = self.suffix_probabilities(word);
+        (0..n)
+            .map(|t| probs[t] / ratio(self.tag_counts[t] as f64, total as f64).max(1e-12))
+            .collect()
+    }
+
+    /// The probability of every tag given the longest known suffix of an unknown word.
+    fn suffix_probabilities(&self, word: &str) -> Vec<f64> {
+        let n = self.tags.len();
+        let trie = if is_capitalized(word) {
+            &self.suffixes.1
+        } else {
+            &self.suffixes.0
+        };
+
+        let mut probs = vec![1.0 / n as f64; n];
+        for suffix in word_suffixes(word, self.max_suffix) {
+            let counts = match trie.get(suffix) {
+                Some(c) => c,
+                None => break,
+            };
+            let total = counts.iter().sum::<usize>();
+            for (p, &c) in probs.iter_mut().zip(counts) {
+                *p = (ratio(c as f64, total as f64) + self.theta * *p) / (1.0 + self.theta);
+            }
+        }
+
+        probs
+    }
+
+    /// Finds the most likely tags of a sentence.
+    pub fn viterbi(&self, words: &[&str]) -> Result<Vec<String>, SmolError> {
+        let n = self.tags.len();
+        if n == 0 {
+            return Err(SmolErrorKind::EmptyModel.into());
+        }
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let log_transitions = (0..n + 1)
+            .map(|p| (0..n + 1).map(|t| self.transition(p, t).ln()).collect())
+            .collect::<Vec<Vec<f64>>>();
+
+        let mut scores = vec![Vec::with_capacity(n); words.len()];
+        let mut backpointers = vec![vec![0; n]; words.len()];
+
+        for (i, word) in words.iter().enumerate() {
+            let emissions = self.emissions(word);
+            for t in 0..n {
+                let (best, score) = if i == 0 {
+                    (n, log_transitions[n][t])
+                } else {
+                    (0..n)
+                        .map(|p| (p, scores[i - 1][p] + log_transitions[p][t]))
+                        .fold((0, f64::NEG_INFINITY), |a, b| if b.1 > a.1 { b } else { a })
+                };
+                backpointers[i][t] = best;
+                scores[i].push(score + emissions[t].ln());
+            }
+        }
+
+        let last = words.len() - 1;
+        let mut t = (0..n)
+            .map(|t| (t, scores[last][t] + log_transitions[t][n]))
+            .fold((0, f64::NEG_INFINITY), |a, b| if b.1 > a.1 { b } else { a })
+            .0;
+
+        let mut res = vec![String::new(); words.len()];
+        for i in (0..words.len()).rev() {
+            res[i] = self.tags[t].clone();
+            t = backpointers[i][t];
+        }
+
+        Ok(res)
+    }
+
+    /// Finds the probability of every tag for every word of a sentence, given the whole sentence.
+    /// The probabilities are indexed like `tags`.
+    pub fn forward_backward(&self, words: &[&str]) -> Result<Vec<Vec<f64>>, SmolError> {
+        let n = self.tags.len();
+        if n == 0 {
+            return Err(SmolErrorKind::EmptyModel.into());
+        }
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let transitions = (0..n + 1)
+            .map(|p| (0..n + 1).map(|t| self.transition(p, t)).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let emissions = words.iter().map(|w| self.emissions(w)).collect::<Vec<_>>();
+
+        // Every column is rescaled to sum to 1, which keeps long sentences from underflowing
+        let mut forward: Vec<Vec<f64>> = Vec::with_capacity(words.len());
+        for i in 0..words.len() {
+            let column = (0..n)
+                .map(|t| {
+                    let prior = if i == 0 {
+                        transitions[n][t]
+                    } else {
+                        (0..n).map(|p| forward[i - 1][p] * transitions[p][t]).sum()
+                    };
+                    prior * emissions[i][t]
+                })
+                .collect();
+            forward.push(normalize(column));
+        }
+
+        let mut backward = vec![Vec::new(); words.len()];
+        backward[words.len() - 1] = normalize((0..n).map(|t| transitions[t][n]).collect());
+        for i in (0..words.len() - 1).rev() {
+            let column = (0..n)
+                .map(|t| {
+                    (0..n)
+                        .map(|s| transitions[t][s] * emissions[i + 1][s] * backward[i + 1][s])
+                        .sum()
+                })
+                .collect();
+            backward[i] = normalize(column);
+        }
+
+        Ok(forward
+            .into_iter()
+            .zip(backward)
+            .map(|(f, b)| normalize(f.iter().zip(&b).map(|(f, b)| f * b).collect()))
+            .collect())
+    }
+
+    /// Tags words, returning every tag along with its probability given the whole sentence, most
+    /// likely first. The score of each tag is the log of its probability.
+    pub fn marginals<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        tokens: I,
+    ) -> Result<Vec<(Token<'a>, Vec<ScoredTag>)>, SmolError> {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let words = tokens.iter().map(|t| &*t.term).collect::<Vec<_>>();
+        let marginals = self.forward_backward(&words)?;
+
+        Ok(tokens
+            .into_iter()
+            .zip(marginals)
+            .map(|(token, probs)| {
+                let mut tags = probs
+                    .into_iter()
+                    .enumerate()
+                    .map(|(t, p)| ScoredTag {
+                        tag: self.tags[t].clone(),
+                        score: p.ln(),
+                        confidence: p,
+                    })
+                    .collect::<Vec<_>>();
+                tags.sort_by(|a, b| {
+                    b.confidence
+                        .partial_cmp(&a.confidence)
+                        .unwrap_or(Ordering::Equal)
+                });
+                (token, tags)
+            })
+            .collect())
+    }
+
+    /// Describes the tagger, as it would be saved right now.
+    pub fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            templates: TemplateSet::empty(),
+            tagset: self.tags.clone(),
+            corpus: self.corpus.clone(),
+            date: now(),
+        }
+    }
+}
+
+impl Tagger for HmmTagger {
+    type Tag = String;
+
+    fn tag<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        tokens: I,
+    ) -> Result<Vec<(Token<'a>, Self::Tag)>, SmolError> {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tags = self.viterbi(&tokens.iter().map(|t| &*t.term).collect::<Vec<_>>())?;
+        Ok(tokens.into_iter().zip(tags).collect())
+    }
+}
+
+impl Persist for HmmTagger {
+    fn save_to<W: Write>(&self, mut writer: W) -> Result<(), SmolError> {
+        let s = encode(&(self.metadata(), self))?;
+        writer.write_all(&s).context(SmolErrorKind::Write)?;
+        Ok(())
+    }
+
+    fn load_from<R: Read>(reader: R) -> Result<HmmTagger, SmolError> {
+        let bytes = read_all(reader)?;
+        let body = decode(&bytes)?.ok_or(SmolErrorKind::Deserialize)?;
+        let (_, tagger): (ModelMetadata, HmmTagger) =
+            deserialize(body).context(SmolErrorKind::Deserialize)?;
+        Ok(tagger)
+    }
+}
+
+fn is_capitalized(word: &str) -> bool {
+    word.chars()
+        .next()
+        .map(|c| c.is_uppercase())
+        .unwrap_or(false)
+}
+
+/// The suffixes of a word, from the empty one up to `max` characters long.
+fn word_suffixes(word: &str, max: usize) -> Vec<&str> {
+    let mut res = vec![""];
+    res.extend(word.char_indices().rev().take(max).map(|(i, _)| &word[i..]));
+    res
+}
+
+fn normalize(v: Vec<f64>) -> Vec<f64> {
+    let sum = v.iter().sum::<f64>();
+    if sum == 0.0 {
+        let n = v.len() as f64;
+        v.into_iter().map(|_| 1.0 / n).collect()
+    } else {
+        v.into_iter().map(|x| x / sum).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus() -> Vec<Vec<(String, String)>> {
+        vec![
+            vec![("the", "DT"), ("dog", "NN"), ("barks", "VBZ")],
+            vec![("a", "DT"), ("cat", "NN"), ("sleeps", "VBZ")],
+            vec![("the", "DT"), ("cats", "NNS"), ("sleep", "VBP")],
+            vec![("a", "DT"), ("dog", "NN"), ("walks", "VBZ")],
+        ]
+        .into_iter()
+        .map(|s| {
+            s.into_iter()
+                .map(|(w, t)| (w.to_owned(), t.to_owned()))
+                .collect()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn hmm_viterbi() {
+        let sentences = corpus();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+        let mut hmm = HmmTagger::new();
+        hmm.train(&corpus);
+
+        let e = Evaluator::new().evaluate(&hmm, &corpus).unwrap();
+        assert_eq!(1.0, e.accuracy());
+
+        // "jumps" is unknown, but ends like the VBZ words
+        assert_eq!(
+            vec!["DT", "NN", "VBZ"],
+            hmm.viterbi(&["the", "dog", "jumps"]).unwrap()
+        );
+        assert_eq!(
+            SmolErrorKind::EmptyModel,
+            HmmTagger::new().viterbi(&["a"]).err().unwrap().kind()
+        );
+    }
+
+    #[test]
+    fn hmm_unseen_transition() {
+        // Every bigram is predicted perfectly by its own counts, which would leave no weight on
+        // unigrams at all
+        let sentence = [
+            ("the".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+            ("barks".to_owned(), "VBZ".to_owned()),
+        ];
+        let mut hmm = HmmTagger::new();
+        hmm.train(&[&sentence[..]; 5]);
+        assert!(hmm.lambdas.0 >= MIN_UNIGRAM_WEIGHT);
+
+        // VBZ is never followed by DT, or DT by VBZ
+        assert_eq!(vec!["VBZ", "DT"], hmm.viterbi(&["barks", "the"]).unwrap());
+        assert_eq!(
+            vec!["DT", "VBZ", "NN"],
+            hmm.viterbi(&["the", "barks", "dog"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn hmm_marginals() {
+        let sentences = corpus();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+        let mut hmm = HmmTagger::new();
+        hmm.train(&corpus);
+
+        let words = ["a", "cat", "jumps"];
+        let marginals = hmm.forward_backward(&words).unwrap();
+        for probs in &marginals {
+            assert!((probs.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+
+        let best = hmm.marginals(sentence_tokens(&sentences[1])).unwrap();
+        assert!(best
+            .iter()
+            .zip(&sentences[1])
+            .all(|(b, s)| b.1[0].tag == s.1));
+
+        let mut bytes = Vec::new();
+        hmm.save_to(&mut bytes).unwrap();
+        assert_eq!(hmm, HmmTagger::load_from(&bytes[..]).unwrap());
+    }
+}
@@ -0,0 +1,392 @@
+//! Exchanging perceptron models with other programs.
+//!
+//! Models can be exported to and imported from a human-readable JSON document, which is handy for
+//! inspecting and diffing weights. Models trained by [NLTK](http://www.nltk.org/) and
+//! [prose](https://github.com/jdkato/prose), which `PerceptronTagger` is ported from, can be
+//! imported too.
+//!
+//! Both projects name their features just like smol's default templates, so their weights carry
+//! over as is. Features with other names are dropped, since the default templates would never
+//! produce them.
+
+use super::*;
+use error::*;
+
+use failure::ResultExt;
+use serde_json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+/// The JSON representation of a perceptron model.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct JsonModel {
+    /// Every tag the model can output.
+    pub classes: Vec<String>,
+    /// Words which are always given the same tag.
+    pub tagdict: BTreeMap<String, String>,
+    /// The weight of every class, by feature.
+    pub weights: BTreeMap<String, BTreeMap<String, f64>>,
+    #[serde(default)]
+    pub templates: TemplateSet,
+    #[serde(default)]
+    pub corpus: CorpusInfo,
+}
+
+/// The names of the features produced by the default templates.
+const DEFAULT_FEATURES: &[&str] = &[
+    "i suffix",
+    "i pref1",
+    "i-1 tag",
+    "i-2 tag",
+    "i tag+i-2 tag",
+    "i word",
+    "i-1 tag+i word",
+    "i-1 word",
+    "i-1 suffix",
+    "i-2 word",
+    "i+1 word",
+    "i+1 suffix",
+    "i+2 word",
+];
+
+/// Maps the name of an NLTK or prose feature to smol's, if the default templates produce it.
+pub fn map_feature_key(key: &str) -> Option<&str> {
+    if key == "bias"
+        || DEFAULT_FEATURES
+            .iter()
+            .any(|f| key.starts_with(f) && key[f.len()..].starts_with(' '))
+    {
+        Some(key)
+    } else {
+        None
+    }
+}
+
+impl PerceptronTagger {
+    /// Writes the model as pretty-printed JSON. Everything is sorted, so that exports of similar
+    /// models can be diffed.
+    pub fn export_json<W: Write>(&self, writer: W) -> Result<(), SmolError> {
+        let model = JsonModel {
+            classes: self.metadata().tagset,
+            tagdict: self
+                .get_tagdict()
+                .iter()
+                .map(|(w, t)| (w.clone(), t.clone()))
+                .collect(),
+            weights: self.get_model().to_weights(),
+            templates: self.get_templates().clone(),
+            corpus: self.get_corpus().clone(),
+        };
+
+        serde_json::to_writer_pretty(writer, &model).context(SmolErrorKind::Serialize)?;
+
+        Ok(())
+    }
+
+    /// Reads a model exported with `export_json`.
+    pub fn import_json<R: Read>(reader: R) -> Result<PerceptronTagger, SmolError> {
+        let model: JsonModel =
+            serde_json::from_reader(reader).context(SmolErrorKind::Deserialize)?;
+
+        let weights = model
+            .weights
+            .into_iter()
+            .map(|(f, ws)| (f, ws.into_iter().collect()))
+            .collect();
+        let perceptron =
+            AveragedPerceptron::from_weights(weights, model.classes.into_iter().collect());
+
+        Ok(PerceptronTagger::from_parts(
+            perceptron,
+            model.tagdict.into_iter().collect(),
+            model.templates,
+            model.corpus,
+        ))
+    }
+
+    /// Imports a model saved by NLTK 3.8.2 or later from the directory it was saved to, e.g.
+    /// `nltk_data/taggers/averaged_perceptron_tagger_eng`. The directory holds a
+    /// `*.weights.json`, a `*.tagdict.json` and a `*.classes.json` file.
+    pub fn from_nltk<P: AsRef<Path>>(dir: P) -> Result<PerceptronTagger, SmolError> {
+        let open = |suffix: &str| -> Result<BufReader<File>, SmolError> {
+            let entries = fs::read_dir(dir.as_ref()).context(SmolErrorKind::Read)?;
+            for entry in entries {
+                let path = entry.context(SmolErrorKind::Read)?.path();
+                let matches = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.ends_with(suffix))
+                    .unwrap_or(false);
+                if matches {
+                    return Ok(BufReader::new(
+                        File::open(path).context(SmolErrorKind::Read)?,
+                    ));
+                }
+            }
+            Err(SmolErrorKind::Read.into())
+        };
+
+        PerceptronTagger::from_nltk_json(
+            open(".weights.json")?,
+            open(".tagdict.json")?,
+            open(".classes.json")?,
+        )
+    }
+
+    /// Imports a model from the three JSON documents NLTK saves it as.
+    pub fn from_nltk_json<W: Read, T: Read, C: Read>(
+        weights: W,
+        tagdict: T,
+        classes: C,
+    ) -> Result<PerceptronTagger, SmolError> {
+        let weights: HashMap<String, HashMap<String, f64>> =
+            serde_json::from_reader(weights).context(SmolErrorKind::Deserialize)?;
+        let tags = serde_json::from_reader(tagdict).context(SmolErrorKind::Deserialize)?;
+        let classes = serde_json::from_reader(classes).context(SmolErrorKind::Deserialize)?;
+
+        Ok(imported(weights, tags, classes))
+    }
+
+    /// Imports a model from the directory of prose's averaged perceptron model, which holds
+    /// `weights.gob`, `tags.gob` and `classes.gob`.
+    pub fn from_prose<P: AsRef<Path>>(dir: P) -> Result<PerceptronTagger, SmolError> {
+        let open = |name: &str| -> Result<BufReader<File>, SmolError> {
+            let f = File::open(dir.as_ref().join(name)).context(SmolErrorKind::Read)?;
+            Ok(BufReader::new(f))
+        };
+
+        PerceptronTagger::from_prose_gob(
+            open("weights.gob")?,
+            open("tags.gob")?,
+            open("classes.gob")?,
+        )
+    }
+
+    /// Imports a model from the three gob-encoded values prose saves it as.
+    pub fn from_prose_gob<W: Read, T: Read, C: Read>(
+        weights: W,
+        tags: T,
+        classes: C,
+    ) -> Result<PerceptronTagger, SmolError> {
+        let weights = gob::decode(weights, |g| g.map(|g| g.map(|g| g.float())))?;
+        let tags = gob::decode(tags, |g| g.map(|g| g.string()))?;
+        let classes = gob::decode(classes, |g| g.slice(|g| g.string()))?;
+
+        Ok(imported(weights, tags, classes.into_iter().collect()))
+    }
+}
+
+fn imported(
+    weights: HashMap<String, HashMap<String, f64>>,
+    tags: HashMap<String, String>,
+    classes: HashSet<String>,
+) -> PerceptronTagger {
+    let weights = weights
+        .into_iter()
+        .filter(|x| map_feature_key(&x.0).is_some())
+        .collect();
+
+    PerceptronTagger::from_parts(
+        AveragedPerceptron::from_weights(weights, classes),
+        tags,
+        TemplateSet::default(),
+        CorpusInfo::default(),
+    )
+}
+
+/// Just enough of Go's [gob](https://golang.org/pkg/encoding/gob/) encoding to read prose's
+/// models: a single top-level map or slice of strings, floats and nested maps.
+mod gob {
+    use error::*;
+
+    use failure::ResultExt;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    pub struct Gob<'a> {
+        bytes: &'a [u8],
+    }
+
+    impl<'a> Gob<'a> {
+        fn byte(&mut self) -> Option<u8> {
+            let (&b, rest) = self.bytes.split_first()?;
+            self.bytes = rest;
+            Some(b)
+        }
+
+        fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+            if n > self.bytes.len() {
+                return None;
+            }
+            let (res, rest) = self.bytes.split_at(n);
+            self.bytes = rest;
+            Some(res)
+        }
+
+        /// Small numbers are a single byte. Others are a byte holding the negated length,
+        /// followed by the number in big-endian order.
+        pub fn uint(&mut self) -> Option<u64> {
+            let b = self.byte()?;
+            if b < 0x80 {
+                return Some(u64::from(b));
+            }
+
+            let n = 256 - b as usize;
+            if n > 8 {
+                return None;
+            }
+            Some(self.take(n)?.iter().fold(0, |x, &b| x << 8 | u64::from(b)))
+        }
+
+        /// The sign is kept in the lowest bit.
+        pub fn int(&mut self) -> Option<i64> {
+            let u = self.uint()?;
+            if u & 1 == 1 {
+                Some(!(u >> 1) as i64)
+            } else {
+                Some((u >> 1) as i64)
+            }
+        }
+
+        /// Floats are sent as their bits in reversed byte order.
+        pub fn float(&mut self) -> Option<f64> {
+            Some(f64::from_bits(self.uint()?.swap_bytes()))
+        }
+
+        pub fn string(&mut self) -> Option<String> {
+            let n = self.uint()? as usize;
+            Some(String::from_utf8_lossy(self.take(n)?).into_owned())
+        }
+
+        pub fn slice<T, F>(&mut self, mut elem: F) -> Option<Vec<T>>
+        where
+            F: FnMut(&mut Gob<'a>) -> Option<T>,
+        {
+            let n = self.uint()?;
+            (0..n).map(|_| elem(self)).collect()
+        }
+
+        /// A map with string keys.
+        pub fn map<V, F>(&mut self, mut value: F) -> Option<HashMap<String, V>>
+        where
+            F: FnMut(&mut Gob<'a>) -> Option<V>,
+        {
+            let n = self.uint()?;
+            (0..n)
+                .map(|_| Some((self.string()?, value(self)?)))
+                .collect()
+        }
+    }
+
+    /// Decodes the first value in a gob stream, skipping type definitions.
+    pub fn decode<R, T, F>(mut reader: R, value: F) -> Result<T, SmolError>
+    where
+        R: Read,
+        F: FnOnce(&mut Gob) -> Option<T>,
+    {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .context(SmolErrorKind::Read)?;
+
+        first_value(&bytes)
+            .and_then(|mut g| value(&mut g))
+            .ok_or_else(|| SmolErrorKind::Deserialize.into())
+    }
+
+    fn first_value(bytes: &[u8]) -> Option<Gob<'_>> {
+        let mut stream = Gob { bytes };
+        loop {
+            let len = stream.uint()? as usize;
+            let mut message = Gob {
+                bytes: stream.take(len)?,
+            };
+
+            // Type definitions have negative IDs
+            if message.int()? > 0 {
+                // Values which aren't structs start with a zero field delta
+                if message.uint()? != 0 {
+                    return None;
+                }
+                return Some(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence() -> Vec<(String, String)> {
+        vec![
+            ("the".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let sentence = sentence();
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(vec![&sentence[..]], &mut TrainConfig::new().seed(0));
+
+        let mut json = Vec::new();
+        tagger.export_json(&mut json).unwrap();
+        let imported = PerceptronTagger::import_json(&json[..]).unwrap();
+
+        assert_eq!(
+            tagger.get_model().to_weights(),
+            imported.get_model().to_weights()
+        );
+        assert_eq!(tagger.get_tagdict(), imported.get_tagdict());
+        assert_eq!(tagger.get_corpus(), imported.get_corpus());
+    }
+
+    #[test]
+    fn nltk_import() {
+        let weights = r#"{
+            "bias": {"NN": 0.5, "DT": -0.5},
+            "i word the": {"DT": 2.0},
+            "i capitalization lower": {"DT": 100.0}
+        }"#;
+        let tagdict = r#"{"dog": "NN"}"#;
+        let classes = r#"["DT", "NN"]"#;
+
+        let tagger = PerceptronTagger::from_nltk_json(
+            weights.as_bytes(),
+            tagdict.as_bytes(),
+            classes.as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(2, tagger.get_model().num_features());
+        let sentence = sentence();
+        let tagged = tagger.tag(sentence_tokens(&sentence)).unwrap();
+        assert_eq!(
+            vec!["DT", "NN"],
+            tagged.iter().map(|x| &*x.1).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn gob_values() {
+        // A (skipped) type definition, followed by []string{"NN", "VB"} with type ID 65
+        let bytes = [
+            4, 0xff, 0x81, 0x01, 0x02, 10, 0xff, 0x82, 0x00, 2, 2, b'N', b'N', 2, b'V', b'B',
+        ];
+        let classes = gob::decode(&bytes[..], |g| g.slice(|g| g.string())).unwrap();
+        assert_eq!(vec!["NN", "VB"], classes);
+
+        // map[string]float64{"bias": 1.5}, where 1.5 is 0x3ff8000000000000 with reversed bytes
+        let bytes = [
+            12, 0xff, 0x82, 0x00, 1, 4, b'b', b'i', b'a', b's', 0xfe, 0xf8, 0x3f,
+        ];
+        let weights = gob::decode(&bytes[..], |g| g.map(|g| g.float())).unwrap();
+        assert_eq!(Some(&1.5), weights.get("bias"));
+
+        assert!(gob::decode(&[5, 0xff][..], |g| g.string()).is_err());
+    }
+}
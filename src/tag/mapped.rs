@@ -0,0 +1,479 @@
+//! A compact, memory-mapped format for perceptron tagger models.
+//!
+//! Loading a `PerceptronTagger` deserializes every weight into memory. A `MappedTagger` instead
+//! maps a model file and reads weights straight out of it, so opening a model is nearly instant
+//! no matter its size, and processes which map the same file share one copy of it through the OS
+//! page cache.
+//!
+//! A mapped model file holds a header, the feature templates, four string tables (classes, sorted
+//! features, and the words and tags of the sorted tag dictionary) and a dense table of weights,
+//! one row of class weights per feature. All integers and floats are little-endian.
+//!
+//! In version 1, weights are 64-bit floats. Version 2 stores quantized weights instead: the table
+//! starts with the width of a weight in bits (8 or 16, as a `u32` padded to 8 bytes) and the step
+//! between weights as a 64-bit float, followed by one signed integer per weight.
+
+use super::*;
+use super::format::read_all;
+use super::perceptron::quantization_scale;
+use error::*;
+
+use bincode::{deserialize, serialize, Infinite};
+use failure::ResultExt;
+use memmap::Mmap;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::str;
+
+const MAGIC: &[u8; 8] = b"SMOLMAP\0";
+const VERSION: u32 = 1;
+/// The version of files with quantized weights.
+const QUANTIZED_VERSION: u32 = 2;
+/// The size of the header: the magic bytes followed by five `u32`s.
+const HEADER: usize = 28;
+
+/// The bytes of a mapped model, whether they're mapped from a file or live in memory.
+type Bytes = Box<dyn Deref<Target = [u8]> + Send + Sync>;
+
+/// A sorted or unsorted table of strings inside a mapped model.
+///
+/// The table is stored as `len + 1` offsets into a blob of string bytes, followed by the blob.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Table {
+    len: usize,
+    offsets: usize,
+    blob: usize,
+}
+
+impl Table {
+    /// Parses a table starting at `start`, returning it and the position right after it.
+    fn parse(data: &[u8], start: usize, len: usize) -> Option<(Table, usize)> {
+        let blob = start.checked_add(len.checked_add(1)?.checked_mul(4)?)?;
+        let table = Table {
+            len,
+            offsets: start,
+            blob,
+        };
+        let end = blob.checked_add(table.offset(data, len)?)?;
+
+        if end > data.len() {
+            None
+        } else {
+            Some((table, end))
+        }
+    }
+
+    fn offset(&self, data: &[u8], i: usize) -> Option<usize> {
+        read_u32(data, self.offsets + i * 4).map(|x| x as usize)
+    }
+
+    fn get<'d>(&self, data: &'d [u8], i: usize) -> Option<&'d [u8]> {
+        let (a, b) = (self.offset(data, i)?, self.offset(data, i + 1)?);
+        data.get(self.blob + a..self.blob + b)
+    }
+
+    /// Finds the index of `key` in a sorted table.
+    fn find(&self, data: &[u8], key: &str) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.get(data, mid)?.cmp(key.as_bytes()) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+}
+
+/// A perceptron tagger which reads its model straight from a memory-mapped file.
+///
+/// Mapped models are written with `PerceptronTagger::save_mapped`. They keep the model and the tag
+/// dictionary of the tagger they were saved from, but not its beam width, tag mapping or lexicon
+/// of forced tags: mapped models always tag greedily, with the tags the model was trained on, and
+/// a lexicon has to be set again with `lexicon`. Quantized weights may also change the odd tag.
+/// Mapped models can't be trained any further.
+///
+/// # Examples
+/// ```rust,no_run
+/// # use smol::tag::*;
+/// let tagger = PerceptronTagger::load("model.bin").unwrap();
+/// tagger.save_mapped("model.map").unwrap();
+///
+/// let mut mapped = MappedTagger::open("model.map").unwrap();
+/// ```
+pub struct MappedTagger {
+    data: Bytes,
+    templates: TemplateSet,
+    classes: Vec<String>,
+    features: Table,
+    words: Table,
+    tags: Table,
+    weights: usize,
+    /// The width of a weight in bits, and the step between quantized weights.
+    bits: usize,
+    scale: f64,
+    lexicon: HashMap<String, String>,
+}
+
+impl MappedTagger {
+    /// Maps a model file written by `PerceptronTagger::save_mapped`.
+    ///
+    /// The file mustn't be modified while it's mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedTagger, SmolError> {
+        let f = File::open(path).context(SmolErrorKind::Read)?;
+        let data = unsafe { Mmap::map(&f) }.context(SmolErrorKind::Read)?;
+
+        MappedTagger::from_bytes(data)
+    }
+
+    /// Uses a model which is already in memory, such as one embedded with `include_bytes!`.
+    pub fn from_bytes<B>(bytes: B) -> Result<MappedTagger, SmolError>
+    where
+        B: Deref<Target = [u8]> + Send + Sync + 'static,
+    {
+        MappedTagger::parse(Box::new(bytes)).ok_or_else(|| SmolErrorKind::Deserialize.into())
+    }
+
+    fn parse(data: Bytes) -> Option<MappedTagger> {
+        let version = read_u32(&data, 8)?;
+        if data.get(..8)? != MAGIC || (version != VERSION && version != QUANTIZED_VERSION) {
+            return None;
+        }
+
+        let num_classes = read_u32(&data, 12)? as usize;
+        let num_features = read_u32(&data, 16)? as usize;
+        let num_tags = read_u32(&data, 20)? as usize;
+        let templates_len = read_u32(&data, 24)? as usize;
+
+        let templates = deserialize(data.get(HEADER..HEADER + templates_len)?).ok()?;
+        let (class_table, end) = Table::parse(&data, HEADER + templates_len, num_classes)?;
+        let (features, end) = Table::parse(&data, end, num_features)?;
+        let (words, end) = Table::parse(&data, end, num_tags)?;
+        let (tags, end) = Table::parse(&data, end, num_tags)?;
+
+        let (weights, bits, scale) = if version == VERSION {
+            (align(end), 64, 1.0)
+        } else {
+            let bits = read_u32(&data, align(end))? as usize;
+            if bits != 8 && bits != 16 {
+                return None;
+            }
+            (align(end) + 16, bits, read_f64(&data, align(end) + 8)?)
+        };
+        let size = num_features
+            .checked_mul(num_classes)?
+            .checked_mul(bits / 8)?;
+        if weights.checked_add(size)? > data.len() {
+            return None;
+        }
+
+        let classes = (0..num_classes)
+            .map(|c| {
+                class_table
+                    .get(&data, c)
+                    .and_then(|b| str::from_utf8(b).ok())
+                    .map(|s| s.to_owned())
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(MappedTagger {
+            data,
+            templates,
+            classes,
+            features,
+            words,
+            tags,
+            weights,
+            bits,
+            scale,
+            lexicon: HashMap::new(),
+        })
+    }
+
+    /// The width of a stored weight in bits: 64 for floats, or 8 or 16 for quantized weights.
+    pub fn weight_bits(&self) -> usize {
+        self.bits
+    }
+
+    /// Reads the weight at index `ix` of the weight table.
+    fn weight(&self, ix: usize) -> f64 {
+        let at = self.weights + ix * self.bits / 8;
+        match self.bits {
+            8 => self.data.get(at).map(|&b| b as i8 as f64 * self.scale),
+            16 => self.data.get(at..at + 2).map(|b| {
+                i16::from_le_bytes([b[0], b[1]]) as f64 * self.scale
+            }),
+            _ => read_f64(&self.data, at),
+        }.unwrap_or(0.0)
+    }
+
+    /// Sets words whose tags are forced, which take precedence over both the tag dictionary and
+    /// the model, like `PerceptronTagger::lexicon`.
+    pub fn lexicon(mut self, lexicon: HashMap<String, String>) -> MappedTagger {
+        self.lexicon = lexicon;
+        self
+    }
+
+    pub fn get_lexicon(&self) -> &HashMap<String, String> {
+        &self.lexicon
+    }
+
+    pub fn get_templates(&self) -> &TemplateSet {
+        &self.templates
+    }
+
+    /// The name of every class, indexed by class ID.
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    /// The number of features with weights.
+    pub fn num_features(&self) -> usize {
+        self.features.len
+    }
+
+    /// Looks a word up in the lexicon of forced tags, and then in the tag dictionary.
+    pub fn dictionary_tag(&self, word: &str) -> Option<&str> {
+        if let Some(tag) = self.lexicon.get(word) {
+            return Some(tag);
+        }
+
+        let i = self.words.find(&self.data, word)?;
+        self.tags
+            .get(&self.data, i)
+            .and_then(|b| str::from_utf8(b).ok())
+    }
+
+    /// The score of every class, indexed by class ID.
+    pub fn scores(&self, features: &Features) -> Vec<f64> {
+        let n = self.classes.len();
+        let mut scores = vec![0.0; n];
+        for f in features.iter() {
+            if let Some(row) = self.features.find(&self.data, f) {
+                for (c, s) in scores.iter_mut().enumerate() {
+                    *s += self.weight(row * n + c);
+                }
+            }
+        }
+        scores
+    }
+
+    pub fn pos<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        words: I,
+    ) -> Result<Vec<(Token<'a>, String)>, SmolError> {
+        tag_words(
+            words,
+            &self.templates,
+            |w| self.dictionary_tag(w).map(|t| t.to_owned()),
+            |f| {
+                best_class(&self.scores(f), &self.classes)
+                    .map(|c| self.classes[c].clone())
+                    .ok_or_else(|| SmolErrorKind::EmptyModel.into())
+            },
+        )
+    }
+
+    /// Tags words like `pos`, but returns up to `k` of the best tags for every word, best first.
+    pub fn top_k<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        words: I,
+        k: usize,
+    ) -> Result<Vec<(Token<'a>, Vec<ScoredTag>)>, SmolError> {
+        tag_words(
+            words,
+            &self.templates,
+            |w| self.dictionary_tag(w).map(|t| vec![ScoredTag::certain(t)]),
+            |f| match top_classes(&self.scores(f), &self.classes, k.max(1)) {
+                ref tags if tags.is_empty() => Err(SmolErrorKind::EmptyModel.into()),
+                tags => Ok(tags),
+            },
+        )
+    }
+}
+
+/// Mapped models are saved as they are. Loading one from a reader reads it into memory; use
+/// `MappedTagger::open` to map a file instead.
+impl Persist for MappedTagger {
+    fn save_to<W: Write>(&self, mut writer: W) -> Result<(), SmolError> {
+        writer.write_all(&self.data).context(SmolErrorKind::Write)?;
+        Ok(())
+    }
+
+    fn load_from<R: Read>(reader: R) -> Result<MappedTagger, SmolError> {
+        MappedTagger::from_bytes(read_all(reader)?)
+    }
+}
+
+impl Tagger for MappedTagger {
+    type Tag = String;
+
+    fn tag<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        tokens: I,
+    ) -> Result<Vec<(Token<'a>, Self::Tag)>, SmolError> {
+        self.pos(tokens)
+    }
+}
+
+/// Writes a model in the mapped format.
+pub(crate) fn write_mapped<W: Write>(
+    w: &mut W,
+    model: &AveragedPerceptron,
+    tags: &HashMap<String, String>,
+    templates: &TemplateSet,
+    quantization: Option<Quantization>,
+) -> Result<(), SmolError> {
+    let templates = serialize(templates, Infinite).context(SmolErrorKind::Serialize)?;
+
+    // Features without any weight never change a score, so they're left out
+    let mut features = model
+        .features()
+        .filter(|&(_, f)| model.weights(f).iter().any(|w| *w != 0.0))
+        .collect::<Vec<_>>();
+    features.sort();
+    let mut tags = tags.iter().collect::<Vec<_>>();
+    tags.sort();
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    let version = match quantization {
+        Some(_) => QUANTIZED_VERSION,
+        None => VERSION,
+    };
+    for x in &[
+        version as usize,
+        model.classes().len(),
+        features.len(),
+        tags.len(),
+        templates.len(),
+    ] {
+        push_u32(&mut buf, *x)?;
+    }
+    buf.extend_from_slice(&templates);
+
+    push_table(&mut buf, model.classes().iter().map(|c| &**c))?;
+    push_table(&mut buf, features.iter().map(|x| x.0))?;
+    push_table(&mut buf, tags.iter().map(|x| &**x.0))?;
+    push_table(&mut buf, tags.iter().map(|x| &**x.1))?;
+
+    let padded = align(buf.len());
+    buf.resize(padded, 0);
+    let weights = features.iter().flat_map(|&(_, f)| model.weights(f));
+
+    match quantization {
+        None => {
+            for w in weights {
+                buf.extend_from_slice(&w.to_bits().to_le_bytes());
+            }
+        }
+        Some(q) => {
+            let all = weights.cloned().collect::<Vec<_>>();
+            let scale = quantization_scale(&all, q.levels());
+            push_u32(&mut buf, q.bits())?;
+            push_u32(&mut buf, 0)?;
+            buf.extend_from_slice(&scale.to_bits().to_le_bytes());
+
+            for w in all {
+                let x = if scale > 0.0 { (w / scale).round() } else { 0.0 };
+                match q {
+                    Quantization::Bits8 => buf.push(x as i8 as u8),
+                    Quantization::Bits16 => buf.extend_from_slice(&(x as i16).to_le_bytes()),
+                }
+            }
+        }
+    }
+
+    w.write_all(&buf).context(SmolErrorKind::Write)?;
+
+    Ok(())
+}
+
+fn push_u32(buf: &mut Vec<u8>, x: usize) -> Result<(), SmolError> {
+    if x > u32::MAX as usize {
+        return Err(SmolErrorKind::Serialize.into());
+    }
+    buf.extend_from_slice(&(x as u32).to_le_bytes());
+    Ok(())
+}
+
+fn push_table<'s, I: Iterator<Item = &'s str> + Clone>(
+    buf: &mut Vec<u8>,
+    strings: I,
+) -> Result<(), SmolError> {
+    let mut offset = 0;
+    push_u32(buf, offset)?;
+    for s in strings.clone() {
+        offset += s.len();
+        push_u32(buf, offset)?;
+    }
+    for s in strings {
+        buf.extend_from_slice(s.as_bytes());
+    }
+    Ok(())
+}
+
+/// Rounds up to a multiple of 8, so that weights are aligned within the file.
+fn align(x: usize) -> usize {
+    x.div_ceil(8) * 8
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    let mut b = [0; 4];
+    b.copy_from_slice(data.get(at..at.checked_add(4)?)?);
+    Some(u32::from_le_bytes(b))
+}
+
+fn read_f64(data: &[u8], at: usize) -> Option<f64> {
+    let mut b = [0; 8];
+    b.copy_from_slice(data.get(at..at.checked_add(8)?)?);
+    Some(f64::from_bits(u64::from_le_bytes(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_matches_tagger() {
+        let sentences = vec![
+            vec![("the", "DT"), ("dog", "NN"), ("barks", "VBZ")],
+            vec![("a", "DT"), ("cat", "NN"), ("sleeps", "VBZ")],
+            vec![("dogs", "NNS"), ("bark", "VBP")],
+        ].into_iter()
+            .map(|s| {
+                s.into_iter()
+                    .map(|(w, t)| (w.to_owned(), t.to_owned()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(corpus.clone(), &mut TrainConfig::new().seed(5));
+
+        let mut bytes = Vec::new();
+        tagger.save_mapped_to(&mut bytes).unwrap();
+        let mapped = MappedTagger::from_bytes(bytes).unwrap();
+
+        assert_eq!(tagger.get_templates(), mapped.get_templates());
+        for sentence in corpus {
+            let expected = tagger.tag(sentence_tokens(sentence)).unwrap();
+            assert_eq!(expected, mapped.tag(sentence_tokens(sentence)).unwrap());
+        }
+    }
+
+    #[test]
+    fn mapped_rejects_garbage() {
+        let err = MappedTagger::from_bytes(b"not a model at all".to_vec())
+            .err()
+            .unwrap();
+        assert_eq!(SmolErrorKind::Deserialize, err.kind());
+    }
+}
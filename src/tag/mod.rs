@@ -1,19 +1,67 @@
+pub mod beam;
 pub mod corpus;
+pub mod crf;
+pub mod eval;
+pub mod features;
+pub mod format;
+pub mod hmm;
+pub mod interop;
+pub mod mapped;
+#[cfg(feature = "parallel")]
+pub mod parallel;
 pub mod perceptron;
+pub mod prune;
+pub mod split;
+pub mod tagset;
+pub mod train;
 
 // Re-exports
+pub use self::beam::*;
 pub use self::corpus::*;
+pub use self::crf::*;
+pub use self::eval::*;
+pub use self::features::*;
+pub use self::format::*;
+pub use self::hmm::*;
+pub use self::interop::*;
+pub use self::mapped::*;
 pub use self::perceptron::*;
+pub use self::prune::*;
+pub use self::split::*;
+pub use self::tagset::*;
+pub use self::train::*;
 
 use error::*;
 use tokenize::*;
 
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
 /// A part-of-speech tagger.
 pub trait Tagger {
     type Tag;
 
     fn tag<'a, I: IntoIterator<Item = Token<'a>>>(
-        &mut self,
+        &self,
         tokens: I,
     ) -> Result<Vec<(Token<'a>, Self::Tag)>, SmolError>;
+
+    /// Tags many sentences, returning their tags in the same order as the sentences. With the
+    /// `parallel` feature, the sentences are tagged at the same time on a thread pool.
+    #[allow(clippy::type_complexity)]
+    fn tag_batch<'a>(
+        &self,
+        sentences: Vec<Vec<Token<'a>>>,
+    ) -> Result<Vec<Vec<(Token<'a>, Self::Tag)>>, SmolError>
+    where
+        Self: Sync,
+        Self::Tag: Send,
+    {
+        #[cfg(feature = "parallel")]
+        let sentences = sentences.into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let sentences = sentences.into_iter();
+
+        sentences.map(|s| self.tag(s)).collect()
+    }
 }
@@ -0,0 +1,105 @@
+//! Training on a thread pool.
+//!
+//! Parallel training uses iterative parameter mixing (McDonald et al., 2010): every epoch, the
+//! training sentences are dealt out into shards, a copy of the model is trained on each shard at
+//! the same time, and the copies are mixed back into one model by averaging their weights. The
+//! shards and the order they're mixed in only depend on the order of the sentences, so training
+//! with a seed gives the same model however the threads happen to be scheduled.
+
+use super::*;
+
+use rayon::prelude::*;
+use std::mem;
+
+impl PerceptronTagger {
+    /// Makes a single pass over the training sentences, split into `shards` shards which are
+    /// trained on in parallel, returning the number of wrong guesses and the number of tokens seen.
+    pub(crate) fn train_epoch_parallel(
+        &mut self,
+        sentences: &[TaggedSentence],
+        shards: usize,
+        width: usize,
+    ) -> (usize, usize) {
+        let shards = shards.min(sentences.len()).max(1);
+        let results = (0..shards)
+            .into_par_iter()
+            .map(|shard| {
+                let sentences = sentences
+                    .iter()
+                    .skip(shard)
+                    .step_by(shards)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                // Training only needs the model, the tag dictionary and the templates
+                let mut tagger = PerceptronTagger::from_parts(
+                    self.get_model().clone(),
+                    self.get_tagdict().clone(),
+                    self.get_templates().clone(),
+                    CorpusInfo::default(),
+                );
+                let (errors, tokens) = tagger.train_epoch(&sentences, width);
+                (mem::take(tagger.model_mut()), errors, tokens)
+            })
+            .collect::<Vec<_>>();
+
+        let (errors, tokens) = results
+            .iter()
+            .fold((0, 0), |(errors, tokens), r| (errors + r.1, tokens + r.2));
+        let mut copies = results.into_iter().map(|r| r.0).collect::<Vec<_>>();
+        self.model_mut().mix(&mut copies);
+
+        (errors, tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus() -> Vec<Vec<(String, String)>> {
+        vec![
+            vec![("the", "DT"), ("dog", "NN"), ("runs", "VBZ")],
+            vec![("the", "DT"), ("runs", "NNS"), ("end", "VBP")],
+            vec![("a", "DT"), ("cat", "NN"), ("sleeps", "VBZ")],
+            vec![("cats", "NNS"), ("sleep", "VBP")],
+            vec![("a", "DT"), ("dog", "NN"), ("sleeps", "VBZ")],
+            vec![("dogs", "NNS"), ("run", "VBP")],
+        ]
+        .into_iter()
+        .map(|s| {
+            s.into_iter()
+                .map(|(w, t)| (w.to_owned(), t.to_owned()))
+                .collect()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn parallel_training() {
+        let sentences = corpus();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+        let train = |shards, width| {
+            let mut tagger = PerceptronTagger::new().beam_width(width);
+            let mut config = TrainConfig::new()
+                .iterations(10)
+                .seed(1)
+                .beam_width(width)
+                .shards(shards);
+            tagger.train_with(corpus.clone(), &mut config);
+            tagger
+        };
+
+        let serial = train(1, 1);
+        let parallel = train(3, 1);
+        assert_eq!(parallel, train(3, 1));
+        assert_ne!(serial.get_model(), parallel.get_model());
+
+        let evaluator = Evaluator::new();
+        for tagger in [serial, parallel, train(2, 4)] {
+            assert_eq!(
+                1.0,
+                evaluator.evaluate(&tagger, &corpus).unwrap().accuracy()
+            );
+        }
+    }
+}
@@ -3,27 +3,50 @@
 //! [prose](https://github.com/jdkato/prose/blob/master/tag/aptag.go).
 //!
 //! Based on [an algorithm by Matthew Honnibal](https://github.com/jdkato/prose/blob/master/tag/aptag.go)
+//!
+//! # Compatibility
+//!
+//! Training used to store weights under keys it never read back, so taggers trained before that
+//! was fixed only learned a tag dictionary and should be retrained. The `i+1 suffix` feature also
+//! used to hold the suffix of the previous word instead of the next one, as it does in NLTK, so
+//! weights learned for it by older models no longer match the features they're given.
 
 use error::*;
 use super::*;
+use super::beam::{self, Beam};
+use super::format::{decode, encode, now, read_all};
+use super::mapped::write_mapped;
+use super::split::seeded_rng;
 
-use bincode::{deserialize, serialize, Infinite};
+use bincode::deserialize;
 use failure::ResultExt;
-use itertools::Itertools;
 use rand::{thread_rng, Rng};
-use std::cmp::min;
-use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
 use std::fs::File;
 use std::path::Path;
+use std::time::Instant;
 
+/// An averaged perceptron over interned features and dense class IDs.
+///
+/// Every feature string is interned to a row of the weight table, which holds one weight per
+/// class. Features are only interned while training; unknown features are ignored when
+/// predicting.
 #[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
 pub struct AveragedPerceptron {
-    classes: HashSet<String>,
+    /// The name of every class, indexed by class ID.
+    classes: Vec<String>,
+    /// The row of every feature in the weight table.
+    features: HashMap<String, usize>,
     instances: usize,
-    stamps: HashMap<String, f64>,
-    totals: HashMap<String, f64>,
-    weights: HashMap<String, HashMap<String, f64>>,
+    /// One row of `classes.len()` weights per feature.
+    weights: Vec<f64>,
+    /// Accumulated weights used for averaging, laid out like `weights`.
+    totals: Vec<f64>,
+    /// The instance at which each weight was last changed, laid out like `weights`.
+    stamps: Vec<usize>,
 }
 
 impl AveragedPerceptron {
@@ -31,129 +54,542 @@ impl AveragedPerceptron {
         AveragedPerceptron::default()
     }
 
-    pub fn weights(mut self, weights: HashMap<String, HashMap<String, f64>>) -> AveragedPerceptron {
-        self.weights = weights;
-        self
+    /// Creates a model from a map of features to the weight of each class.
+    pub fn from_weights(
+        weights: HashMap<String, HashMap<String, f64>>,
+        classes: HashSet<String>,
+    ) -> AveragedPerceptron {
+        let mut res = AveragedPerceptron::new();
+
+        // Sort everything, so that the same weights always lead to the same IDs
+        let mut classes = classes
+            .into_iter()
+            .chain(weights.values().flat_map(|w| w.keys().cloned()))
+            .collect::<Vec<_>>();
+        classes.sort();
+        classes.dedup();
+        for c in classes {
+            res.add_class(&c);
+        }
+
+        let mut weights = weights.into_iter().collect::<Vec<_>>();
+        weights.sort_by(|a, b| a.0.cmp(&b.0));
+        let n = res.classes.len();
+        for (feat, ws) in weights {
+            let row = res.intern(&feat) * n;
+            for (class, w) in ws {
+                let c = res.class_id(&class).unwrap();
+                res.weights[row + c] = w;
+            }
+        }
+
+        res
     }
 
-    pub fn classes(mut self, classes: HashSet<String>) -> AveragedPerceptron {
-        self.classes = classes;
-        self
+    /// Returns the non-zero weights of every feature, by class.
+    pub fn to_weights(&self) -> BTreeMap<String, BTreeMap<String, f64>> {
+        let n = self.classes.len();
+        self.features
+            .iter()
+            .map(|(feat, &row)| {
+                let ws = self.weights[row * n..(row + 1) * n]
+                    .iter()
+                    .enumerate()
+                    .filter(|x| *x.1 != 0.0)
+                    .map(|(c, w)| (self.classes[c].clone(), *w))
+                    .collect::<BTreeMap<_, _>>();
+                (feat.clone(), ws)
+            })
+            .filter(|x| !x.1.is_empty())
+            .collect()
+    }
+
+    /// The name of every class, indexed by class ID.
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    pub fn class_id(&self, class: &str) -> Option<usize> {
+        self.classes.iter().position(|c| c == class)
+    }
+
+    /// Adds a class if it doesn't exist yet, returning its ID.
+    pub fn add_class(&mut self, class: &str) -> usize {
+        if let Some(c) = self.class_id(class) {
+            return c;
+        }
+
+        let n = self.classes.len();
+        let rows = self.features.len();
+        let widen = |v: &[f64]| {
+            v.chunks(n)
+                .flat_map(|row| row.iter().cloned().chain(Some(0.0)))
+                .collect::<Vec<_>>()
+        };
+
+        if n == 0 {
+            self.weights = vec![0.0; rows];
+            self.totals = vec![0.0; rows];
+            self.stamps = vec![0; rows];
+        } else {
+            self.weights = widen(&self.weights);
+            self.totals = widen(&self.totals);
+            self.stamps = self.stamps
+                .chunks(n)
+                .flat_map(|row| row.iter().cloned().chain(Some(0)))
+                .collect();
+        }
+
+        self.classes.push(class.to_owned());
+        n
     }
 
-    pub fn predict(&self, features: &HashMap<String, f64>) -> Result<String, SmolError> {
-        let mut scores: HashMap<String, f64> = HashMap::new();
-        for (feat, val) in features {
-            if self.weights.get(feat).is_none() || *val == 0.0 {
+    /// The number of interned features.
+    pub fn num_features(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn feature_id(&self, feature: &str) -> Option<usize> {
+        self.features.get(feature).cloned()
+    }
+
+    /// Interns a feature if it doesn't exist yet, returning its ID.
+    pub fn intern(&mut self, feature: &str) -> usize {
+        if let Some(&f) = self.features.get(feature) {
+            return f;
+        }
+
+        let f = self.features.len();
+        let n = self.classes.len();
+        self.features.insert(feature.to_owned(), f);
+        self.weights.extend((0..n).map(|_| 0.0));
+        self.totals.extend((0..n).map(|_| 0.0));
+        self.stamps.extend((0..n).map(|_| 0));
+        f
+    }
+
+    /// Every interned feature and its ID, in no particular order.
+    pub fn features(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.features.iter().map(|(f, &id)| (&**f, id))
+    }
+
+    /// The weight of every class for a feature.
+    pub fn weights(&self, feature: usize) -> &[f64] {
+        let n = self.classes.len();
+        &self.weights[feature * n..(feature + 1) * n]
+    }
+
+    /// The number of non-zero weights.
+    pub fn num_weights(&self) -> usize {
+        self.weights.iter().filter(|w| **w != 0.0).count()
+    }
+
+    /// Zeroes every weight for which `keep`, given its feature ID, class ID and value, returns
+    /// false. Features left without any weights are then dropped, which changes feature IDs.
+    pub fn retain<F: FnMut(usize, usize, f64) -> bool>(&mut self, mut keep: F) {
+        let n = self.classes.len();
+        if n == 0 {
+            return;
+        }
+
+        for (ix, w) in self.weights.iter_mut().enumerate() {
+            if *w != 0.0 && !keep(ix / n, ix % n, *w) {
+                *w = 0.0;
+            }
+        }
+
+        let mut rows = self.features
+            .iter()
+            .map(|(f, &id)| (id, f.clone()))
+            .collect::<Vec<_>>();
+        rows.sort();
+
+        let mut features = HashMap::new();
+        let (mut weights, mut totals, mut stamps) = (Vec::new(), Vec::new(), Vec::new());
+        for (id, f) in rows {
+            let row = id * n..(id + 1) * n;
+            if self.weights[row.clone()].iter().all(|w| *w == 0.0) {
                 continue;
             }
-            let weights = &self.weights[feat];
-            for (label, weight) in weights {
-                scores
-                    .get_mut(label)
-                    .map(|w| *w += (*weight as f64 * val) as f64)
-                    .unwrap_or_else(|| {
-                        scores.insert(label.to_owned(), (*weight as f64 * val) as f64);
-                    });
+
+            features.insert(f, features.len());
+            weights.extend_from_slice(&self.weights[row.clone()]);
+            totals.extend_from_slice(&self.totals[row.clone()]);
+            stamps.extend_from_slice(&self.stamps[row]);
+        }
+
+        self.features = features;
+        self.weights = weights;
+        self.totals = totals;
+        self.stamps = stamps;
+    }
+
+    /// Rounds every weight to a multiple of the largest weight divided by `levels`, so that it can
+    /// be stored as an integer from `-levels` to `levels`. Returns the multiple.
+    pub fn quantize(&mut self, levels: usize) -> f64 {
+        let scale = quantization_scale(&self.weights, levels);
+        if scale > 0.0 {
+            for w in &mut self.weights {
+                *w = (*w / scale).round() * scale;
             }
         }
+        scale
+    }
+
+    /// Replaces the contents of `ids` with the IDs of all known features.
+    pub fn lookup(&self, features: &Features, ids: &mut Vec<usize>) {
+        ids.clear();
+        ids.extend(features.iter().filter_map(|f| self.feature_id(f)));
+    }
+
+    /// Replaces the contents of `ids` with the IDs of all features, interning unknown ones.
+    pub fn intern_all(&mut self, features: &Features, ids: &mut Vec<usize>) {
+        ids.clear();
+        for f in features.iter() {
+            ids.push(self.intern(f));
+        }
+    }
 
+    /// The score of every class, indexed by class ID.
+    pub fn scores(&self, features: &[usize]) -> Vec<f64> {
+        let n = self.classes.len();
+        let mut scores = vec![0.0; n];
+        for &f in features {
+            for (s, w) in scores.iter_mut().zip(&self.weights[f * n..(f + 1) * n]) {
+                *s += w;
+            }
+        }
         scores
-            .iter()
-            .map(|i| ((i.1 * 100000.0) as isize, i.0))
-            .max()
-            .map(|x| x.1.clone())
+    }
+
+    /// Returns the ID of the highest scoring class.
+    pub fn predict(&self, features: &[usize]) -> Result<usize, SmolError> {
+        best_class(&self.scores(features), &self.classes)
             .ok_or_else(|| SmolErrorKind::EmptyModel.into())
     }
 
-    pub fn update(&mut self, truth: &str, guess: &str, features: &HashMap<String, f64>) {
+    /// The probability of every class, indexed by class ID.
+    pub fn probabilities(&self, features: &[usize]) -> Vec<f64> {
+        softmax(&self.scores(features))
+    }
+
+    /// Returns the `k` highest scoring classes, best first.
+    pub fn top_k(&self, features: &[usize], k: usize) -> Vec<ScoredTag> {
+        top_classes(&self.scores(features), &self.classes, k)
+    }
+
+    /// Counts `n` more training instances, without changing any weights.
+    pub fn advance(&mut self, n: usize) {
+        self.instances += n;
+    }
+
+    /// Adds `delta` to the weight of a class for every feature.
+    pub fn adjust(&mut self, class: usize, features: &[usize], delta: f64) {
+        let n = self.classes.len();
+        for &f in features {
+            self.update_feat(f * n + class, delta);
+        }
+    }
+
+    pub fn update(&mut self, truth: usize, guess: usize, features: &[usize]) {
         self.instances += 1;
         if truth == guess {
             return;
         }
 
-        for f in features.keys() {
-            self.weights
-                .get(f)
-                .and_then(|weights| {
-                    Some((
-                        *weights.get(truth).unwrap_or(&0.0),
-                        *weights.get(guess).unwrap_or(&0.0),
-                    ))
-                })
-                .and_then(|weights| {
-                    self.update_feat(truth, f.as_ref(), weights.0, 1.0);
-                    self.update_feat(guess, f.as_ref(), weights.1, -1.0);
-                    Some(())
-                })
-                .or_else(|| {
-                    self.update_feat(truth, f.as_ref(), 0.0, 1.0);
-                    self.update_feat(guess, f.as_ref(), 0.0, -1.0);
-                    Some(())
-                });
+        let n = self.classes.len();
+        for &f in features {
+            self.update_feat(f * n + truth, 1.0);
+            self.update_feat(f * n + guess, -1.0);
         }
     }
 
     pub fn average_weights(&mut self) {
-        let stamps = &mut self.stamps;
-        for (feat, weights) in &mut self.weights {
-            let mut new: HashMap<String, f64> = HashMap::new();
-            for (class, weight) in weights.clone() {
-                let key = format!("{}-{}", feat, class);
-                let delta = stamps
-                    .get_mut(&key)
-                    .and_then(|v| Some(*v))
-                    .or_else(|| {
-                        let v = stamps.insert(key.to_owned(), 0.0).unwrap();
-                        Some(v)
-                    })
-                    .unwrap();
-
-                let total = self.totals.entry(key).or_insert(0.0);
-                *total += (self.instances as f64 - delta) * weight;
-                let averaged = (*total / (self.instances as f64) * 1000.0).round() / 1000.0;
-                if averaged != 0.0 {
-                    new.insert(class.to_string(), averaged);
+        if self.instances == 0 {
+            return;
+        }
+
+        self.flush();
+        let instances = self.instances as f64;
+        for (w, total) in self.weights.iter_mut().zip(&self.totals) {
+            *w = (*total / instances * 1000.0).round() / 1000.0;
+        }
+    }
+
+    /// Folds in copies of this model which were trained separately, as in iterative parameter
+    /// mixing: every weight becomes the mean of the copies' weights, and the totals add up what
+    /// each copy accumulated, so that averaging covers every instance any copy saw. Features and
+    /// classes new to this model are added in the order of the copies.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn mix(&mut self, copies: &mut [AveragedPerceptron]) {
+        if copies.is_empty() {
+            return;
+        }
+
+        self.flush();
+        let base = self.clone();
+        let n = base.classes.len();
+        let k = copies.len() as f64;
+
+        for copy in copies.iter_mut() {
+            copy.flush();
+            let classes = copy.classes
+                .iter()
+                .map(|c| self.add_class(c))
+                .collect::<Vec<_>>();
+            // Go through features by ID, so that new features are always interned in the same order
+            let mut rows = copy.features
+                .iter()
+                .map(|(f, &row)| (row, &**f))
+                .collect::<Vec<_>>();
+            rows.sort_unstable();
+
+            for (row, feature) in rows {
+                let to = self.intern(feature);
+                let from = base.feature_id(feature);
+                for (c, &class) in classes.iter().enumerate() {
+                    let ix = row * classes.len() + c;
+                    // The copies started out as this model, so they share its classes
+                    let (w, total) = match from {
+                        Some(f) if c < n => (base.weights[f * n + c], base.totals[f * n + c]),
+                        _ => (0.0, 0.0),
+                    };
+                    let to = to * self.classes.len() + class;
+                    self.weights[to] += (copy.weights[ix] - w) / k;
+                    self.totals[to] += copy.totals[ix] - total;
                 }
             }
-            *weights = new;
+            self.instances += copy.instances - base.instances;
+        }
+
+        let instances = self.instances;
+        for stamp in &mut self.stamps {
+            *stamp = instances;
         }
     }
 
-    fn update_feat(&mut self, c: &str, f: &str, v: f64, w: f64) {
-        let key = format!("{}-{}", c, f);
+    /// Brings the totals up to date with the current instance.
+    fn flush(&mut self) {
+        let instances = self.instances;
+        for ((w, total), stamp) in self.weights
+            .iter()
+            .zip(&mut self.totals)
+            .zip(&mut self.stamps)
+        {
+            *total += (instances - *stamp) as f64 * *w;
+            *stamp = instances;
+        }
+    }
 
-        // TODO: Right now we're accessing the HashMap twice for everything so that we don't have
-        // to constantly copy strings
-        // Maybe there's a better way...
-        self.totals
-            .get_mut(&key)
-            .and_then(|_| Some(()))
-            .or_else(|| {
-                self.totals.insert(key.to_owned(), 0.0);
-                Some(())
-            });
+    /// Adds `w` to the weight at index `ix` of the weight table.
+    fn update_feat(&mut self, ix: usize, w: f64) {
+        // The total accumulates the weight for every instance since it was last changed
+        self.totals[ix] += (self.instances - self.stamps[ix]) as f64 * self.weights[ix];
+        self.stamps[ix] = self.instances;
+        self.weights[ix] += w;
+    }
+}
 
-        let delta = self.stamps
-            .get_mut(&key)
-            .and_then(|v| Some(*v))
-            .or_else(|| {
-                let v = self.stamps.insert(key.to_owned(), 0.0).unwrap();
-                Some(v)
-            })
-            .unwrap();
-
-        *self.totals.get_mut(&key).unwrap() += (self.instances as f64 - delta) * w;
-        *self.stamps.get_mut(&key).unwrap() = self.instances as f64;
-        self.weights
-            .entry(key)
-            .or_insert_with(HashMap::new)
-            .get_mut(c)
-            .and_then(|val| {
-                *val = w + v;
-                Some(())
-            })
-            .or_else(|| Some(()));
+/// The step between quantized weights, or 0 if every weight is 0.
+pub(crate) fn quantization_scale(weights: &[f64], levels: usize) -> f64 {
+    let max = weights.iter().fold(0.0f64, |m, w| m.max(w.abs()));
+    if levels == 0 {
+        0.0
+    } else {
+        max / levels as f64
+    }
+}
+
+/// A candidate tag for a word.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct ScoredTag {
+    pub tag: String,
+    /// The raw score of the tag. Tags from the tag dictionary have an infinite score.
+    pub score: f64,
+    /// The softmax of the score over every tag, between 0 and 1.
+    pub confidence: f64,
+}
+
+impl ScoredTag {
+    /// A tag from the tag dictionary.
+    pub(crate) fn certain(tag: &str) -> ScoredTag {
+        ScoredTag {
+            tag: tag.to_owned(),
+            score: f64::INFINITY,
+            confidence: 1.0,
+        }
+    }
+}
+
+/// Normalizes scores into probabilities which sum to 1.
+pub fn softmax(scores: &[f64]) -> Vec<f64> {
+    let max = scores.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let exps = scores.iter().map(|s| (s - max).exp()).collect::<Vec<_>>();
+    let sum = exps.iter().sum::<f64>();
+    exps.into_iter().map(|e| e / sum).collect()
+}
+
+/// Compares two classes by their score, breaking ties by name.
+fn rank(scores: &[f64], classes: &[String], a: usize, b: usize) -> Ordering {
+    scores[a]
+        .partial_cmp(&scores[b])
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| classes[a].cmp(&classes[b]))
+}
+
+/// Returns the ID of the highest scoring class, breaking ties by name.
+pub(crate) fn best_class(scores: &[f64], classes: &[String]) -> Option<usize> {
+    (0..scores.len()).max_by(|&a, &b| rank(scores, classes, a, b))
+}
+
+/// Returns the `k` highest scoring classes, best first.
+pub(crate) fn top_classes(scores: &[f64], classes: &[String], k: usize) -> Vec<ScoredTag> {
+    let confidence = softmax(scores);
+    let mut ids = (0..scores.len()).collect::<Vec<_>>();
+    ids.sort_by(|&a, &b| rank(scores, classes, b, a));
+
+    ids.into_iter()
+        .take(k)
+        .map(|c| ScoredTag {
+            tag: classes[c].clone(),
+            score: scores[c],
+            confidence: confidence[c],
+        })
+        .collect()
+}
+
+/// The output of tagging a single word, which determines the tag its neighbors see.
+pub(crate) trait Tagged {
+    fn tag(&self) -> &str;
+}
+
+impl Tagged for String {
+    fn tag(&self) -> &str {
+        self
+    }
+}
+
+/// Candidates are ordered best first, and there's always at least one.
+impl Tagged for Vec<ScoredTag> {
+    fn tag(&self) -> &str {
+        &self[0].tag
+    }
+}
+
+/// Tags a sentence word by word, looking each word up with `dict` and predicting the tag of
+/// words it doesn't know from their features.
+pub(crate) fn tag_words<'a, I, T, D, P>(
+    words: I,
+    templates: &TemplateSet,
+    mut dict: D,
+    mut predict: P,
+) -> Result<Vec<(Token<'a>, T)>, SmolError>
+where
+    I: IntoIterator<Item = Token<'a>>,
+    T: Tagged,
+    D: FnMut(&str) -> Option<T>,
+    P: FnMut(&Features) -> Result<T, SmolError>,
+{
+    let clean = words.into_iter();
+
+    let mut context = vec![
+        "-START-".to_owned(),
+        "-START2-".to_owned(),
+        "-END-".to_owned(),
+        "-END2-".to_owned(),
+    ];
+    let mut c = Vec::new();
+
+    for (ix, i) in (2..).zip(clean) {
+        context.insert(ix, PerceptronTagger::normalize_str(&i.term));
+        c.push(i);
+    }
+
+    let (mut p1, mut p2) = ("-START-".to_owned(), "-START2-".to_owned());
+    let mut features = Features::new();
+
+    let mut res = Vec::with_capacity(c.len());
+
+    for (i, word) in c.into_iter().enumerate() {
+        let tag = match dict(&word.term) {
+            Some(s) => s,
+            None => {
+                let ctx = FeatureContext {
+                    i,
+                    word: &word.term,
+                    context: &context[..],
+                    p1: &p1,
+                    p2: &p2,
+                    pos: &[],
+                };
+                features.clear();
+                templates.extract(&ctx, &mut features);
+                predict(&features)?
+            }
+        };
+
+        p2 = p1;
+        p1 = tag.tag().to_owned();
+        res.push((word, tag));
+    }
+
+    Ok(res)
+}
+
+/// Where the tag of a word came from.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize)]
+pub enum TagSource {
+    /// The user's lexicon.
+    Lexicon,
+    /// The tag dictionary built during training, for frequent and unambiguous words.
+    Dictionary,
+    /// The model's prediction.
+    Model,
+}
+
+/// Reads a lexicon of forced tags, with a word and its tag on every line, separated by
+/// whitespace. Blank lines and lines starting with `#` are skipped.
+pub fn read_lexicon<R: Read>(reader: R) -> Result<HashMap<String, String>, SmolError> {
+    let mut res = HashMap::new();
+
+    for line in BufReader::new(reader).lines() {
+        let line = line.context(SmolErrorKind::Read)?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(word), Some(tag), None) => {
+                res.insert(word.to_owned(), tag.to_owned());
+            }
+            _ => return Err(SmolErrorKind::Parse.into()),
+        }
+    }
+
+    Ok(res)
+}
+
+/// Everything a tagger needs to carry on training where it left off, which is lost once its
+/// weights are averaged.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct TrainingState {
+    /// The model before averaging, along with the totals averaging needs.
+    model: AveragedPerceptron,
+    /// How often every word was seen with every tag, from which the tag dictionary is built.
+    counts: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+impl TrainingState {
+    /// The model before averaging.
+    pub fn get_model(&self) -> &AveragedPerceptron {
+        &self.model
+    }
+
+    /// How often every word was seen with every tag.
+    pub fn get_counts(&self) -> &BTreeMap<String, BTreeMap<String, usize>> {
+        &self.counts
     }
 }
 
@@ -161,6 +597,18 @@ impl AveragedPerceptron {
 pub struct PerceptronTagger {
     model: AveragedPerceptron,
     tags: HashMap<String, String>,
+    features: TemplateSet,
+    corpus: CorpusInfo,
+    #[serde(default)]
+    beam_width: usize,
+    #[serde(default)]
+    mapping: Option<(TagsetName, TagsetName)>,
+    #[serde(skip)]
+    state: Option<TrainingState>,
+    #[serde(skip)]
+    lexicon: HashMap<String, String>,
+    #[serde(default)]
+    quantization: Option<Quantization>,
 }
 
 impl PerceptronTagger {
@@ -168,138 +616,622 @@ impl PerceptronTagger {
         PerceptronTagger::default()
     }
 
-    pub fn save(&self, path: &str) -> Result<(), SmolError> {
-        let s = serialize(
-            &(&self.model.weights, &self.tags, &self.model.classes),
-            Infinite,
-        ).context(SmolErrorKind::Serialize)?;
+    /// Sets the features used by the tagger. This should be done before training, since the
+    /// weights of a trained model only make sense with the features it was trained with.
+    pub fn templates(mut self, features: TemplateSet) -> PerceptronTagger {
+        self.features = features;
+        self
+    }
+
+    pub fn get_templates(&self) -> &TemplateSet {
+        &self.features
+    }
 
-        let p = Path::new(path);
-        let mut f = File::create(p).context(SmolErrorKind::Write)?;
+    /// Sets the number of tag sequences kept while tagging. Widths of 0 and 1 both mean greedy
+    /// decoding, which is the default. The width isn't saved with the model.
+    pub fn beam_width(mut self, width: usize) -> PerceptronTagger {
+        self.beam_width = width;
+        self
+    }
 
-        f.write_all(&s).context(SmolErrorKind::Write)?;
+    pub fn get_beam_width(&self) -> usize {
+        self.beam_width
+    }
+
+    /// Maps the tags output by `pos` from the tagset the tagger was trained on to another one,
+    /// e.g. from PTB to UD. Tags without a counterpart are output unchanged. Like the beam width,
+    /// the mapping isn't saved with the model.
+    pub fn map_tags(mut self, from: TagsetName, to: TagsetName) -> PerceptronTagger {
+        self.mapping = Some((from, to));
+        self
+    }
+
+    pub fn get_tag_mapping(&self) -> Option<(TagsetName, TagsetName)> {
+        self.mapping
+    }
+
+    /// Sets the name of the training corpus, which is saved along with the model.
+    pub fn corpus_name(mut self, name: &str) -> PerceptronTagger {
+        self.corpus.name = Some(name.to_owned());
+        self
+    }
+
+    /// Information about the corpus the tagger was last trained on.
+    pub fn get_corpus(&self) -> &CorpusInfo {
+        &self.corpus
+    }
+
+    pub fn get_model(&self) -> &AveragedPerceptron {
+        &self.model
+    }
+
+    /// The words which are always given the same tag, without consulting the model.
+    pub fn get_tagdict(&self) -> &HashMap<String, String> {
+        &self.tags
+    }
+
+    /// Sets words whose tags are forced, like product names which should always be `NNP`. The
+    /// lexicon takes precedence over both the tag dictionary and the model. Like the beam width, it
+    /// isn't saved with the model in any format, so set it again after loading a model.
+    pub fn lexicon(mut self, lexicon: HashMap<String, String>) -> PerceptronTagger {
+        self.lexicon = lexicon;
+        self
+    }
+
+    pub fn get_lexicon(&self) -> &HashMap<String, String> {
+        &self.lexicon
+    }
+
+    /// Where the tag of a word comes from when tagging it.
+    pub fn tag_source(&self, word: &str) -> TagSource {
+        if self.lexicon.contains_key(word) {
+            TagSource::Lexicon
+        } else if self.tags.contains_key(word) {
+            TagSource::Dictionary
+        } else {
+            TagSource::Model
+        }
+    }
+
+    /// The state training can be resumed from, if the tagger was trained with
+    /// `TrainConfig::keep_state` or loaded from a checkpoint.
+    pub fn get_training_state(&self) -> Option<&TrainingState> {
+        self.state.as_ref()
+    }
+
+    /// Saves the tagger along with its training state, so that training can be resumed exactly
+    /// where it left off. A checkpoint can also be loaded as a regular tagger.
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), SmolError> {
+        let f = File::create(path).context(SmolErrorKind::Write)?;
+        let mut w = BufWriter::new(f);
+
+        self.save_checkpoint_to(&mut w)?;
+        w.flush().context(SmolErrorKind::Write)?;
 
         Ok(())
     }
 
-    pub fn load(path: &str) -> Result<PerceptronTagger, SmolError> {
-        let p = Path::new(path);
-        let mut f = File::open(p).context(SmolErrorKind::Write)?;
+    /// Writes the tagger and its training state, failing if it has no training state.
+    pub fn save_checkpoint_to<W: Write>(&self, mut writer: W) -> Result<(), SmolError> {
+        let state = self.state.as_ref().ok_or(SmolErrorKind::MissingState)?;
+        let weights = self.model.to_weights();
+        let tags = self.tags.iter().collect::<BTreeMap<_, _>>();
 
-        let mut s = String::new();
-        f.read_to_string(&mut s).context(SmolErrorKind::Write)?;
-        let (weights, tags, classes) =
-            deserialize(s.as_bytes()).context(SmolErrorKind::Deserialize)?;
+        // The state goes last, so that the start of a checkpoint is a regular model
+        let s = encode(&(self.metadata(), weights, tags, state))?;
+        writer.write_all(&s).context(SmolErrorKind::Write)?;
 
-        let m = AveragedPerceptron::new().weights(weights).classes(classes);
+        Ok(())
+    }
+
+    /// Loads a tagger saved with `save_checkpoint`, along with its training state.
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<PerceptronTagger, SmolError> {
+        let f = File::open(path).context(SmolErrorKind::Read)?;
+        PerceptronTagger::load_checkpoint_from(BufReader::new(f))
+    }
+
+    /// Reads a tagger written with `save_checkpoint_to`, along with its training state.
+    pub fn load_checkpoint_from<R: Read>(reader: R) -> Result<PerceptronTagger, SmolError> {
+        let bytes = read_all(reader)?;
+        let body = decode(&bytes)?.ok_or(SmolErrorKind::Version)?;
+        let (metadata, weights, tags, state): (ModelMetadata, _, _, TrainingState) =
+            deserialize(body).context(SmolErrorKind::MissingState)?;
+
+        let m = AveragedPerceptron::from_weights(weights, metadata.tagset.into_iter().collect());
+        let mut tagger = PerceptronTagger::from_parts(m, tags, metadata.templates, metadata.corpus);
+        tagger.state = Some(state);
+
+        Ok(tagger)
+    }
+
+    pub(crate) fn from_parts(
+        model: AveragedPerceptron,
+        tags: HashMap<String, String>,
+        features: TemplateSet,
+        corpus: CorpusInfo,
+    ) -> PerceptronTagger {
+        PerceptronTagger {
+            model,
+            tags,
+            features,
+            corpus,
+            beam_width: 0,
+            mapping: None,
+            state: None,
+            lexicon: HashMap::new(),
+            quantization: None,
+        }
+    }
+
+    /// Describes the tagger, as it would be saved right now.
+    pub fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            templates: self.features.clone(),
+            tagset: self.model
+                .classes()
+                .iter()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .cloned()
+                .collect(),
+            corpus: self.corpus.clone(),
+            date: now(),
+        }
+    }
+
+    /// Saves the tagger in a compact format which can be memory-mapped by a `MappedTagger`. The
+    /// beam width and tag mapping aren't saved.
+    pub fn save_mapped<P: AsRef<Path>>(&self, path: P) -> Result<(), SmolError> {
+        let f = File::create(path).context(SmolErrorKind::Write)?;
+        let mut w = BufWriter::new(f);
+
+        self.save_mapped_to(&mut w)?;
+        w.flush().context(SmolErrorKind::Write)?;
+
+        Ok(())
+    }
+
+    /// Writes the tagger in the format read by `MappedTagger`.
+    pub fn save_mapped_to<W: Write>(&self, mut writer: W) -> Result<(), SmolError> {
+        write_mapped(
+            &mut writer,
+            &self.model,
+            &self.tags,
+            &self.features,
+            self.quantization,
+        )
+    }
+
+    /// Sets how weights are stored when saving in the mapped format. By default they're stored
+    /// as 64-bit floats. Like the beam width, this isn't saved with the model.
+    pub fn quantization(mut self, quantization: Option<Quantization>) -> PerceptronTagger {
+        self.quantization = quantization;
+        self
+    }
+
+    pub fn get_quantization(&self) -> Option<Quantization> {
+        self.quantization
+    }
 
-        let p = PerceptronTagger {
-            model: m,
-            tags: tags,
+    pub(crate) fn model_mut(&mut self) -> &mut AveragedPerceptron {
+        &mut self.model
+    }
+
+    /// Reads a bare bincode model, as saved before model files had a header.
+    #[allow(clippy::type_complexity)]
+    fn load_legacy(
+        bytes: &[u8],
+    ) -> Result<
+        (
+            ModelMetadata,
+            HashMap<String, HashMap<String, f64>>,
+            HashMap<String, String>,
+        ),
+        SmolError,
+    > {
+        // Models saved before feature templates existed always use the default features
+        let (weights, tags, classes, templates): (_, _, HashSet<String>, _) = deserialize(bytes)
+            .or_else(|_| {
+                deserialize(bytes)
+                    .map(|(weights, tags, classes)| (weights, tags, classes, TemplateSet::default()))
+            })
+            .context(SmolErrorKind::Deserialize)?;
+
+        let metadata = ModelMetadata {
+            templates,
+            tagset: classes.into_iter().collect(),
+            corpus: CorpusInfo::default(),
+            date: 0,
         };
 
-        Ok(p)
+        Ok((metadata, weights, tags))
     }
 
     pub fn pos<'a, I: IntoIterator<Item = Token<'a>>>(
-        &mut self,
+        &self,
         words: I,
     ) -> Result<Vec<(Token<'a>, String)>, SmolError> {
-        let clean = words.into_iter();
+        let mut res = if self.beam_width > 1 {
+            self.pos_beam(words)?
+        } else {
+            self.pos_greedy(words)?
+        };
 
-        let mut context = vec![
-            "-START-".to_owned(),
-            "-START2-".to_owned(),
-            "-END-".to_owned(),
-            "-END2-".to_owned(),
-        ];
-        let mut c = Vec::new();
-        let mut ix = 2;
+        if let Some((from, to)) = self.mapping {
+            for x in &mut res {
+                if let Some(t) = from.map(&x.1, to) {
+                    x.1 = t;
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn pos_greedy<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        words: I,
+    ) -> Result<Vec<(Token<'a>, String)>, SmolError> {
+        let model = &self.model;
+        let mut ids = Vec::new();
+
+        tag_words(
+            words,
+            &self.features,
+            |w| self.lexicon.get(w).or_else(|| self.tags.get(w)).cloned(),
+            |f| {
+                model.lookup(f, &mut ids);
+                let c = model.predict(&ids)?;
+                Ok(model.classes()[c].clone())
+            },
+        )
+    }
+
+    fn pos_beam<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        words: I,
+    ) -> Result<Vec<(Token<'a>, String)>, SmolError> {
+        let tokens = words.into_iter().collect::<Vec<_>>();
+        let tags = Beam {
+            model: &self.model,
+            tags: &self.tags,
+            lexicon: Some(&self.lexicon),
+            templates: &self.features,
+            width: self.beam_width,
+        }.decode(&tokens.iter().map(|t| &*t.term).collect::<Vec<_>>())?;
+
+        Ok(tokens.into_iter().zip(tags).collect())
+    }
+
+    /// Tags words like `pos`, along with where every tag came from.
+    pub fn pos_with_sources<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        words: I,
+    ) -> Result<Vec<(Token<'a>, String, TagSource)>, SmolError> {
+        Ok(self
+            .pos(words)?
+            .into_iter()
+            .map(|(token, tag)| {
+                let source = self.tag_source(&token.term);
+                (token, tag, source)
+            })
+            .collect())
+    }
+
+    /// Whether the tagger saw a word in training, either in its tag dictionary or as a word
+    /// feature. Words are compared after normalization, so every year counts as known once any
+    /// year was seen. Without a `Word(0)` template, only dictionary words are known.
+    pub fn knows(&self, word: &str) -> bool {
+        self.tags.contains_key(word)
+            || self
+                .model
+                .feature_id(&format!("i word {}", Self::normalize_str(word)))
+                .is_some()
+    }
 
-        for i in clean {
-            context.insert(ix, Self::normalize_str(&i.term));
-            c.push(i);
-            ix += 1;
+    /// Tags the words of every sentence, and reports how many of them the tagger doesn't know
+    /// along with its accuracy on known and unknown words. Words with an empty tag are left out
+    /// of the accuracies, so untagged text can be checked too.
+    pub fn oov_report(&self, sentences: &[TaggedSentence]) -> Result<OovReport, SmolError> {
+        let mut res = OovReport::new();
+
+        for sentence in sentences {
+            let tagged = self.pos(sentence_tokens(sentence))?;
+            for ((word, gold), (_, guess)) in sentence.iter().zip(tagged) {
+                res.add(word, self.knows(word), gold, &guess);
+            }
         }
 
-        let (mut p1, mut p2) = ("-START-".to_owned(), "-START2-".to_owned());
+        Ok(res)
+    }
 
-        let mut res = Vec::with_capacity(c.len());
+    /// Tags words like `pos`, but returns up to `k` of the best tags for every word, best first.
+    /// Tagging is always greedy. Words in the tag dictionary only ever get their dictionary tag,
+    /// with full confidence.
+    pub fn top_k<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        words: I,
+        k: usize,
+    ) -> Result<Vec<(Token<'a>, Vec<ScoredTag>)>, SmolError> {
+        let model = &self.model;
+        let mut ids = Vec::new();
 
-        for (i, word) in c.into_iter().enumerate() {
-            let tag = match self.tags.get(&*word.term) {
-                Some(s) => s.to_string(),
+        tag_words(
+            words,
+            &self.features,
+            |w| {
+                self.lexicon
+                    .get(w)
+                    .or_else(|| self.tags.get(w))
+                    .map(|t| vec![ScoredTag::certain(t)])
+            },
+            |f| {
+                model.lookup(f, &mut ids);
+                match model.top_k(&ids, k.max(1)) {
+                    ref tags if tags.is_empty() => Err(SmolErrorKind::EmptyModel.into()),
+                    tags => Ok(tags),
+                }
+            },
+        )
+    }
+
+    /// Tags words like `pos`, but returns every tag along with its score and confidence.
+    pub fn distributions<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        words: I,
+    ) -> Result<Vec<(Token<'a>, Vec<ScoredTag>)>, SmolError> {
+        let k = self.model.classes().len();
+        self.top_k(words, k)
+    }
+
+    /// Trains the tagger for the given number of iterations, shuffling the sentences between
+    /// iterations with a random seed.
+    pub fn train(&mut self, sentences: Vec<TaggedSentence>, iterations: usize) {
+        self.train_with(sentences, &mut TrainConfig::new().iterations(iterations))
+    }
+
+    /// Trains the tagger with the given configuration.
+    ///
+    /// Training a tagger which has already been trained carries on from where it is, which can be
+    /// used to adapt it to another domain. With a training state, training resumes exactly where
+    /// it left off, and the tag dictionary is rebuilt from every sentence seen so far. Otherwise,
+    /// training starts from the averaged weights, and dictionary words which the new sentences
+    /// tag differently are dropped from the dictionary.
+    pub fn train_with(&mut self, sentences: Vec<TaggedSentence>, config: &mut TrainConfig) {
+        match config.get_seed() {
+            Some(seed) => self.train_with_rng(sentences, config, &mut seeded_rng(seed)),
+            None => self.train_with_rng(sentences, config, &mut thread_rng()),
+        }
+    }
+
+    /// Trains the tagger with the given configuration, shuffling the sentences between iterations
+    /// with `rng`. The seed of the configuration is ignored.
+    // TODO: How to ensure we have sentences
+    pub fn train_with_rng<R: Rng>(
+        &mut self,
+        mut sentences: Vec<TaggedSentence>,
+        config: &mut TrainConfig,
+        rng: &mut R,
+    ) {
+        let start = Instant::now();
+        let mut best: Option<(f64, AveragedPerceptron)> = None;
+        let mut since_best = 0;
+
+        let mut counts = match self.state.take() {
+            Some(state) => {
+                self.model = state.model;
+                self.corpus.sentences += sentences.len();
+                self.corpus.tokens += sentences.iter().map(|s| s.len()).sum::<usize>();
+                state.counts
+            }
+            None => {
+                self.corpus.sentences = sentences.len();
+                self.corpus.tokens = sentences.iter().map(|s| s.len()).sum();
+                BTreeMap::new()
+            }
+        };
+        self.make_tags(&sentences, &mut counts, config);
+
+        for epoch in 1..config.get_iterations() + 1 {
+            let (errors, tokens) = self.train_epoch_with(&sentences, config);
+            rng.shuffle(&mut sentences);
+
+            let mut stats = EpochStats {
+                epoch,
+                errors,
+                tokens,
+                dev_accuracy: None,
+                dev_error: None,
+                elapsed: start.elapsed(),
+            };
+
+            let dev = match config.get_dev() {
+                Some(dev) => dev,
                 None => {
-                    let features = Self::get_features(i, &context[..], &*word.term, &p1, &p2);
-                    self.model.predict(&features)?
+                    config.report(&stats);
+                    continue;
                 }
             };
 
-            if &*word.term != "-START-" || &*word.term != "-START2-" || &*word.term != "-END-"
-                || &*word.term != "-END2-"
-            {
-                res.push((word, tag.to_owned()));
+            // Evaluate a copy of the model, so that training can carry on afterwards
+            let mut averaged = self.model.clone();
+            averaged.average_weights();
+            let tagger = PerceptronTagger::from_parts(
+                averaged,
+                self.tags.clone(),
+                self.features.clone(),
+                CorpusInfo::default(),
+            ).beam_width(config.get_beam_width());
+
+            // An epoch which can't be evaluated doesn't count towards early stopping
+            let accuracy = match Evaluator::new().evaluate(&tagger, dev) {
+                Ok(evaluation) => evaluation.accuracy(),
+                Err(e) => {
+                    stats.dev_error = Some(e.kind());
+                    stats.elapsed = start.elapsed();
+                    config.report(&stats);
+                    continue;
+                }
+            };
+
+            stats.dev_accuracy = Some(accuracy);
+            stats.elapsed = start.elapsed();
+            config.report(&stats);
+
+            if best.as_ref().map(|b| accuracy > b.0).unwrap_or(true) {
+                best = Some((accuracy, tagger.model));
+                since_best = 0;
+            } else {
+                since_best += 1;
             }
 
-            p2 = p1;
-            p1 = tag;
+            if config.get_patience().map(|p| since_best >= p).unwrap_or(false) {
+                break;
+            }
         }
 
-        Ok(res)
+        if config.get_keep_state() {
+            self.state = Some(TrainingState {
+                model: self.model.clone(),
+                counts,
+            });
+        }
+
+        match best {
+            Some((_, model)) if config.get_keep_best() => self.model = model,
+            _ => self.model.average_weights(),
+        }
     }
 
-    // TODO: How to ensure we have sentences
-    pub fn train(&mut self, mut sentences: Vec<TaggedSentence>, iterations: usize) {
-        self.make_tags(&sentences);
-        for _ in 0..iterations {
-            for sentence in &mut sentences {
-                let (words, tags): (Vec<_>, Vec<_>) = sentence.iter().cloned().unzip();
-
-                let context = vec!["-START-".to_owned(), "-START2-".to_owned()]
-                    .into_iter()
-                    .chain(words.iter().map(|x| Self::normalize_str(x)))
-                    .chain(vec!["-END-".to_owned(), "-END2-".to_owned()].into_iter())
-                    .collect::<Vec<_>>();
-
-                let (mut p1, mut p2) = ("-START-".to_owned(), "-START2-".to_owned());
-
-                for (i, word) in words.iter().enumerate() {
-                    let guess = match self.tags.get(word) {
-                        Some(s) => s.to_owned(),
-                        None => {
-                            let features = Self::get_features(i, &context[..], word, &p1, &p2);
-                            let g = self.model.predict(&features).unwrap();
-                            self.model.update(&tags[i], &g, &features);
-                            g
-                        }
-                    };
+    /// Makes a single pass over the training sentences, in parallel if the configuration splits
+    /// them into shards.
+    fn train_epoch_with(
+        &mut self,
+        sentences: &[TaggedSentence],
+        config: &TrainConfig,
+    ) -> (usize, usize) {
+        #[cfg(feature = "parallel")]
+        {
+            if config.get_shards() > 1 {
+                let (shards, width) = (config.get_shards(), config.get_beam_width());
+                return self.train_epoch_parallel(sentences, shards, width);
+            }
+        }
 
-                    p2 = p1;
-                    p1 = guess;
+        self.train_epoch(sentences, config.get_beam_width())
+    }
+
+    /// Makes a single pass over the training sentences, returning the number of wrong guesses and
+    /// the number of tokens seen.
+    pub(crate) fn train_epoch(
+        &mut self,
+        sentences: &[TaggedSentence],
+        width: usize,
+    ) -> (usize, usize) {
+        if width > 1 {
+            return sentences.iter().fold((0, 0), |(errors, tokens), sentence| {
+                let (e, t) = beam::train_sentence(
+                    &mut self.model,
+                    &self.tags,
+                    &self.features,
+                    width,
+                    sentence,
+                );
+                (errors + e, tokens + t)
+            });
+        }
+
+        let (mut errors, mut tokens) = (0, 0);
+        let mut features = Features::new();
+        let mut ids = Vec::new();
+
+        for sentence in sentences {
+            let (words, tags): (Vec<_>, Vec<_>) = sentence.iter().cloned().unzip();
+
+            let context = vec!["-START-".to_owned(), "-START2-".to_owned()]
+                .into_iter()
+                .chain(words.iter().map(|x| Self::normalize_str(x)))
+                .chain(vec!["-END-".to_owned(), "-END2-".to_owned()])
+                .collect::<Vec<_>>();
+
+            let (mut p1, mut p2) = ("-START-".to_owned(), "-START2-".to_owned());
+
+            for (i, word) in words.iter().enumerate() {
+                let guess = match self.tags.get(word) {
+                    Some(s) => s.to_owned(),
+                    None => {
+                        self.get_features(i, &context[..], word, &p1, &p2, &mut features);
+                        self.model.intern_all(&features, &mut ids);
+                        let truth = self.model.add_class(&tags[i]);
+                        let g = self.model.predict(&ids).unwrap();
+                        self.model.update(truth, g, &ids);
+                        self.model.classes()[g].clone()
+                    }
+                };
+
+                tokens += 1;
+                if guess != tags[i] {
+                    errors += 1;
                 }
+
+                p2 = p1;
+                p1 = guess;
             }
-            let mut rng = thread_rng();
-            rng.shuffle(&mut sentences);
         }
-        self.model.average_weights();
+
+        (errors, tokens)
     }
 
-    // TODO: How to ensure we have sentences
-    fn make_tags(&mut self, sentences: &[TaggedSentence]) {
-        let mut counts: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+    /// Adds the words and tags of the training sentences to `counts`, and updates the tag
+    /// dictionary. `counts` holds the counts of every sentence the tagger was trained on before,
+    /// if they're known.
+    fn make_tags(
+        &mut self,
+        sentences: &[TaggedSentence],
+        counts: &mut BTreeMap<String, BTreeMap<String, usize>>,
+        config: &TrainConfig,
+    ) {
+        let resumed = !counts.is_empty();
+        let mut new: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+        let mut classes = BTreeSet::new();
         for sentence in sentences {
-            for &(ref word, ref tag) in *sentence {
-                let hm = counts.entry(word).or_insert_with(HashMap::new);
+            for (word, tag) in *sentence {
+                let hm = new.entry(word).or_default();
                 *hm.entry(tag).or_insert(0) += 1;
-                self.model.classes.insert(tag.to_string());
+                classes.insert(tag);
             }
         }
-        for (word, tag_freq) in counts {
-            let (tag, mode) = tag_freq.iter().max().unwrap();
-            let n = tag_freq.iter().map(|x| x.1).fold(0, |acc, &x| acc + x) as f64;
+        for class in classes {
+            self.model.add_class(class);
+        }
 
-            let freq_thresh = 20.0;
-            let ambiguity_thresh = 0.97;
+        let freq_thresh = config.get_tagdict_frequency() as f64;
+        let ambiguity_thresh = config.get_tagdict_ambiguity();
+
+        for (word, tag_freq) in new {
+            // Without the old counts, the old dictionary can only be checked against the new ones
+            if !resumed {
+                if let Some(t) = self.tags.get(word) {
+                    let n = tag_freq.values().sum::<usize>() as f64;
+                    let agree = tag_freq.get(&**t).cloned().unwrap_or(0) as f64;
+                    if agree / n < ambiguity_thresh {
+                        self.tags.remove(word);
+                    }
+                }
+            }
+
+            let c = counts.entry(word.to_owned()).or_default();
+            for (tag, n) in tag_freq {
+                *c.entry(tag.to_owned()).or_insert(0) += n;
+            }
+        }
+
+        if resumed {
+            self.tags.clear();
+        }
+        for (word, tag_freq) in counts.iter() {
+            let (tag, mode) = tag_freq.iter().max_by_key(|x| (x.1, x.0)).unwrap();
+            let n = tag_freq.values().sum::<usize>() as f64;
 
             if n >= freq_thresh && (*mode as f64 / n) >= ambiguity_thresh {
                 self.tags.insert(word.to_string(), tag.to_string());
@@ -308,58 +1240,38 @@ impl PerceptronTagger {
     }
 
     fn get_features(
+        &self,
         i: usize,
         context: &[String],
         w: &str,
         p1: &str,
         p2: &str,
-    ) -> HashMap<String, f64> {
-        let w = w.chars().collect::<Vec<_>>();
-        let suf = min(w.len(), 3);
-        let i = min(context.len() - 2, i + 2);
-        let iminus = min(context[i - 1].len(), 3);
-        let iplus = min(context[i + 1].len(), 3);
-
-        let mut res = HashMap::new();
-        Self::add_feature(&["bias"], &mut res);
-        Self::add_feature(
-            &["i suffix", &w[w.len() - suf..].iter().collect::<String>()],
-            &mut res,
-        );
-        Self::add_feature(&["i pref1", &w[0].to_string()], &mut res);
-        Self::add_feature(&["i-1 tag", p1], &mut res);
-        Self::add_feature(&["i-2 tag", p2], &mut res);
-        Self::add_feature(&["i tag+i-2 tag", p1, p2], &mut res);
-        Self::add_feature(&["i word", &context[i]], &mut res);
-        Self::add_feature(&["i-1 tag+i word", p1, &context[i]], &mut res);
-        Self::add_feature(&["i-1 word", &context[i - 1]], &mut res);
-        Self::add_feature(
-            &[
-                "i-1 suffix",
-                &context[i - 1][context[i - 1].len() - iminus..],
-            ],
-            &mut res,
-        );
-        Self::add_feature(&["i-2 word", &context[i - 2]], &mut res);
-        Self::add_feature(&["i+1 word", &context[i + 1]], &mut res);
-        Self::add_feature(
-            &[
-                "i+1 suffix",
-                &context[i - 1][context[i - 1].len() - iplus..],
-            ],
-            &mut res,
-        );
-        Self::add_feature(&["i+2 word", &context[i + 2]], &mut res);
+        features: &mut Features,
+    ) {
+        let ctx = FeatureContext {
+            i,
+            word: w,
+            context,
+            p1,
+            p2,
+            pos: &[],
+        };
 
-        res
+        features.clear();
+        self.features.extract(&ctx, features);
     }
 
-    fn add_feature(args: &[&str], features: &mut HashMap<String, f64>) {
-        let key = args.iter().join(" ");
-        *features.entry(key).or_insert(0.0) += 1.0;
+    /// The normalized words of a sentence, padded with two start and two end markers, as seen by
+    /// a `FeatureContext`.
+    pub(crate) fn context(words: &[&str]) -> Vec<String> {
+        vec!["-START-".to_owned(), "-START2-".to_owned()]
+            .into_iter()
+            .chain(words.iter().map(|w| Self::normalize_str(w)))
+            .chain(vec!["-END-".to_owned(), "-END2-".to_owned()])
+            .collect()
     }
 
-    fn normalize_str(t: &str) -> String {
+    pub(crate) fn normalize_str(t: &str) -> String {
         if t.find('-').is_some() && t.chars().nth(0) != Some('-') {
             "!HYPHEN".to_owned()
         } else if t.parse::<usize>().is_ok() {
@@ -374,11 +1286,44 @@ impl PerceptronTagger {
     }
 }
 
+impl Persist for PerceptronTagger {
+    /// Saves the tagger along with its metadata.
+    fn save_to<W: Write>(&self, mut writer: W) -> Result<(), SmolError> {
+        // Sort everything first, so that the same model always results in the same file
+        let weights = self.model.to_weights();
+        let tags = self.tags.iter().collect::<BTreeMap<_, _>>();
+
+        let s = encode(&(self.metadata(), weights, tags))?;
+        writer.write_all(&s).context(SmolErrorKind::Write)?;
+
+        Ok(())
+    }
+
+    /// Loads a saved tagger, including ones saved before model files had a header.
+    fn load_from<R: Read>(reader: R) -> Result<PerceptronTagger, SmolError> {
+        let bytes = read_all(reader)?;
+
+        let (metadata, weights, tags): (ModelMetadata, _, _) = match decode(&bytes)? {
+            Some(body) => deserialize(body).context(SmolErrorKind::Deserialize)?,
+            None => Self::load_legacy(&bytes)?,
+        };
+
+        let m = AveragedPerceptron::from_weights(weights, metadata.tagset.into_iter().collect());
+
+        Ok(PerceptronTagger::from_parts(
+            m,
+            tags,
+            metadata.templates,
+            metadata.corpus,
+        ))
+    }
+}
+
 impl Tagger for PerceptronTagger {
     type Tag = String;
 
     fn tag<'a, I: IntoIterator<Item = Token<'a>>>(
-        &mut self,
+        &self,
         tokens: I,
     ) -> Result<Vec<(Token<'a>, Self::Tag)>, SmolError> {
         self.pos(tokens)
@@ -390,6 +1335,67 @@ mod tests {
     use super::*;
     use std::borrow::Cow;
 
+    fn owned(sentence: &[(&str, &str)]) -> Vec<(String, String)> {
+        sentence
+            .iter()
+            .map(|&(w, t)| (w.to_owned(), t.to_owned()))
+            .collect()
+    }
+
+    fn tokens<'a>(sentence: &'a [(String, String)]) -> Vec<Token<'a>> {
+        sentence
+            .iter()
+            .enumerate()
+            .map(|(index, x)| Token {
+                term: Cow::Borrowed(&*x.0),
+                offset: 0,
+                index,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn perceptron_training() {
+        let sentences = vec![
+            owned(&[("the", "DT"), ("dog", "NN"), ("barks", "VBZ")]),
+            owned(&[("a", "DT"), ("cat", "NN"), ("sleeps", "VBZ")]),
+            owned(&[("dogs", "NNS"), ("bark", "VBP")]),
+        ];
+        let mut pt = PerceptronTagger::new();
+        pt.train(sentences.iter().map(|s| &s[..]).collect(), 5);
+
+        // None of these words are frequent enough for the tag dictionary, so every tag has to
+        // come from the weights the model learned
+        assert!(pt.tags.is_empty());
+        for s in &sentences {
+            let tags = pt.tag(tokens(s)).unwrap();
+            assert_eq!(
+                s.iter().map(|x| &*x.1).collect::<Vec<_>>(),
+                tags.iter().map(|x| &*x.1).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn perceptron_tagdict() {
+        // The most frequent tag goes in the dictionary, even when a rarer one sorts after it
+        let mut sentences = vec![owned(&[("the", "NN")])];
+        sentences.extend(vec![owned(&[("the", "DT")]); 40]);
+        let mut pt = PerceptronTagger::new();
+        pt.train(sentences.iter().map(|s| &s[..]).collect(), 1);
+
+        assert_eq!(Some("DT"), pt.tags.get("the").map(|t| &**t));
+    }
+
+    #[test]
+    fn perceptron_multibyte() {
+        let sentence = owned(&[("naïve", "JJ"), ("café", "NN"), ("東京", "NNP"), ("", "SYM")]);
+        let mut pt = PerceptronTagger::new();
+        pt.train(vec![&sentence[..]], 1);
+
+        assert_eq!(4, pt.tag(tokens(&sentence)).unwrap().len());
+    }
+
     #[test]
     fn perceptron_empty() {
         let ts = vec![
@@ -399,8 +1405,279 @@ mod tests {
                 index: 0,
             },
         ];
-        let mut pt = PerceptronTagger::new();
+        let pt = PerceptronTagger::new();
+
+        assert_eq!(SmolErrorKind::EmptyModel, pt.tag(ts).err().unwrap().kind());
+    }
+
+    #[test]
+    fn perceptron_seeded() {
+        let sentences = vec![
+            vec![("the", "DT"), ("dog", "NN"), ("barks", "VBZ")],
+            vec![("a", "DT"), ("cat", "NN"), ("sleeps", "VBZ")],
+            vec![("dogs", "NNS"), ("bark", "VBP")],
+            vec![("the", "DT"), ("cats", "NNS"), ("sleep", "VBP")],
+        ].into_iter()
+            .map(|s| {
+                s.into_iter()
+                    .map(|(w, t)| (w.to_owned(), t.to_owned()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+        let mut config = TrainConfig::new().iterations(8).seed(1234);
+
+        let mut a = PerceptronTagger::new();
+        a.train_with(corpus.clone(), &mut config);
+        let mut b = PerceptronTagger::new();
+        b.train_with(corpus, &mut config);
+        assert_eq!(a, b);
+
+        // Everything but the save date, which can tick over between the two saves
+        type Saved = (
+            ModelMetadata,
+            BTreeMap<String, BTreeMap<String, f64>>,
+            BTreeMap<String, String>,
+        );
+        let saved = |tagger: &PerceptronTagger| {
+            let mut bytes = Vec::new();
+            tagger.save_to(&mut bytes).unwrap();
+            let body = decode(&bytes).unwrap().unwrap();
+            let mut saved: Saved = deserialize(body).unwrap();
+            saved.0.date = 0;
+            saved
+        };
+        assert_eq!(saved(&a), saved(&b));
+    }
+
+    #[test]
+    fn perceptron_formats() {
+        let sentence = [
+            ("the".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+        ];
+        let mut tagger = PerceptronTagger::new().corpus_name("dogs");
+        tagger.train_with(vec![&sentence[..]], &mut TrainConfig::new().seed(0));
+
+        let mut bytes = Vec::new();
+        tagger.save_to(&mut bytes).unwrap();
+        let loaded = PerceptronTagger::load_from(&bytes[..]).unwrap();
+        assert_eq!(tagger.model.to_weights(), loaded.model.to_weights());
+        assert_eq!(tagger.tags, loaded.tags);
+        assert_eq!(tagger.corpus, loaded.corpus);
+
+        let metadata = ModelMetadata::read_from(&bytes[..]).unwrap();
+        assert_eq!(vec!["DT", "NN"], metadata.tagset);
+        assert_eq!(Some("dogs".to_owned()), metadata.corpus.name);
+        assert_eq!(2, metadata.corpus.tokens);
+
+        // Models saved as a bare tuple can still be loaded, minus their corpus information
+        let classes = tagger.model.classes().iter().collect::<BTreeSet<_>>();
+        let legacy = ::bincode::serialize(
+            &(tagger.model.to_weights(), &tagger.tags, classes),
+            ::bincode::Infinite,
+        ).unwrap();
+
+        let loaded = PerceptronTagger::load_from(&legacy[..]).unwrap();
+        assert_eq!(tagger.model.to_weights(), loaded.model.to_weights());
+        assert_eq!(CorpusInfo::default(), loaded.corpus);
+    }
+
+    #[test]
+    fn perceptron_top_k() {
+        let sentence = [
+            ("the".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+            ("barks".to_owned(), "VBZ".to_owned()),
+        ];
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(vec![&sentence[..]], &mut TrainConfig::new().seed(0));
+
+        let tags = tagger.pos(sentence_tokens(&sentence)).unwrap();
+        let top = tagger.top_k(sentence_tokens(&sentence), 2).unwrap();
+        let all = tagger.distributions(sentence_tokens(&sentence)).unwrap();
+
+        for ((t, top), (_, all)) in tags.iter().zip(&top).zip(&all) {
+            assert_eq!(2, top.1.len());
+            assert_eq!(t.1, top.1[0].tag);
+            assert_eq!(&top.1[..], &all[..2]);
+            assert!(all.windows(2).all(|w| w[0].confidence >= w[1].confidence));
+            assert!((all.iter().map(|s| s.confidence).sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn perceptron_batch() {
+        fn shared<T: Sync>(tagger: T) -> T {
+            tagger
+        }
+
+        let sentences = vec![
+            vec![("the", "DT"), ("dog", "NN"), ("barks", "VBZ")],
+            vec![("a", "DT"), ("cat", "NN")],
+            vec![("cats", "NNS"), ("sleep", "VBP"), ("a", "DT"), ("lot", "NN")],
+        ].into_iter()
+            .map(|s| {
+                s.into_iter()
+                    .map(|(w, t)| (w.to_owned(), t.to_owned()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(corpus.clone(), &mut TrainConfig::new().seed(0));
+        let tagger = shared(tagger);
+
+        let batch = corpus.iter().cycle().take(30).map(|s| sentence_tokens(s));
+        let tagged = tagger.tag_batch(batch.collect()).unwrap();
+        assert_eq!(30, tagged.len());
+        for (sentence, tagged) in corpus.iter().cycle().zip(tagged) {
+            assert_eq!(tagger.tag(sentence_tokens(sentence)).unwrap(), tagged);
+        }
+    }
+
+    #[test]
+    fn perceptron_ranking() {
+        let classes = vec!["b".to_owned(), "a".to_owned(), "c".to_owned()];
+
+        // Scores are compared in full, however close or large they are
+        assert_eq!(Some(1), best_class(&[1.000001, 1.000002, 0.0], &classes));
+        assert_eq!(Some(0), best_class(&[2e15, 1e15, 0.0], &classes));
+        // Ties go to the class which sorts last, like NLTK
+        assert_eq!(Some(0), best_class(&[1.0, 1.0, 0.0], &classes));
+
+        let top = top_classes(&[1.0, 1.0, 1.5], &classes, 3);
+        let tags = top.iter().map(|t| &*t.tag).collect::<Vec<_>>();
+        assert_eq!(vec!["c", "b", "a"], tags);
+    }
+
+    #[test]
+    fn perceptron_oov() {
+        let sentence = [
+            ("The".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+            ("barks".to_owned(), "VBZ".to_owned()),
+        ];
+        let mut tagger = PerceptronTagger::new().templates(TemplateSet::unknown_words());
+        tagger.train_with(vec![&sentence[..]], &mut TrainConfig::new().seed(0));
+        assert!(tagger.knows("the"));
+        assert!(!tagger.knows("cat"));
+
+        let gold = [
+            ("the".to_owned(), "DT".to_owned()),
+            ("cat".to_owned(), "NN".to_owned()),
+            ("Meows".to_owned(), String::new()),
+        ];
+        let report = tagger.oov_report(&[&gold]).unwrap();
+        assert_eq!((3, 2), (report.total, report.unknown));
+        assert_eq!((1, 1), (report.known_gold, report.known_correct));
+        assert_eq!(1, report.unknown_gold);
+        assert_eq!(vec![("Xxxxx", 1), ("xxx", 1)], report.top_shapes(5));
+    }
+
+    #[test]
+    fn perceptron_resume() {
+        let pairs = |s: &[(&str, &str)]| {
+            s.iter()
+                .map(|&(w, t)| (w.to_owned(), t.to_owned()))
+                .collect::<Vec<_>>()
+        };
+        let general = pairs(&[("the", "DT"), ("dog", "NN"), ("barks", "VBZ")]);
+        let domain = pairs(&[("the", "DT"), ("barks", "NNS"), ("fall", "VBP")]);
+
+        let mut tagger = PerceptronTagger::new();
+        let mut config = TrainConfig::new().seed(0).keep_state(true);
+        tagger.train_with(vec![&general[..]; 20], &mut config);
+        assert_eq!(Some(&"VBZ".to_owned()), tagger.get_tagdict().get("barks"));
+
+        // A checkpoint is also a regular model, but not the other way around
+        let (mut checkpoint, mut plain) = (Vec::new(), Vec::new());
+        tagger.save_checkpoint_to(&mut checkpoint).unwrap();
+        tagger.save_to(&mut plain).unwrap();
+        let loaded = PerceptronTagger::load_from(&checkpoint[..]).unwrap();
+        assert_eq!(tagger.model.to_weights(), loaded.model.to_weights());
+        assert!(loaded.get_training_state().is_none());
+        assert_eq!(
+            SmolErrorKind::MissingState,
+            PerceptronTagger::load_checkpoint_from(&plain[..])
+                .err()
+                .unwrap()
+                .kind()
+        );
+
+        // Resuming carries on counting instances, and rebuilds the dictionary from every sentence
+        let mut resumed = PerceptronTagger::load_checkpoint_from(&checkpoint[..]).unwrap();
+        assert_eq!(tagger.state, resumed.state);
+        let instances = resumed.state.as_ref().unwrap().model.instances;
+        resumed.train_with(vec![&domain[..]; 20], &mut config);
+        assert!(resumed.state.as_ref().unwrap().model.instances > instances);
+        assert_eq!(40, resumed.get_training_state().unwrap().get_counts()["the"]["DT"]);
+        assert!(!resumed.get_tagdict().contains_key("barks"));
+
+        // Fine-tuning a plain model only knows the new counts, which override the old dictionary
+        let mut tuned = PerceptronTagger::load_from(&plain[..]).unwrap();
+        tuned.train_with(vec![&domain[..]; 20], &mut TrainConfig::new().seed(0));
+        assert_eq!(Some(&"NNS".to_owned()), tuned.get_tagdict().get("barks"));
+
+        for t in &mut [resumed, tuned] {
+            let tags = t.pos(sentence_tokens(&domain)).unwrap();
+            assert_eq!("NNS", tags[1].1);
+        }
+    }
+
+    #[test]
+    fn perceptron_lexicon() {
+        let sentence = [
+            ("the".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+            ("barks".to_owned(), "VBZ".to_owned()),
+        ];
+        let mut config = TrainConfig::new().seed(0).tagdict_frequency(2);
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(vec![&sentence[..]; 2], &mut config);
+        assert_eq!(3, tagger.get_tagdict().len());
+
+        let mut config = config.tagdict_frequency(usize::MAX);
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(vec![&sentence[..]; 2], &mut config);
+        assert!(tagger.get_tagdict().is_empty());
+
+        let lexicon = read_lexicon("# Forced tags\nbarks NNP\n\n".as_bytes()).unwrap();
+        let tagger = tagger.lexicon(lexicon).beam_width(2);
+        let tags = tagger.pos_with_sources(sentence_tokens(&sentence)).unwrap();
+        assert_eq!(("NNP", TagSource::Lexicon), (&*tags[2].1, tags[2].2));
+        assert_eq!(TagSource::Model, tags[0].2);
+        assert!(read_lexicon("barks NNP VBZ".as_bytes()).is_err());
+
+        // The lexicon isn't saved in any format, but can be set again after loading
+        let mut bytes = Vec::new();
+        tagger.save_to(&mut bytes).unwrap();
+        assert!(PerceptronTagger::load_from(&bytes[..]).unwrap().get_lexicon().is_empty());
+
+        let mut bytes = Vec::new();
+        tagger.save_mapped_to(&mut bytes).unwrap();
+        let mapped = MappedTagger::from_bytes(bytes).unwrap();
+        assert_eq!(None, mapped.dictionary_tag("barks"));
+        let mapped = mapped.lexicon(tagger.get_lexicon().clone());
+        assert_eq!(Some("NNP"), mapped.dictionary_tag("barks"));
+        assert_eq!("NNP", mapped.tag(sentence_tokens(&sentence)).unwrap()[2].1);
+    }
+
+    #[test]
+    fn perceptron_add_class() {
+        let mut weights = HashMap::new();
+        weights.insert(
+            "i word dog".to_owned(),
+            vec![("NN".to_owned(), 1.5)].into_iter().collect(),
+        );
+        let mut model = AveragedPerceptron::from_weights(weights, HashSet::new());
+        let nn = model.class_id("NN").unwrap();
+        let dog = model.feature_id("i word dog").unwrap();
 
-        assert_eq!(SmolErrorKind::EmptyModel, pt.tag(&ts).err().unwrap().kind());
+        // Adding a class widens every row without moving existing weights
+        let vb = model.add_class("VB");
+        model.update(vb, nn, &[dog]);
+        assert_eq!(vec![0.5, 1.0], model.scores(&[dog]));
+        assert_eq!(vb, model.predict(&[dog]).unwrap());
     }
 }
@@ -0,0 +1,402 @@
+//! Shrinking trained perceptron taggers.
+//!
+//! Averaged perceptrons end up with a weight for nearly every feature they ever saw, most of them
+//! tiny. Pruning drops features which are rare or have small weights, and quantization stores the
+//! remaining weights as 8 or 16-bit integers in the mapped format. Both cost some accuracy, which
+//! `PerceptronTagger::prune` measures on a development set.
+
+use super::*;
+use error::*;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+/// How weights are stored in a mapped model.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize)]
+pub enum Quantization {
+    Bits8,
+    Bits16,
+}
+
+impl Quantization {
+    /// The width of a weight in bits.
+    pub fn bits(self) -> usize {
+        match self {
+            Quantization::Bits8 => 8,
+            Quantization::Bits16 => 16,
+        }
+    }
+
+    /// The largest integer a weight is stored as.
+    pub fn levels(self) -> usize {
+        match self {
+            Quantization::Bits8 => i8::MAX as usize,
+            Quantization::Bits16 => i16::MAX as usize,
+        }
+    }
+}
+
+/// Settings for pruning a trained tagger.
+///
+/// # Examples
+/// ```rust
+/// # use smol::tag::*;
+/// let sentence = [("the".to_owned(), "DT".to_owned()), ("dog".to_owned(), "NN".to_owned())];
+/// let sentences = vec![&sentence[..]];
+/// let mut tagger = PerceptronTagger::new();
+/// tagger.train_with(sentences.clone(), &mut TrainConfig::new().seed(0));
+///
+/// let config = PruneConfig::new()
+///     .min_weight(0.5)
+///     .max_features(1000)
+///     .quantize(Quantization::Bits8)
+///     .dev(&sentences);
+/// let report = tagger.prune(&config).unwrap();
+/// println!("{}", report);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PruneConfig<'a> {
+    min_weight: f64,
+    min_count: usize,
+    train: Option<&'a [TaggedSentence<'a>]>,
+    max_features: Option<usize>,
+    quantization: Option<Quantization>,
+    dev: Option<&'a [TaggedSentence<'a>]>,
+}
+
+impl<'a> PruneConfig<'a> {
+    pub fn new() -> PruneConfig<'a> {
+        PruneConfig::default()
+    }
+
+    /// Drops weights smaller than this in magnitude. Defaults to 0, which keeps every weight.
+    pub fn min_weight(mut self, min_weight: f64) -> PruneConfig<'a> {
+        self.min_weight = min_weight;
+        self
+    }
+
+    /// Drops features which occur fewer than `min_count` times in the training sentences.
+    pub fn min_count(
+        mut self,
+        min_count: usize,
+        train: &'a [TaggedSentence<'a>],
+    ) -> PruneConfig<'a> {
+        self.min_count = min_count;
+        self.train = Some(train);
+        self
+    }
+
+    /// Keeps only the `n` features with the largest weights for every class.
+    pub fn max_features(mut self, n: usize) -> PruneConfig<'a> {
+        self.max_features = Some(n);
+        self
+    }
+
+    /// Rounds weights so that they can be stored as small integers, and stores them that way
+    /// when the tagger is saved in the mapped format.
+    pub fn quantize(mut self, quantization: Quantization) -> PruneConfig<'a> {
+        self.quantization = Some(quantization);
+        self
+    }
+
+    /// Sets the held-out sentences to measure accuracy on before and after pruning.
+    pub fn dev(mut self, dev: &'a [TaggedSentence<'a>]) -> PruneConfig<'a> {
+        self.dev = Some(dev);
+        self
+    }
+
+    pub fn get_min_weight(&self) -> f64 {
+        self.min_weight
+    }
+
+    pub fn get_min_count(&self) -> usize {
+        self.min_count
+    }
+
+    pub fn get_max_features(&self) -> Option<usize> {
+        self.max_features
+    }
+
+    pub fn get_quantization(&self) -> Option<Quantization> {
+        self.quantization
+    }
+
+    pub fn get_dev(&self) -> Option<&'a [TaggedSentence<'a>]> {
+        self.dev
+    }
+}
+
+/// The size and accuracy of a tagger before and after pruning.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PruneReport {
+    pub features_before: usize,
+    pub features_after: usize,
+    /// The number of non-zero weights.
+    pub weights_before: usize,
+    pub weights_after: usize,
+    /// The size of the saved model.
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+    /// The size of the model saved in the mapped format.
+    pub mapped_bytes_before: usize,
+    pub mapped_bytes_after: usize,
+    /// The evaluations on the development set, if there is one.
+    pub before: Option<Evaluation>,
+    pub after: Option<Evaluation>,
+}
+
+impl PruneReport {
+    /// The fraction by which the saved model shrank.
+    pub fn size_reduction(&self) -> f64 {
+        reduction(self.bytes_before, self.bytes_after)
+    }
+
+    /// The fraction by which the model saved in the mapped format shrank.
+    pub fn mapped_size_reduction(&self) -> f64 {
+        reduction(self.mapped_bytes_before, self.mapped_bytes_after)
+    }
+
+    /// The change in accuracy on the development set, which is usually negative.
+    pub fn accuracy_change(&self) -> Option<f64> {
+        match (&self.before, &self.after) {
+            (Some(b), Some(a)) => Some(a.accuracy() - b.accuracy()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PruneReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "features: {} -> {}",
+            self.features_before, self.features_after
+        )?;
+        writeln!(
+            f,
+            "weights:  {} -> {}",
+            self.weights_before, self.weights_after
+        )?;
+        writeln!(
+            f,
+            "model:    {} -> {} bytes ({:.1}% smaller)",
+            self.bytes_before,
+            self.bytes_after,
+            self.size_reduction() * 100.0
+        )?;
+        writeln!(
+            f,
+            "mapped:   {} -> {} bytes ({:.1}% smaller)",
+            self.mapped_bytes_before,
+            self.mapped_bytes_after,
+            self.mapped_size_reduction() * 100.0
+        )?;
+        if let (Some(b), Some(a)) = (&self.before, &self.after) {
+            writeln!(
+                f,
+                "accuracy: {:.4} -> {:.4} ({:+.4})",
+                b.accuracy(),
+                a.accuracy(),
+                a.accuracy() - b.accuracy()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PerceptronTagger {
+    /// Prunes and quantizes the weights of a trained tagger, reporting how much smaller and less
+    /// accurate it got. Features are first dropped by count, then weights by magnitude and by
+    /// rank within their class, and the remaining weights are quantized last.
+    pub fn prune(&mut self, config: &PruneConfig) -> Result<PruneReport, SmolError> {
+        let mut res = PruneReport {
+            features_before: self.get_model().num_features(),
+            weights_before: self.get_model().num_weights(),
+            ..PruneReport::default()
+        };
+        let (bytes, mapped) = self.sizes()?;
+        res.bytes_before = bytes;
+        res.mapped_bytes_before = mapped;
+        if let Some(dev) = config.get_dev() {
+            res.before = Some(Evaluator::new().evaluate(self, dev)?);
+        }
+
+        let counts = match config.train {
+            Some(train) if config.min_count > 0 => Some(self.feature_counts(train)),
+            _ => None,
+        };
+        let min_weight = config.min_weight;
+        self.model_mut().retain(|f, _, w| {
+            let count = counts
+                .as_ref()
+                .map(|c| c.get(&f).cloned().unwrap_or(0))
+                .unwrap_or(usize::MAX);
+            count >= config.min_count && w.abs() >= min_weight
+        });
+
+        if let Some(n) = config.max_features {
+            let keep = self.top_features(n);
+            self.model_mut().retain(|f, c, _| keep[c].contains(&f));
+        }
+
+        if let Some(q) = config.quantization {
+            self.model_mut().quantize(q.levels());
+            // Weights which round to 0 leave some features without any
+            self.model_mut().retain(|_, _, _| true);
+            *self = mem::take(self).quantization(Some(q));
+        }
+
+        res.features_after = self.get_model().num_features();
+        res.weights_after = self.get_model().num_weights();
+        let (bytes, mapped) = self.sizes()?;
+        res.bytes_after = bytes;
+        res.mapped_bytes_after = mapped;
+        if let Some(dev) = config.get_dev() {
+            res.after = Some(Evaluator::new().evaluate(self, dev)?);
+        }
+
+        Ok(res)
+    }
+
+    /// The sizes of the tagger saved normally and in the mapped format.
+    fn sizes(&self) -> Result<(usize, usize), SmolError> {
+        let (mut bytes, mut mapped) = (Vec::new(), Vec::new());
+        self.save_to(&mut bytes)?;
+        self.save_mapped_to(&mut mapped)?;
+        Ok((bytes.len(), mapped.len()))
+    }
+
+    /// How often every feature occurs in the training sentences, given their gold tags. Words in
+    /// the tag dictionary are skipped, like in training.
+    fn feature_counts(&self, sentences: &[TaggedSentence]) -> HashMap<usize, usize> {
+        let mut res = HashMap::new();
+        let mut features = Features::new();
+        let mut ids = Vec::new();
+
+        for sentence in sentences {
+            let words = sentence.iter().map(|x| &*x.0).collect::<Vec<_>>();
+            let context = PerceptronTagger::context(&words);
+            let (mut p1, mut p2) = ("-START-", "-START2-");
+
+            for (i, (word, tag)) in sentence.iter().enumerate() {
+                if !self.get_tagdict().contains_key(word) {
+                    let ctx = FeatureContext {
+                        i,
+                        word,
+                        context: &context,
+                        p1,
+                        p2,
+                        pos: &[],
+                    };
+                    features.clear();
+                    self.get_templates().extract(&ctx, &mut features);
+                    self.get_model().lookup(&features, &mut ids);
+                    for &f in &ids {
+                        *res.entry(f).or_insert(0) += 1;
+                    }
+                }
+
+                p2 = p1;
+                p1 = tag;
+            }
+        }
+
+        res
+    }
+
+    /// The IDs of the `n` features with the largest weights for every class, indexed by class.
+    fn top_features(&self, n: usize) -> Vec<Vec<usize>> {
+        let model = self.get_model();
+        (0..model.classes().len())
+            .map(|c| {
+                let mut features = (0..model.num_features())
+                    .map(|f| (model.weights(f)[c].abs(), f))
+                    .filter(|x| x.0 != 0.0)
+                    .collect::<Vec<_>>();
+                features.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+                let mut keep = features
+                    .into_iter()
+                    .take(n)
+                    .map(|x| x.1)
+                    .collect::<Vec<_>>();
+                keep.sort();
+                keep
+            })
+            .collect()
+    }
+}
+
+fn reduction(before: usize, after: usize) -> f64 {
+    if before == 0 {
+        0.0
+    } else {
+        1.0 - after as f64 / before as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_and_quantize() {
+        let sentences = vec![
+            vec![("the", "DT"), ("dog", "NN"), ("barks", "VBZ")],
+            vec![("a", "DT"), ("cat", "NN"), ("sleeps", "VBZ")],
+            vec![("dogs", "NNS"), ("bark", "VBP")],
+            vec![("the", "DT"), ("cats", "NNS"), ("sleep", "VBP")],
+        ]
+        .into_iter()
+        .map(|s| {
+            s.into_iter()
+                .map(|(w, t)| (w.to_owned(), t.to_owned()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+        let corpus = sentences.iter().map(|s| &s[..]).collect::<Vec<_>>();
+
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(
+            corpus.clone(),
+            &mut TrainConfig::new().iterations(10).seed(0),
+        );
+
+        // On a corpus this small, most features are seen only once
+        let mut pruned = tagger.clone();
+        let report = pruned
+            .prune(&PruneConfig::new().min_count(2, &corpus).dev(&corpus))
+            .unwrap();
+        assert!(report.features_after < report.features_before);
+        assert!(report.bytes_after < report.bytes_before);
+        assert_eq!(Some(1.0), report.before.as_ref().map(|e| e.accuracy()));
+        assert!(report.accuracy_change().unwrap() < 0.0);
+        assert!(pruned.knows("the") && !pruned.knows("barks"));
+
+        let config = PruneConfig::new()
+            .max_features(10)
+            .quantize(Quantization::Bits8)
+            .dev(&corpus);
+        let report = tagger.prune(&config).unwrap();
+        assert!(report.weights_after <= 10 * tagger.get_model().classes().len());
+        assert!(report.mapped_bytes_after * 4 < report.mapped_bytes_before);
+
+        // Quantized mapped models tag exactly like the quantized tagger
+        let mut bytes = Vec::new();
+        tagger.save_mapped_to(&mut bytes).unwrap();
+        let mapped = MappedTagger::from_bytes(bytes).unwrap();
+        assert_eq!(8, mapped.weight_bits());
+        for sentence in corpus {
+            assert_eq!(
+                tagger.tag(sentence_tokens(sentence)).unwrap(),
+                mapped.tag(sentence_tokens(sentence)).unwrap()
+            );
+        }
+
+        // Pruning again without quantizing leaves the weights quantized
+        tagger.prune(&PruneConfig::new().max_features(5)).unwrap();
+        assert_eq!(Some(Quantization::Bits8), tagger.get_quantization());
+    }
+}
@@ -0,0 +1,293 @@
+//! Splitting tagged corpora into training, development and test sets, and cross-validating
+//! taggers over several folds.
+//!
+//! Every split is shuffled with an explicitly seeded RNG, so the same seed always produces the
+//! same split.
+
+use super::*;
+use error::*;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::collections::BTreeSet;
+
+/// Creates a deterministic RNG from a seed.
+pub(crate) fn seeded_rng(seed: u64) -> XorShiftRng {
+    // SplitMix64, so that similar seeds still lead to very different states.
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let (a, b) = (next(), next());
+
+    XorShiftRng::from_seed([a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32 | 1])
+}
+
+/// A corpus split into training, development and test sets.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Split<T> {
+    pub train: Vec<T>,
+    pub dev: Vec<T>,
+    pub test: Vec<T>,
+}
+
+/// Shuffles `items` and splits them into training, development and test sets. `dev` and `test`
+/// are the fractions of items which end up in each set; the rest is used for training.
+///
+/// # Examples
+/// ```rust
+/// # use smol::tag::split;
+/// let s = split(&(0..10).collect::<Vec<_>>(), 0.1, 0.2, 42);
+/// assert_eq!((7, 1, 2), (s.train.len(), s.dev.len(), s.test.len()));
+/// ```
+pub fn split<T: Clone>(items: &[T], dev: f64, test: f64, seed: u64) -> Split<T> {
+    let mut items = items.to_vec();
+    seeded_rng(seed).shuffle(&mut items);
+
+    let n = items.len() as f64;
+    let test = items.len().min((n * test).round() as usize);
+    let dev = (items.len() - test).min((n * dev).round() as usize);
+
+    let test_set = items.split_off(items.len() - test);
+    let dev_set = items.split_off(items.len() - dev);
+
+    Split {
+        train: items,
+        dev: dev_set,
+        test: test_set,
+    }
+}
+
+/// One fold of a k-fold split.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Fold<T> {
+    pub train: Vec<T>,
+    pub test: Vec<T>,
+}
+
+/// Shuffles `items` and splits them into `k` folds. Every item is in the test set of exactly one
+/// fold, and the sizes of the test sets differ by at most one.
+///
+/// # Panics
+/// Panics if `k` is zero.
+pub fn folds<T: Clone>(items: &[T], k: usize, seed: u64) -> Vec<Fold<T>> {
+    assert!(k > 0, "can't split into zero folds");
+
+    let mut ixs = (0..items.len()).collect::<Vec<_>>();
+    seeded_rng(seed).shuffle(&mut ixs);
+
+    let (size, extra) = (items.len() / k, items.len() % k);
+    let mut start = 0;
+
+    (0..k)
+        .map(|i| {
+            let end = start + size + if i < extra { 1 } else { 0 };
+            let test = ixs[start..end].iter().collect::<BTreeSet<_>>();
+            start = end;
+
+            let mut fold = Fold {
+                train: Vec::with_capacity(items.len() - test.len()),
+                test: Vec::with_capacity(test.len()),
+            };
+            for (ix, item) in items.iter().enumerate() {
+                if test.contains(&ix) {
+                    fold.test.push(item.clone());
+                } else {
+                    fold.train.push(item.clone());
+                }
+            }
+
+            fold
+        })
+        .collect()
+}
+
+/// The mean and (sample) standard deviation of a metric.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct Summary {
+    pub mean: f64,
+    pub std_dev: f64,
+}
+
+impl Summary {
+    pub fn of(xs: &[f64]) -> Summary {
+        if xs.is_empty() {
+            return Summary::default();
+        }
+
+        let n = xs.len() as f64;
+        let mean = xs.iter().sum::<f64>() / n;
+        let var = if xs.len() > 1 {
+            xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1.0)
+        } else {
+            0.0
+        };
+
+        Summary {
+            mean,
+            std_dev: var.sqrt(),
+        }
+    }
+}
+
+/// The evaluation of every fold of a cross-validation run.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize, Serialize)]
+pub struct CrossValidation {
+    pub folds: Vec<Evaluation>,
+}
+
+impl CrossValidation {
+    pub fn accuracy(&self) -> Summary {
+        self.summarize(Evaluation::accuracy)
+    }
+
+    pub fn known_accuracy(&self) -> Summary {
+        self.summarize(Evaluation::known_accuracy)
+    }
+
+    pub fn unknown_accuracy(&self) -> Summary {
+        self.summarize(Evaluation::unknown_accuracy)
+    }
+
+    /// The F1 score of a single tag. Folds in which the tag never occurs count as zero.
+    pub fn f1(&self, tag: &str) -> Summary {
+        self.summarize(|e| {
+            e.scores()
+                .into_iter()
+                .find(|s| s.tag == tag)
+                .map(|s| s.f1)
+                .unwrap_or(0.0)
+        })
+    }
+
+    fn summarize<F: Fn(&Evaluation) -> f64>(&self, f: F) -> Summary {
+        Summary::of(&self.folds.iter().map(f).collect::<Vec<_>>())
+    }
+}
+
+/// Trains and evaluates a fresh tagger on each of `k` folds of a corpus.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CrossValidator {
+    k: usize,
+    seed: u64,
+    iterations: usize,
+}
+
+impl Default for CrossValidator {
+    fn default() -> CrossValidator {
+        CrossValidator {
+            k: 10,
+            seed: 0,
+            iterations: 5,
+        }
+    }
+}
+
+impl CrossValidator {
+    pub fn new() -> CrossValidator {
+        CrossValidator::default()
+    }
+
+    /// Sets the number of folds. Defaults to 10.
+    pub fn k(mut self, k: usize) -> CrossValidator {
+        self.k = k;
+        self
+    }
+
+    /// Sets the seed used to split the corpus and train each tagger. Defaults to 0.
+    pub fn seed(mut self, seed: u64) -> CrossValidator {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the number of training iterations of each `PerceptronTagger`. Defaults to 5.
+    pub fn iterations(mut self, iterations: usize) -> CrossValidator {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Cross-validates a `PerceptronTagger`, trained reproducibly using the seed.
+    pub fn run(&self, sentences: &[TaggedSentence]) -> Result<CrossValidation, SmolError> {
+        self.run_with(sentences, |train| {
+            let mut tagger = PerceptronTagger::new();
+            let mut config = TrainConfig::new()
+                .iterations(self.iterations)
+                .seed(self.seed);
+            tagger.train_with(train.to_vec(), &mut config);
+            Ok(tagger)
+        })
+    }
+
+    /// Cross-validates any tagger, using `train` to create a fresh tagger from the training set of
+    /// every fold.
+    pub fn run_with<T, F>(
+        &self,
+        sentences: &[TaggedSentence],
+        mut train: F,
+    ) -> Result<CrossValidation, SmolError>
+    where
+        T: Tagger,
+        T::Tag: ToString,
+        F: FnMut(&[TaggedSentence]) -> Result<T, SmolError>,
+    {
+        let mut res = CrossValidation::default();
+
+        for fold in folds(sentences, self.k, self.seed) {
+            let tagger = train(&fold.train)?;
+            let evaluation = Evaluator::new()
+                .training(&fold.train)
+                .evaluate(&tagger, &fold.test)?;
+            res.folds.push(evaluation);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_partition() {
+        let items = (0..11).collect::<Vec<_>>();
+        let fs = folds(&items, 3, 7);
+
+        assert_eq!(
+            vec![4, 4, 3],
+            fs.iter().map(|f| f.test.len()).collect::<Vec<_>>()
+        );
+        assert_eq!(fs, folds(&items, 3, 7));
+
+        let mut tested = fs.iter().flat_map(|f| f.test.clone()).collect::<Vec<_>>();
+        tested.sort();
+        assert_eq!(items, tested);
+        assert!(fs.iter().all(|f| f.train.len() + f.test.len() == 11));
+    }
+
+    #[test]
+    fn cross_validate_perceptron() {
+        let sentence = [
+            ("the".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+            ("barks".to_owned(), "VBZ".to_owned()),
+        ];
+        let corpus = vec![&sentence[..]; 6];
+
+        let cv = CrossValidator::new().k(3).run(&corpus).unwrap();
+
+        assert_eq!(3, cv.folds.len());
+        assert_eq!(1.0, cv.accuracy().mean);
+        assert_eq!(0.0, cv.accuracy().std_dev);
+    }
+
+    #[test]
+    fn summary_std_dev() {
+        let s = Summary::of(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(5.0, s.mean);
+        assert!((s.std_dev - 2.138_089_935).abs() < 1e-9);
+    }
+}
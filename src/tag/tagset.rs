@@ -0,0 +1,550 @@
+//! Typed tagsets, and mappings between them.
+//!
+//! Taggers output tags as strings, in whatever tagset they were trained on. This module has typed
+//! versions of the two most common ones, the [Penn Treebank](https://www.ling.upenn.edu/courses/Fall_2003/ling001/penn_treebank_pos.html)
+//! tagset (`Ptb`) and the [Universal Dependencies](https://universaldependencies.org/u/pos/)
+//! tagset (`Upos`), along with mappings between them and the tagset of the Brown corpus.
+//!
+//! Mappings into a coarser tagset are exact, but mappings into a finer one can only pick the most
+//! likely tag, since they don't look at words: UD's `VERB` becomes PTB's `VB`, whatever the tense.
+
+use super::*;
+use error::*;
+
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// One of the tagsets tags can be mapped between.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize)]
+pub enum TagsetName {
+    /// The Penn Treebank tagset, as in `Ptb`.
+    Ptb,
+    /// The tagset of the Brown corpus, including compound and suffixed tags like `NP-TL`.
+    Brown,
+    /// The Universal Dependencies tagset, as in `Upos`.
+    Upos,
+}
+
+impl TagsetName {
+    /// Maps a tag of this tagset to `to`, returning `None` if it isn't part of this tagset or has
+    /// no counterpart in `to`.
+    pub fn map(self, tag: &str, to: TagsetName) -> Option<String> {
+        match to {
+            _ if self == to => {
+                let known = match self {
+                    TagsetName::Ptb => Ptb::parse(tag).is_some(),
+                    TagsetName::Brown => brown_to_ptb(tag).is_some(),
+                    TagsetName::Upos => Upos::parse(tag).is_some(),
+                };
+                if known {
+                    Some(tag.to_owned())
+                } else {
+                    None
+                }
+            }
+            TagsetName::Ptb => Ptb::convert(tag, self).map(|t| t.as_str().to_owned()),
+            TagsetName::Upos => Upos::convert(tag, self).map(|t| t.as_str().to_owned()),
+            TagsetName::Brown => Ptb::convert(tag, self)
+                .and_then(Ptb::to_brown)
+                .map(|t| t.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for TagsetName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TagsetName::Ptb => write!(f, "PTB"),
+            TagsetName::Brown => write!(f, "Brown"),
+            TagsetName::Upos => write!(f, "UPOS"),
+        }
+    }
+}
+
+/// A tagset with a fixed list of tags.
+pub trait Tagset: Copy + Eq + Hash + fmt::Debug + fmt::Display + Sized + 'static {
+    /// Every tag of the tagset.
+    fn all() -> &'static [Self];
+
+    /// The tag as written in corpora.
+    fn as_str(self) -> &'static str;
+
+    /// Converts a tag of the tagset `from`.
+    fn convert(tag: &str, from: TagsetName) -> Option<Self>;
+
+    /// Reads a tag as written in corpora.
+    fn parse(tag: &str) -> Option<Self> {
+        Self::all().iter().cloned().find(|t| t.as_str() == tag)
+    }
+}
+
+/// Defines a tagset enum, with the string form of every tag.
+macro_rules! tagset {
+    ($(#[$meta:meta])* pub enum $name:ident { $($(#[$vmeta:meta])* $tag:ident => $s:expr,)* }) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Deserialize, Serialize)]
+        pub enum $name {
+            $($(#[$vmeta])* $tag,)*
+        }
+
+        impl $name {
+            const ALL: &'static [$name] = &[$($name::$tag,)*];
+
+            /// The tag as written in corpora.
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $($name::$tag => $s,)*
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.as_str())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = SmolError;
+
+            fn from_str(s: &str) -> Result<$name, SmolError> {
+                <$name as Tagset>::parse(s).ok_or_else(|| SmolErrorKind::UnknownTag.into())
+            }
+        }
+    };
+}
+
+tagset! {
+    /// The universal part-of-speech tags of Universal Dependencies.
+    pub enum Upos {
+        Adj => "ADJ",
+        Adp => "ADP",
+        Adv => "ADV",
+        Aux => "AUX",
+        Cconj => "CCONJ",
+        Det => "DET",
+        Intj => "INTJ",
+        Noun => "NOUN",
+        Num => "NUM",
+        Part => "PART",
+        Pron => "PRON",
+        Propn => "PROPN",
+        Punct => "PUNCT",
+        Sconj => "SCONJ",
+        Sym => "SYM",
+        Verb => "VERB",
+        X => "X",
+    }
+}
+
+tagset! {
+    /// The Penn Treebank tagset, along with the extra tags of OntoNotes and the English Web
+    /// Treebank.
+    #[allow(clippy::upper_case_acronyms)]
+    pub enum Ptb {
+        CC => "CC",
+        CD => "CD",
+        DT => "DT",
+        EX => "EX",
+        FW => "FW",
+        IN => "IN",
+        JJ => "JJ",
+        JJR => "JJR",
+        JJS => "JJS",
+        LS => "LS",
+        MD => "MD",
+        NN => "NN",
+        NNS => "NNS",
+        NNP => "NNP",
+        NNPS => "NNPS",
+        PDT => "PDT",
+        POS => "POS",
+        PRP => "PRP",
+        /// `PRP$`
+        PRPS => "PRP$",
+        RB => "RB",
+        RBR => "RBR",
+        RBS => "RBS",
+        RP => "RP",
+        SYM => "SYM",
+        TO => "TO",
+        UH => "UH",
+        VB => "VB",
+        VBD => "VBD",
+        VBG => "VBG",
+        VBN => "VBN",
+        VBP => "VBP",
+        VBZ => "VBZ",
+        WDT => "WDT",
+        WP => "WP",
+        /// `WP$`
+        WPS => "WP$",
+        WRB => "WRB",
+        Comma => ",",
+        Period => ".",
+        Colon => ":",
+        OpenQuote => "``",
+        CloseQuote => "''",
+        LRB => "-LRB-",
+        RRB => "-RRB-",
+        Dollar => "$",
+        Pound => "#",
+        HYPH => "HYPH",
+        NFP => "NFP",
+        ADD => "ADD",
+        AFX => "AFX",
+        GW => "GW",
+        XX => "XX",
+    }
+}
+
+impl Upos {
+    /// The most common PTB tag for this tag.
+    pub fn to_ptb(self) -> Ptb {
+        match self {
+            Upos::Adj => Ptb::JJ,
+            Upos::Adp => Ptb::IN,
+            Upos::Adv => Ptb::RB,
+            Upos::Aux => Ptb::MD,
+            Upos::Cconj => Ptb::CC,
+            Upos::Det => Ptb::DT,
+            Upos::Intj => Ptb::UH,
+            Upos::Noun => Ptb::NN,
+            Upos::Num => Ptb::CD,
+            Upos::Part => Ptb::TO,
+            Upos::Pron => Ptb::PRP,
+            Upos::Propn => Ptb::NNP,
+            Upos::Punct => Ptb::Period,
+            Upos::Sconj => Ptb::IN,
+            Upos::Sym => Ptb::SYM,
+            Upos::Verb => Ptb::VB,
+            Upos::X => Ptb::FW,
+        }
+    }
+}
+
+impl Tagset for Upos {
+    fn all() -> &'static [Upos] {
+        Upos::ALL
+    }
+
+    fn as_str(self) -> &'static str {
+        Upos::as_str(self)
+    }
+
+    fn convert(tag: &str, from: TagsetName) -> Option<Upos> {
+        match from {
+            TagsetName::Ptb => Ptb::parse(tag).map(Ptb::to_upos),
+            TagsetName::Brown => brown_to_ptb(tag).map(Ptb::to_upos),
+            TagsetName::Upos => Upos::parse(tag),
+        }
+    }
+}
+
+impl Ptb {
+    /// The UD tag for this tag, following the conversion of the English Web Treebank.
+    pub fn to_upos(self) -> Upos {
+        match self {
+            Ptb::CC => Upos::Cconj,
+            Ptb::CD => Upos::Num,
+            Ptb::DT | Ptb::PDT | Ptb::WDT => Upos::Det,
+            Ptb::EX | Ptb::PRP | Ptb::PRPS | Ptb::WP | Ptb::WPS => Upos::Pron,
+            Ptb::IN | Ptb::RP => Upos::Adp,
+            Ptb::JJ | Ptb::JJR | Ptb::JJS | Ptb::AFX => Upos::Adj,
+            Ptb::MD => Upos::Aux,
+            Ptb::NN | Ptb::NNS => Upos::Noun,
+            Ptb::NNP | Ptb::NNPS => Upos::Propn,
+            Ptb::POS | Ptb::TO => Upos::Part,
+            Ptb::RB | Ptb::RBR | Ptb::RBS | Ptb::WRB => Upos::Adv,
+            Ptb::SYM | Ptb::Dollar | Ptb::Pound => Upos::Sym,
+            Ptb::UH => Upos::Intj,
+            Ptb::VB | Ptb::VBD | Ptb::VBG | Ptb::VBN | Ptb::VBP | Ptb::VBZ => Upos::Verb,
+            Ptb::Comma
+            | Ptb::Period
+            | Ptb::Colon
+            | Ptb::OpenQuote
+            | Ptb::CloseQuote
+            | Ptb::LRB
+            | Ptb::RRB
+            | Ptb::HYPH
+            | Ptb::NFP => Upos::Punct,
+            Ptb::FW | Ptb::LS | Ptb::ADD | Ptb::GW | Ptb::XX => Upos::X,
+        }
+    }
+
+    /// The most common Brown tag for this tag, if there is one.
+    pub fn to_brown(self) -> Option<&'static str> {
+        let tag = match self {
+            Ptb::DT => "AT",
+            Ptb::JJS => "JJT",
+            Ptb::NNP => "NP",
+            Ptb::NNPS => "NPS",
+            Ptb::PDT => "ABN",
+            Ptb::PRP => "PPS",
+            Ptb::PRPS => "PP$",
+            Ptb::RBS => "RBT",
+            Ptb::VBP => "VB",
+            Ptb::WP => "WPS",
+            Ptb::LRB => "(",
+            Ptb::RRB => ")",
+            Ptb::HYPH => "--",
+            Ptb::LS | Ptb::POS | Ptb::SYM | Ptb::Dollar | Ptb::Pound => return None,
+            Ptb::NFP | Ptb::ADD | Ptb::AFX | Ptb::GW | Ptb::XX => return None,
+            t => t.as_str(),
+        };
+        Some(tag)
+    }
+}
+
+impl Tagset for Ptb {
+    fn all() -> &'static [Ptb] {
+        Ptb::ALL
+    }
+
+    fn as_str(self) -> &'static str {
+        Ptb::as_str(self)
+    }
+
+    fn convert(tag: &str, from: TagsetName) -> Option<Ptb> {
+        match from {
+            TagsetName::Ptb => Ptb::parse(tag),
+            TagsetName::Brown => brown_to_ptb(tag),
+            TagsetName::Upos => Upos::parse(tag).map(Upos::to_ptb),
+        }
+    }
+}
+
+/// Maps a Brown tag to PTB. Title and headline suffixes (`-TL`, `-HL`, `-NC`) are ignored, foreign
+/// words (`FW-...`) are always `FW`, contractions like `PPS+BEZ` take the tag of their first
+/// part, and negated forms like `MD*` take the tag of their base word.
+pub fn brown_to_ptb(tag: &str) -> Option<Ptb> {
+    let tag = tag.to_uppercase();
+    if tag.starts_with("FW-") || tag == "FW" {
+        return Some(Ptb::FW);
+    }
+
+    let tag = tag.split('+').next().unwrap_or("");
+    let tag = match tag {
+        "--" => tag,
+        _ => tag.split('-').next().unwrap_or(""),
+    };
+    // Negated forms, like `MD*` for "can't", are tagged as their base word
+    let tag = match tag {
+        "*" => tag,
+        _ => tag.trim_end_matches('*'),
+    };
+
+    brown_base(tag).or_else(|| {
+        // Possessives, like `NN$`, are tagged as their base word
+        if tag.len() > 1 && tag.ends_with('$') {
+            brown_base(&tag[..tag.len() - 1])
+        } else {
+            None
+        }
+    })
+}
+
+fn brown_base(tag: &str) -> Option<Ptb> {
+    let tag = match tag {
+        "." => Ptb::Period,
+        "," => Ptb::Comma,
+        ":" | "--" => Ptb::Colon,
+        "(" => Ptb::LRB,
+        ")" => Ptb::RRB,
+        "'" | "''" => Ptb::CloseQuote,
+        "`" | "``" => Ptb::OpenQuote,
+        "*" | "QL" | "QLP" | "RB" | "RN" => Ptb::RB,
+        "ABL" | "ABN" | "ABX" => Ptb::PDT,
+        "AP" | "JJ" | "OD" => Ptb::JJ,
+        "AT" | "DT" | "DTI" | "DTS" | "DTX" => Ptb::DT,
+        "BE" => Ptb::VB,
+        "BED" | "BEDZ" | "DOD" | "HVD" | "VBD" => Ptb::VBD,
+        "BEG" | "HVG" | "VBG" => Ptb::VBG,
+        "BEN" | "HVN" | "VBN" => Ptb::VBN,
+        "BEM" | "BER" | "DO" | "HV" => Ptb::VBP,
+        "BEZ" | "DOZ" | "HVZ" | "VBZ" => Ptb::VBZ,
+        "CC" => Ptb::CC,
+        "CD" => Ptb::CD,
+        "CS" | "IN" => Ptb::IN,
+        "EX" => Ptb::EX,
+        "JJR" => Ptb::JJR,
+        "JJS" | "JJT" => Ptb::JJS,
+        "MD" => Ptb::MD,
+        "NC" | "NN" | "NR" | "PN" => Ptb::NN,
+        "NNS" | "NRS" => Ptb::NNS,
+        "NP" => Ptb::NNP,
+        "NPS" => Ptb::NNPS,
+        "PP$" => Ptb::PRPS,
+        "PP$$" | "PPL" | "PPLS" | "PPO" | "PPS" | "PPSS" => Ptb::PRP,
+        "RBR" => Ptb::RBR,
+        "RBT" => Ptb::RBS,
+        "RP" => Ptb::RP,
+        "TO" => Ptb::TO,
+        "UH" => Ptb::UH,
+        "VB" => Ptb::VB,
+        "WDT" => Ptb::WDT,
+        "WP$" => Ptb::WPS,
+        "WPO" | "WPS" => Ptb::WP,
+        "WQL" | "WRB" => Ptb::WRB,
+        _ => return None,
+    };
+    Some(tag)
+}
+
+/// A tagger whose tags are converted to a typed tagset, so that they can be matched on.
+///
+/// # Examples
+/// ```rust
+/// # use smol::tag::*;
+/// let sentence = [("dogs".to_owned(), "NNS".to_owned()), ("bark".to_owned(), "VBP".to_owned())];
+/// let mut tagger = PerceptronTagger::new();
+/// tagger.train_with(vec![&sentence[..]], &mut TrainConfig::new().seed(0));
+///
+/// let mut tagger = TypedTagger::<_, Upos>::new(tagger, TagsetName::Ptb);
+/// let tagged = tagger.tag(sentence_tokens(&sentence)).unwrap();
+/// assert_eq!(Upos::Noun, tagged[0].1);
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub struct TypedTagger<T, S> {
+    tagger: T,
+    from: TagsetName,
+    tagset: PhantomData<S>,
+}
+
+impl<T, S> TypedTagger<T, S> {
+    /// Wraps a tagger which outputs tags of the tagset `from`.
+    pub fn new(tagger: T, from: TagsetName) -> TypedTagger<T, S> {
+        TypedTagger {
+            tagger,
+            from,
+            tagset: PhantomData,
+        }
+    }
+
+    pub fn get_tagger(&self) -> &T {
+        &self.tagger
+    }
+
+    pub fn into_tagger(self) -> T {
+        self.tagger
+    }
+}
+
+impl<T: Tagger<Tag = String>, S: Tagset> Tagger for TypedTagger<T, S> {
+    type Tag = S;
+
+    /// Tags tokens, failing if the tagger outputs a tag which can't be converted.
+    fn tag<'a, I: IntoIterator<Item = Token<'a>>>(
+        &self,
+        tokens: I,
+    ) -> Result<Vec<(Token<'a>, S)>, SmolError> {
+        let from = self.from;
+        self.tagger
+            .tag(tokens)?
+            .into_iter()
+            .map(|(token, tag)| {
+                let tag = S::convert(&tag, from).ok_or(SmolErrorKind::UnknownTag)?;
+                Ok((token, tag))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tagset_mappings() {
+        for &t in Ptb::all() {
+            assert_eq!(Some(t), t.as_str().parse().ok());
+            if let Some(b) = t.to_brown() {
+                assert_eq!(t.to_upos(), Upos::convert(b, TagsetName::Brown).unwrap());
+            }
+        }
+        // Subordinating conjunctions share `IN` with prepositions
+        for &t in Upos::all().iter().filter(|&&t| t != Upos::Sconj) {
+            assert_eq!(t, t.to_ptb().to_upos());
+        }
+        assert!("NOUN".parse::<Ptb>().is_err());
+
+        assert_eq!(Some(Ptb::NNP), brown_to_ptb("np-tl"));
+        assert_eq!(Some(Ptb::NN), brown_to_ptb("NN$"));
+        assert_eq!(Some(Ptb::PRPS), brown_to_ptb("PP$"));
+        assert_eq!(Some(Ptb::PRP), brown_to_ptb("PPS+BEZ"));
+        assert_eq!(Some(Ptb::FW), brown_to_ptb("FW-IN"));
+        assert_eq!(Some(Ptb::Colon), brown_to_ptb("--"));
+        assert_eq!(Some(Ptb::MD), brown_to_ptb("MD*"));
+        assert_eq!(Some(Ptb::VBZ), brown_to_ptb("BEZ*"));
+        assert_eq!(Some(Ptb::VBD), brown_to_ptb("DOD*"));
+        assert_eq!(Some(Ptb::VBD), brown_to_ptb("HVD*"));
+        assert_eq!(Some(Ptb::VBD), brown_to_ptb("BED*-TL"));
+        assert_eq!(Some(Ptb::RB), brown_to_ptb("*"));
+        assert_eq!(None, brown_to_ptb("NIL"));
+
+        let map = |t, from: TagsetName, to| from.map(t, to);
+        assert_eq!(
+            Some("PROPN".to_owned()),
+            map("NP-HL", TagsetName::Brown, TagsetName::Upos)
+        );
+        assert_eq!(
+            Some("NOUN".to_owned()),
+            map("NNS", TagsetName::Ptb, TagsetName::Upos)
+        );
+        assert_eq!(
+            Some("VB".to_owned()),
+            map("VERB", TagsetName::Upos, TagsetName::Ptb)
+        );
+        assert_eq!(
+            Some("AT".to_owned()),
+            map("DT", TagsetName::Ptb, TagsetName::Brown)
+        );
+        assert_eq!(
+            Some("NNS".to_owned()),
+            map("NNS", TagsetName::Ptb, TagsetName::Ptb)
+        );
+        assert_eq!(
+            Some("NP-TL".to_owned()),
+            map("NP-TL", TagsetName::Brown, TagsetName::Brown)
+        );
+        assert_eq!(None, map("foo", TagsetName::Ptb, TagsetName::Ptb));
+        assert_eq!(None, map("NNS", TagsetName::Upos, TagsetName::Upos));
+    }
+
+    #[test]
+    fn tagset_mapped_tagger() {
+        let sentence = [
+            ("the".to_owned(), "AT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+            ("barks".to_owned(), "VBZ".to_owned()),
+        ];
+        let mut tagger = PerceptronTagger::new();
+        tagger.train_with(vec![&sentence[..]], &mut TrainConfig::new().seed(0));
+
+        let mapped = tagger.clone().map_tags(TagsetName::Brown, TagsetName::Ptb);
+        let tags = mapped.tag(sentence_tokens(&sentence)).unwrap();
+        assert_eq!(
+            vec!["DT", "NN", "VBZ"],
+            tags.iter().map(|x| &*x.1).collect::<Vec<_>>()
+        );
+
+        let typed = TypedTagger::<_, Upos>::new(tagger, TagsetName::Brown);
+        let tags = typed.tag(sentence_tokens(&sentence)).unwrap();
+        assert_eq!(
+            vec![Upos::Det, Upos::Noun, Upos::Verb],
+            tags.into_iter().map(|x| x.1).collect::<Vec<_>>()
+        );
+
+        // Tags outside the tagset are errors, rather than being silently dropped
+        let typed = TypedTagger::<_, Ptb>::new(
+            mapped.map_tags(TagsetName::Ptb, TagsetName::Upos),
+            TagsetName::Ptb,
+        );
+        assert_eq!(
+            SmolErrorKind::UnknownTag,
+            typed.tag(sentence_tokens(&sentence)).err().unwrap().kind()
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! Configuration for training taggers.
+
+use super::*;
+
+use std::fmt;
+use std::time::Duration;
+
+/// Statistics about a single pass over the training sentences.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct EpochStats {
+    /// The number of the epoch, starting from 1.
+    pub epoch: usize,
+    /// The number of tokens the model guessed wrong during the epoch.
+    pub errors: usize,
+    /// The number of tokens seen during the epoch.
+    pub tokens: usize,
+    /// The accuracy of the averaged model on the development set, if there is one.
+    pub dev_accuracy: Option<f64>,
+    /// Why the averaged model couldn't be evaluated on the development set, if it couldn't.
+    pub dev_error: Option<SmolErrorKind>,
+    /// The time since training started.
+    pub elapsed: Duration,
+}
+
+impl EpochStats {
+    /// The fraction of tokens guessed correctly during the epoch, before averaging.
+    pub fn train_accuracy(&self) -> f64 {
+        if self.tokens == 0 {
+            0.0
+        } else {
+            1.0 - self.errors as f64 / self.tokens as f64
+        }
+    }
+}
+
+impl fmt::Display for EpochStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "epoch {}: {} errors, train accuracy {:.4}",
+            self.epoch,
+            self.errors,
+            self.train_accuracy()
+        )?;
+        if let Some(acc) = self.dev_accuracy {
+            write!(f, ", dev accuracy {:.4}", acc)?;
+        }
+        if let Some(kind) = self.dev_error {
+            write!(f, ", dev evaluation failed: {}", kind)?;
+        }
+        write!(
+            f,
+            " ({}.{:03}s)",
+            self.elapsed.as_secs(),
+            self.elapsed.subsec_millis()
+        )
+    }
+}
+
+/// A function called with the statistics of every epoch.
+pub type EpochCallback<'a> = Box<dyn FnMut(&EpochStats) + 'a>;
+
+/// Settings for training a `PerceptronTagger`.
+///
+/// By default, sentences are shuffled between iterations using a random seed. Setting a seed makes
+/// training reproducible: the same sentences and settings always produce an identical model.
+///
+/// Given a development set, the averaged model is evaluated on it after every epoch. Training
+/// then stops early once accuracy hasn't improved for `patience` epochs, and the model which did
+/// best on the development set is kept.
+///
+/// # Examples
+/// ```rust
+/// # use smol::tag::*;
+/// let dev = vec![("the".to_owned(), "DT".to_owned())];
+/// let dev = vec![&dev[..]];
+///
+/// let config = TrainConfig::new()
+///     .iterations(10)
+///     .seed(42)
+///     .dev(&dev)
+///     .patience(2)
+///     .callback(|stats| println!("{}", stats));
+/// ```
+pub struct TrainConfig<'a> {
+    iterations: usize,
+    seed: Option<u64>,
+    dev: Option<&'a [TaggedSentence<'a>]>,
+    patience: Option<usize>,
+    keep_best: bool,
+    beam_width: usize,
+    keep_state: bool,
+    tagdict_frequency: usize,
+    tagdict_ambiguity: f64,
+    #[cfg(feature = "parallel")]
+    shards: usize,
+    callback: Option<EpochCallback<'a>>,
+}
+
+impl<'a> Default for TrainConfig<'a> {
+    fn default() -> TrainConfig<'a> {
+        TrainConfig {
+            iterations: 5,
+            seed: None,
+            dev: None,
+            patience: None,
+            keep_best: true,
+            beam_width: 1,
+            keep_state: false,
+            tagdict_frequency: 20,
+            tagdict_ambiguity: 0.97,
+            #[cfg(feature = "parallel")]
+            shards: 1,
+            callback: None,
+        }
+    }
+}
+
+impl<'a> TrainConfig<'a> {
+    pub fn new() -> TrainConfig<'a> {
+        TrainConfig::default()
+    }
+
+    /// Sets the maximum number of passes over the training sentences. Defaults to 5.
+    pub fn iterations(mut self, iterations: usize) -> TrainConfig<'a> {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Sets the seed used to shuffle the training sentences between iterations.
+    pub fn seed(mut self, seed: u64) -> TrainConfig<'a> {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets the held-out sentences to evaluate the model on after every epoch.
+    pub fn dev(mut self, dev: &'a [TaggedSentence<'a>]) -> TrainConfig<'a> {
+        self.dev = Some(dev);
+        self
+    }
+
+    /// Stops training once accuracy on the development set hasn't improved for this many epochs.
+    pub fn patience(mut self, patience: usize) -> TrainConfig<'a> {
+        self.patience = Some(patience);
+        self
+    }
+
+    /// Sets whether to keep the model which did best on the development set, rather than the one
+    /// from the last epoch. Defaults to true.
+    pub fn keep_best(mut self, keep_best: bool) -> TrainConfig<'a> {
+        self.keep_best = keep_best;
+        self
+    }
+
+    /// Trains with a beam search of this width, using early updates. Defaults to 1, which trains
+    /// greedily.
+    pub fn beam_width(mut self, width: usize) -> TrainConfig<'a> {
+        self.beam_width = width;
+        self
+    }
+
+    /// Sets whether the tagger keeps its full training state once training is done, so that it can
+    /// be saved as a checkpoint and trained further later on. This roughly doubles the memory the
+    /// tagger uses. Defaults to false.
+    pub fn keep_state(mut self, keep_state: bool) -> TrainConfig<'a> {
+        self.keep_state = keep_state;
+        self
+    }
+
+    /// Sets how many times a word must occur in the training sentences to be put in the tag
+    /// dictionary. Defaults to 20; `usize::MAX` turns the dictionary off.
+    pub fn tagdict_frequency(mut self, frequency: usize) -> TrainConfig<'a> {
+        self.tagdict_frequency = frequency;
+        self
+    }
+
+    /// Sets the fraction of a word's occurrences its most common tag must account for to be put in
+    /// the tag dictionary. Defaults to 0.97.
+    pub fn tagdict_ambiguity(mut self, ambiguity: f64) -> TrainConfig<'a> {
+        self.tagdict_ambiguity = ambiguity;
+        self
+    }
+
+    /// Splits every epoch into this many shards of sentences, which are trained on at the same time
+    /// on a thread pool and then mixed back into one model. Every shard sees fewer sentences per
+    /// epoch, so parallel training usually needs a few more iterations to be as accurate as serial
+    /// training. Defaults to 1, which trains serially.
+    #[cfg(feature = "parallel")]
+    pub fn shards(mut self, shards: usize) -> TrainConfig<'a> {
+        self.shards = shards;
+        self
+    }
+
+    /// Sets a function to call with the statistics of every epoch.
+    pub fn callback<F: FnMut(&EpochStats) + 'a>(mut self, callback: F) -> TrainConfig<'a> {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn get_iterations(&self) -> usize {
+        self.iterations
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn get_dev(&self) -> Option<&'a [TaggedSentence<'a>]> {
+        self.dev
+    }
+
+    pub fn get_patience(&self) -> Option<usize> {
+        self.patience
+    }
+
+    pub fn get_keep_best(&self) -> bool {
+        self.keep_best
+    }
+
+    pub fn get_beam_width(&self) -> usize {
+        self.beam_width
+    }
+
+    pub fn get_keep_state(&self) -> bool {
+        self.keep_state
+    }
+
+    pub fn get_tagdict_frequency(&self) -> usize {
+        self.tagdict_frequency
+    }
+
+    pub fn get_tagdict_ambiguity(&self) -> f64 {
+        self.tagdict_ambiguity
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn get_shards(&self) -> usize {
+        self.shards
+    }
+
+    /// Calls the callback, if there is one.
+    pub fn report(&mut self, stats: &EpochStats) {
+        if let Some(ref mut f) = self.callback {
+            f(stats);
+        }
+    }
+}
+
+/// Settings for models which are trained for a fixed number of passes over their training
+/// sentences, like `PerceptronChunker` and `DependencyParser`.
+///
+/// Like `TrainConfig`, setting a seed makes training reproducible.
+///
+/// # Examples
+/// ```rust
+/// # use smol::tag::*;
+/// let config = EpochConfig::new()
+///     .iterations(10)
+///     .seed(42)
+///     .callback(|stats| println!("{}", stats));
+/// ```
+pub struct EpochConfig<'a> {
+    iterations: usize,
+    seed: Option<u64>,
+    callback: Option<EpochCallback<'a>>,
+}
+
+impl<'a> Default for EpochConfig<'a> {
+    fn default() -> EpochConfig<'a> {
+        EpochConfig {
+            iterations: 5,
+            seed: None,
+            callback: None,
+        }
+    }
+}
+
+impl<'a> EpochConfig<'a> {
+    pub fn new() -> EpochConfig<'a> {
+        EpochConfig::default()
+    }
+
+    /// Sets the number of passes over the training sentences. Defaults to 5.
+    pub fn iterations(mut self, iterations: usize) -> EpochConfig<'a> {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Sets the seed used to shuffle the training sentences between iterations.
+    pub fn seed(mut self, seed: u64) -> EpochConfig<'a> {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets a function to call with the statistics of every epoch.
+    pub fn callback<F: FnMut(&EpochStats) + 'a>(mut self, callback: F) -> EpochConfig<'a> {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn get_iterations(&self) -> usize {
+        self.iterations
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Calls the callback, if there is one.
+    pub fn report(&mut self, stats: &EpochStats) {
+        if let Some(ref mut f) = self.callback {
+            f(stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn train_early_stopping() {
+        let sentence = [
+            ("the".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+            ("barks".to_owned(), "VBZ".to_owned()),
+        ];
+        let train = vec![&sentence[..]; 4];
+        let dev = vec![&sentence[..]];
+
+        let mut epochs = Vec::new();
+        {
+            let mut config = TrainConfig::new()
+                .iterations(20)
+                .seed(3)
+                .dev(&dev)
+                .patience(2)
+                .callback(|stats| epochs.push(*stats));
+
+            PerceptronTagger::new().train_with(train, &mut config);
+        }
+
+        // The dev set is learned perfectly right away, so nothing can improve on the first epoch
+        assert_eq!(3, epochs.len());
+        assert_eq!(vec![1, 2, 3], epochs.iter().map(|s| s.epoch).collect::<Vec<_>>());
+        assert!(epochs.iter().all(|s| s.dev_accuracy == Some(1.0)));
+    }
+
+    #[test]
+    fn train_keep_best() {
+        let sentence = [
+            ("the".to_owned(), "DT".to_owned()),
+            ("dog".to_owned(), "NN".to_owned()),
+            ("barks".to_owned(), "VBZ".to_owned()),
+        ];
+        let dev = vec![&sentence[..]];
+        let train = |iterations, keep_best| {
+            let mut config = TrainConfig::new()
+                .iterations(iterations)
+                .seed(3)
+                .dev(&dev)
+                .keep_best(keep_best);
+            let mut tagger = PerceptronTagger::new();
+            tagger.train_with(vec![&sentence[..]; 4], &mut config);
+            tagger.get_model().clone()
+        };
+
+        // Later epochs only tie with the first one, so the model from the first epoch is kept
+        assert_eq!(train(1, true), train(5, true));
+        assert_ne!(train(5, true), train(5, false));
+    }
+}
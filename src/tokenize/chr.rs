@@ -14,8 +14,8 @@ pub struct CharTokenIter<'a> {
 impl<'a> CharTokenIter<'a> {
     pub fn new(input: &'a str, filter: fn(&(usize, (usize, char))) -> bool) -> Self {
         CharTokenIter {
-            filter: filter,
-            input: input,
+            filter,
+            input,
             byte_offset: 0,
             char_offset: 0,
             index: 0,
@@ -16,7 +16,7 @@ impl<'a> RegexTokenIter<'a> {
         // TODO: Gracefully error
         let r = Regex::new(pattern).unwrap();
         RegexTokenIter {
-            input: input,
+            input,
             regex: r,
             offset: 0,
             index: 0,